@@ -0,0 +1,1070 @@
+//! Chain state: block acceptance, fork choice and reorganization
+//!
+//! Every accepted block is recorded in the block index together with the
+//! cumulative work of its branch. The active chain always follows the
+//! valid branch with the most work; ties keep the branch seen first.
+//!
+//! Headers can be indexed ahead of their blocks ([`Chain::accept_header`]),
+//! which lets a syncing node pick the best header chain before downloading
+//! any block bodies.
+
+use nullchain_storage::{
+    BlockIndexEntry, BlockStatus, BlockStore, Database, StorageError, UtxoSet,
+};
+use nullchain_types::{constants, Block, BlockHeader, Hash256};
+use thiserror::Error;
+
+use crate::clock::{Clock, SystemClock};
+
+use crate::difficulty::{block_work, DifficultyAlgorithm, DifficultyRule};
+use crate::params::ChainParams;
+use crate::validation::{
+    check_block, check_header_context, check_timestamp, validate_block, ValidationError,
+};
+
+/// Consecutive recent blocks included in a locator before it starts skipping
+const LOCATOR_DENSE_BLOCKS: u64 = 10;
+
+#[derive(Error, Debug)]
+pub enum ChainError {
+    #[error("parent block {0} not found")]
+    UnknownParent(Hash256),
+
+    #[error("block {0} is known to be invalid")]
+    KnownInvalid(Hash256),
+
+    #[error("genesis block {found} does not match this network's genesis {expected}")]
+    WrongGenesis { expected: Hash256, found: Hash256 },
+
+    #[error("invalid block: {0}")]
+    Invalid(#[from] ValidationError),
+
+    #[error("storage error: {0}")]
+    Storage(#[from] StorageError),
+}
+
+/// What happened to an accepted block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AcceptOutcome {
+    /// Block was already in the index
+    AlreadyKnown,
+    /// Block was stored on a branch with no more work than the active chain
+    SideBranch,
+    /// Active chain switched to a new tip
+    NewTip {
+        height: u64,
+        /// Blocks rolled back from the old chain, tip first
+        disconnected: Vec<Hash256>,
+        /// Blocks connected from the fork point up to the new tip
+        connected: Vec<Hash256>,
+    },
+}
+
+pub struct Chain<'a> {
+    store: BlockStore<'a>,
+    utxos: UtxoSet<'a>,
+    difficulty: Box<dyn DifficultyAlgorithm>,
+    initial_bits: u32,
+    /// Required first block; any block may start the chain if `None`
+    genesis: Option<Block>,
+    clock: Box<dyn Clock>,
+    max_future_drift: u64,
+}
+
+impl<'a> Chain<'a> {
+    /// Chain using the default interval retarget
+    pub fn new(db: &'a Database) -> Self {
+        Self::with_difficulty(db, DifficultyRule::default())
+    }
+
+    /// Chain starting at the genesis block of `params` and following its
+    /// difficulty rules
+    pub fn with_params(db: &'a Database, params: &ChainParams) -> Self {
+        let mut chain = Self::with_difficulty(db, params.difficulty);
+        chain.initial_bits = params.initial_bits;
+        chain.genesis = Some(params.genesis.clone());
+        chain
+    }
+
+    /// Chain whose blocks must follow `difficulty`
+    pub fn with_difficulty(
+        db: &'a Database,
+        difficulty: impl DifficultyAlgorithm + 'static,
+    ) -> Self {
+        Self {
+            store: BlockStore::new(db),
+            utxos: UtxoSet::new(db),
+            difficulty: Box::new(difficulty),
+            initial_bits: Block::genesis().header.bits,
+            genesis: None,
+            clock: Box::new(SystemClock),
+            max_future_drift: constants::MAX_FUTURE_BLOCK_TIME,
+        }
+    }
+
+    /// Use `clock` instead of the system clock for the future-timestamp rule
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Seconds a block timestamp may run ahead of the clock
+    pub fn with_max_future_drift(mut self, seconds: u64) -> Self {
+        self.max_future_drift = seconds;
+        self
+    }
+
+    /// Store the required genesis block in an empty database
+    ///
+    /// Fails if the database already holds a chain starting at a different
+    /// genesis block, such as one of another network.
+    pub fn initialize(&self) -> Result<(), ChainError> {
+        let Some(genesis) = &self.genesis else {
+            return Ok(());
+        };
+
+        match self.store.get_hash_by_height(0)? {
+            None => {
+                self.accept_genesis(genesis)?;
+                tracing::info!("Initialized chain with genesis {}", genesis.hash());
+                Ok(())
+            }
+            Some(hash) if hash == genesis.hash() => Ok(()),
+            Some(found) => Err(ChainError::WrongGenesis {
+                expected: genesis.hash(),
+                found,
+            }),
+        }
+    }
+
+    /// Unspent outputs of the active chain
+    pub fn utxos(&self) -> &UtxoSet<'a> {
+        &self.utxos
+    }
+
+    /// Index entry of the active chain tip, `None` for an empty chain
+    pub fn tip(&self) -> Result<Option<BlockIndexEntry>, ChainError> {
+        match self.store.get_best()? {
+            Some(hash) => Ok(Some(self.entry(&hash)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Height the next block on the active chain will have, 0 for an empty chain
+    pub fn next_height(&self) -> Result<u64, ChainError> {
+        Ok(self.tip()?.map_or(0, |tip| tip.height + 1))
+    }
+
+    /// Store and index a block, switching the active chain if it now has the most work
+    ///
+    /// The first block accepted into an empty chain becomes its genesis.
+    pub fn accept_block(&self, block: &Block) -> Result<AcceptOutcome, ChainError> {
+        let hash = block.hash();
+
+        if let Some(entry) = self.store.get_index(&hash)? {
+            match entry.status {
+                BlockStatus::Invalid => return Err(ChainError::KnownInvalid(hash)),
+                BlockStatus::HaveData => return Ok(AcceptOutcome::AlreadyKnown),
+                BlockStatus::HeaderOnly => {}
+            }
+        }
+
+        let tip = match self.tip()? {
+            Some(tip) => tip,
+            None => return self.accept_genesis(block),
+        };
+
+        let prev = block.header.previous_block;
+        let parent = self
+            .store
+            .get_index(&prev)?
+            .ok_or(ChainError::UnknownParent(prev))?;
+
+        let mut entry = BlockIndexEntry {
+            header: block.header.clone(),
+            height: parent.height + 1,
+            chain_work: parent
+                .chain_work
+                .saturating_add(block_work(block.header.bits)),
+            status: BlockStatus::HaveData,
+        };
+
+        match parent.status {
+            BlockStatus::Invalid => {
+                entry.status = BlockStatus::Invalid;
+                self.store.put_index(&entry)?;
+                return Err(ChainError::KnownInvalid(prev));
+            }
+            // Blocks are connected in order, so the parent body must come first
+            BlockStatus::HeaderOnly => return Err(ChainError::UnknownParent(prev)),
+            BlockStatus::HaveData => {}
+        }
+
+        check_block(block)?;
+        self.check_context(&block.header, &parent)?;
+
+        self.store.put_data(block)?;
+        self.store.put_index(&entry)?;
+        self.update_best_header(&entry)?;
+
+        if entry.chain_work <= tip.chain_work {
+            tracing::debug!("Block {} stored on side branch", hash);
+            return Ok(AcceptOutcome::SideBranch);
+        }
+
+        self.activate(&tip, &entry)
+    }
+
+    /// Validate and index a header whose block body has not been seen yet
+    ///
+    /// Checks proof of work and the link to its parent; the transactions are
+    /// only validated once the block itself arrives via [`Chain::accept_block`].
+    /// Returns the index entry, which may already have existed.
+    pub fn accept_header(&self, header: &BlockHeader) -> Result<BlockIndexEntry, ChainError> {
+        let hash = header.hash();
+
+        if let Some(entry) = self.store.get_index(&hash)? {
+            return match entry.status {
+                BlockStatus::Invalid => Err(ChainError::KnownInvalid(hash)),
+                _ => Ok(entry),
+            };
+        }
+
+        if !header.meets_difficulty_target() {
+            return Err(ValidationError::InvalidProofOfWork.into());
+        }
+
+        let prev = header.previous_block;
+        let entry = if prev == Hash256::zero() && self.tip()?.is_none() {
+            self.check_genesis(&hash)?;
+            BlockIndexEntry {
+                header: header.clone(),
+                height: 0,
+                chain_work: block_work(header.bits),
+                status: BlockStatus::HeaderOnly,
+            }
+        } else {
+            let parent = self
+                .store
+                .get_index(&prev)?
+                .ok_or(ChainError::UnknownParent(prev))?;
+            if parent.status == BlockStatus::Invalid {
+                return Err(ChainError::KnownInvalid(prev));
+            }
+            self.check_context(header, &parent)?;
+
+            BlockIndexEntry {
+                header: header.clone(),
+                height: parent.height + 1,
+                chain_work: parent.chain_work.saturating_add(block_work(header.bits)),
+                status: BlockStatus::HeaderOnly,
+            }
+        };
+
+        self.store.put_index(&entry)?;
+        self.update_best_header(&entry)?;
+        Ok(entry)
+    }
+
+    /// Valid header with the most work, which may be ahead of the active tip
+    pub fn best_header(&self) -> Result<Option<BlockIndexEntry>, ChainError> {
+        let tip = self.tip()?;
+        let best = match self.store.get_best_header()? {
+            Some(hash) => self.store.get_index(&hash)?,
+            None => None,
+        };
+
+        Ok(match (best, tip) {
+            (Some(best), Some(tip)) if tip.chain_work >= best.chain_work => Some(tip),
+            (Some(best), _) if best.status != BlockStatus::Invalid => Some(best),
+            (_, tip) => tip,
+        })
+    }
+
+    /// Hashes describing our view of the chain, most recent first
+    ///
+    /// Starts with the best header, then walks the active chain back with
+    /// exponentially growing steps and always ends at genesis, so a peer can
+    /// find the last block we have in common with few hashes.
+    pub fn locator(&self) -> Result<Vec<Hash256>, ChainError> {
+        let mut locator = Vec::new();
+
+        if let Some(best) = self.best_header()? {
+            if best.status == BlockStatus::HeaderOnly {
+                locator.push(best.hash());
+            }
+        }
+
+        let tip = match self.tip()? {
+            Some(tip) => tip,
+            None => return Ok(locator),
+        };
+
+        let mut height = tip.height;
+        let mut step = 1;
+        loop {
+            if let Some(hash) = self.store.get_hash_by_height(height)? {
+                locator.push(hash);
+            }
+            if height == 0 {
+                break;
+            }
+            if locator.len() as u64 >= LOCATOR_DENSE_BLOCKS {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+
+        Ok(locator)
+    }
+
+    /// Active-chain headers following the first locator hash we recognise
+    ///
+    /// Starts at genesis if none of the locator hashes is on our active chain.
+    pub fn headers_after(
+        &self,
+        locator: &[Hash256],
+        limit: usize,
+    ) -> Result<Vec<BlockHeader>, ChainError> {
+        let mut start = 0;
+        for hash in locator {
+            if let Some(entry) = self.store.get_index(hash)? {
+                if self.is_active(&entry)? {
+                    start = entry.height + 1;
+                    break;
+                }
+            }
+        }
+
+        let mut headers = Vec::new();
+        let mut height = start;
+        while headers.len() < limit {
+            match self.store.get_hash_by_height(height)? {
+                Some(hash) => headers.push(self.entry(&hash)?.header),
+                None => break,
+            }
+            height += 1;
+        }
+
+        Ok(headers)
+    }
+
+    /// Blocks on the best header chain whose bodies are still missing, in chain order
+    pub fn missing_blocks(&self) -> Result<Vec<Hash256>, ChainError> {
+        let best = match self.best_header()? {
+            Some(best) => best,
+            None => return Ok(vec![]),
+        };
+
+        let mut missing = Vec::new();
+        let mut entry = best;
+        while !self.is_active(&entry)? {
+            if entry.status == BlockStatus::HeaderOnly {
+                missing.push(entry.hash());
+            }
+            if entry.height == 0 {
+                break;
+            }
+            entry = self.entry(&entry.parent())?;
+        }
+
+        missing.reverse();
+        Ok(missing)
+    }
+
+    /// Stored block body, `None` if we only know its header or nothing at all
+    pub fn get_block(&self, hash: &Hash256) -> Result<Option<Block>, ChainError> {
+        match self.store.get_index(hash)? {
+            Some(entry) if entry.status == BlockStatus::HaveData => {
+                Ok(Some(self.store.get_by_hash(hash)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn accept_genesis(&self, block: &Block) -> Result<AcceptOutcome, ChainError> {
+        let prev = block.header.previous_block;
+        if prev != Hash256::zero() {
+            return Err(ChainError::UnknownParent(prev));
+        }
+        self.check_genesis(&block.hash())?;
+
+        check_block(block)?;
+
+        let entry = BlockIndexEntry {
+            header: block.header.clone(),
+            height: 0,
+            chain_work: block_work(block.header.bits),
+            status: BlockStatus::HaveData,
+        };
+        self.store.put_index(&entry)?;
+        self.utxos.apply_block(0, block)?;
+        self.update_best_header(&entry)?;
+
+        Ok(AcceptOutcome::NewTip {
+            height: 0,
+            disconnected: vec![],
+            connected: vec![entry.hash()],
+        })
+    }
+
+    /// Make `target` the active tip, disconnecting and reconnecting as needed
+    ///
+    /// If a block on the new branch fails validation it and its descendants
+    /// on that branch are marked invalid and the previous chain is restored.
+    fn activate(
+        &self,
+        tip: &BlockIndexEntry,
+        target: &BlockIndexEntry,
+    ) -> Result<AcceptOutcome, ChainError> {
+        let (to_disconnect, to_connect) = self.find_fork(tip, target)?;
+
+        if !to_disconnect.is_empty() {
+            tracing::info!(
+                "Reorganizing: disconnecting {} blocks, connecting {}",
+                to_disconnect.len(),
+                to_connect.len()
+            );
+        }
+
+        for entry in &to_disconnect {
+            let block = self.store.get_by_hash(&entry.hash())?;
+            self.utxos.disconnect_block(&block)?;
+        }
+
+        for (i, entry) in to_connect.iter().enumerate() {
+            let block = self.store.get_by_hash(&entry.hash())?;
+            let parent = self.entry(&entry.parent())?;
+
+            match validate_block(&block, &parent.header, entry.height, &self.utxos) {
+                Ok(()) => self.utxos.apply_block(entry.height, &block)?,
+                Err(ValidationError::Storage(e)) => return Err(e.into()),
+                Err(e) => {
+                    tracing::warn!("Block {} failed validation: {}", entry.hash(), e);
+
+                    for invalid in &to_connect[i..] {
+                        let mut invalid = invalid.clone();
+                        invalid.status = BlockStatus::Invalid;
+                        self.store.put_index(&invalid)?;
+                    }
+                    self.forget_invalid_best_header(&to_connect[i], tip)?;
+
+                    for connected in to_connect[..i].iter().rev() {
+                        let block = self.store.get_by_hash(&connected.hash())?;
+                        self.utxos.disconnect_block(&block)?;
+                    }
+                    for old in to_disconnect.iter().rev() {
+                        let block = self.store.get_by_hash(&old.hash())?;
+                        self.utxos.apply_block(old.height, &block)?;
+                    }
+
+                    return Err(e.into());
+                }
+            }
+        }
+
+        Ok(AcceptOutcome::NewTip {
+            height: target.height,
+            disconnected: to_disconnect.iter().map(|e| e.hash()).collect(),
+            connected: to_connect.iter().map(|e| e.hash()).collect(),
+        })
+    }
+
+    /// Blocks to disconnect (tip first) and connect (fork point first) to move
+    /// the active chain from `tip` to `target`
+    fn find_fork(
+        &self,
+        tip: &BlockIndexEntry,
+        target: &BlockIndexEntry,
+    ) -> Result<(Vec<BlockIndexEntry>, Vec<BlockIndexEntry>), ChainError> {
+        let mut old = tip.clone();
+        let mut new = target.clone();
+        let mut to_disconnect = Vec::new();
+        let mut to_connect = Vec::new();
+
+        while old.height > new.height {
+            let parent = self.entry(&old.parent())?;
+            to_disconnect.push(old);
+            old = parent;
+        }
+        while new.height > old.height {
+            let parent = self.entry(&new.parent())?;
+            to_connect.push(new);
+            new = parent;
+        }
+        while old.hash() != new.hash() {
+            let old_parent = self.entry(&old.parent())?;
+            let new_parent = self.entry(&new.parent())?;
+            to_disconnect.push(old);
+            to_connect.push(new);
+            old = old_parent;
+            new = new_parent;
+        }
+
+        to_connect.reverse();
+        Ok((to_disconnect, to_connect))
+    }
+
+    /// Compact difficulty required of a child of `parent`
+    pub fn next_required_bits(&self, parent: &BlockIndexEntry) -> Result<u32, ChainError> {
+        Ok(self.difficulty.next_required_bits(&self.store, parent)?)
+    }
+
+    /// Compact difficulty of a genesis block for this chain
+    pub fn initial_bits(&self) -> u32 {
+        self.initial_bits
+    }
+
+    /// Median timestamp of `entry` and the blocks before it, up to
+    /// `MEDIAN_TIME_SPAN` blocks in total
+    pub fn median_time_past(&self, entry: &BlockIndexEntry) -> Result<u64, ChainError> {
+        let mut timestamps = Vec::with_capacity(constants::MEDIAN_TIME_SPAN);
+        let mut current = entry.clone();
+        loop {
+            timestamps.push(current.header.timestamp);
+            if timestamps.len() == constants::MEDIAN_TIME_SPAN || current.height == 0 {
+                break;
+            }
+            current = self.entry(&current.parent())?;
+        }
+
+        timestamps.sort_unstable();
+        Ok(timestamps[timestamps.len() / 2])
+    }
+
+    /// Reject a genesis other than the network's
+    fn check_genesis(&self, hash: &Hash256) -> Result<(), ChainError> {
+        match &self.genesis {
+            Some(genesis) if genesis.hash() != *hash => Err(ChainError::WrongGenesis {
+                expected: genesis.hash(),
+                found: *hash,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Header checks that need the parent's index entry
+    fn check_context(
+        &self,
+        header: &BlockHeader,
+        parent: &BlockIndexEntry,
+    ) -> Result<(), ChainError> {
+        check_header_context(header, &parent.header)?;
+        check_timestamp(
+            header,
+            self.median_time_past(parent)?,
+            self.clock.now(),
+            self.max_future_drift,
+        )?;
+
+        let expected = self.next_required_bits(parent)?;
+        if header.bits != expected {
+            return Err(ValidationError::BadDifficulty {
+                expected,
+                found: header.bits,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    fn update_best_header(&self, entry: &BlockIndexEntry) -> Result<(), ChainError> {
+        let more_work = match self.best_header()? {
+            Some(best) => entry.chain_work > best.chain_work,
+            None => true,
+        };
+        if more_work {
+            self.store.set_best_header(&entry.hash())?;
+        }
+        Ok(())
+    }
+
+    /// Fall back to `tip` if the best header descends from `invalid`
+    fn forget_invalid_best_header(
+        &self,
+        invalid: &BlockIndexEntry,
+        tip: &BlockIndexEntry,
+    ) -> Result<(), ChainError> {
+        let mut entry = match self.store.get_best_header()? {
+            Some(hash) => self.entry(&hash)?,
+            None => return Ok(()),
+        };
+        while entry.height > invalid.height {
+            entry = self.entry(&entry.parent())?;
+        }
+        if entry.hash() == invalid.hash() {
+            self.store.set_best_header(&tip.hash())?;
+        }
+        Ok(())
+    }
+
+    fn is_active(&self, entry: &BlockIndexEntry) -> Result<bool, ChainError> {
+        Ok(self.store.get_hash_by_height(entry.height)? == Some(entry.hash()))
+    }
+
+    fn entry(&self, hash: &Hash256) -> Result<BlockIndexEntry, ChainError> {
+        self.store
+            .get_index(hash)?
+            .ok_or_else(|| StorageError::BlockNotFound(hash.to_string()).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::difficulty::IntervalRetarget;
+    use crate::pow::mine_block;
+    use nullchain_test_utils::{child_block, first_output, EASY_BITS};
+    use nullchain_types::{OutPoint, Transaction, TransactionInput, TransactionOutput, U256};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn genesis() -> Block {
+        let mut block = Block::genesis();
+        block.header.bits = EASY_BITS;
+        block.header.merkle_root = block.calculate_merkle_root();
+        mine_block(block, Some(1_000_000)).unwrap().0
+    }
+
+    /// Mine a child of `parent` at `height`; `tag` makes sibling blocks distinct
+    fn child(parent: &Block, height: u64, tag: u8, bits: u32, extra: Vec<Transaction>) -> Block {
+        let timestamp = parent.header.timestamp + 600;
+        child_at(parent, height, tag, bits, timestamp, extra)
+    }
+
+    fn child_at(
+        parent: &Block,
+        height: u64,
+        tag: u8,
+        bits: u32,
+        timestamp: u64,
+        extra: Vec<Transaction>,
+    ) -> Block {
+        let mut transactions = vec![Transaction::coinbase(vec![tag; 20], 50, height)];
+        transactions.extend(extra);
+
+        let mut block = child_block(parent, transactions);
+        block.header.timestamp = timestamp;
+        block.header.bits = bits;
+        mine_block(block, Some(10_000_000)).unwrap().0
+    }
+
+    fn coinbase_outpoint(block: &Block) -> OutPoint {
+        first_output(&block.transactions[0])
+    }
+
+    #[test]
+    fn test_extend_chain() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let chain = Chain::new(&db);
+
+        let g = genesis();
+        let a1 = child(&g, 1, 1, EASY_BITS, vec![]);
+
+        assert!(matches!(
+            chain.accept_block(&g).unwrap(),
+            AcceptOutcome::NewTip { height: 0, .. }
+        ));
+        assert!(matches!(
+            chain.accept_block(&a1).unwrap(),
+            AcceptOutcome::NewTip { height: 1, .. }
+        ));
+        assert_eq!(
+            chain.accept_block(&a1).unwrap(),
+            AcceptOutcome::AlreadyKnown
+        );
+
+        let tip = chain.tip().unwrap().unwrap();
+        assert_eq!(tip.hash(), a1.hash());
+        assert_eq!(tip.chain_work, U256::from_u64(2));
+    }
+
+    #[test]
+    fn test_unknown_parent() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let chain = Chain::new(&db);
+
+        let g = genesis();
+        let a1 = child(&g, 1, 1, EASY_BITS, vec![]);
+        let a2 = child(&a1, 2, 1, EASY_BITS, vec![]);
+
+        chain.accept_block(&g).unwrap();
+        assert!(matches!(
+            chain.accept_block(&a2),
+            Err(ChainError::UnknownParent(hash)) if hash == a1.hash()
+        ));
+    }
+
+    #[test]
+    fn test_initialize_with_network_genesis() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let params = ChainParams::regtest();
+        let chain = Chain::with_params(&db, &params);
+        let expected = params.genesis.hash();
+
+        // Only the network's own genesis may start the chain
+        let other = genesis();
+        assert!(matches!(
+            chain.accept_block(&other),
+            Err(ChainError::WrongGenesis { found, .. }) if found == other.hash()
+        ));
+        assert!(matches!(
+            chain.accept_header(&other.header),
+            Err(ChainError::WrongGenesis { .. })
+        ));
+
+        chain.initialize().unwrap();
+        chain.initialize().unwrap();
+        let tip = chain.tip().unwrap().unwrap();
+        assert_eq!((tip.height, tip.hash()), (0, expected));
+
+        let a1 = child(&params.genesis, 1, 1, params.initial_bits, vec![]);
+        assert!(matches!(
+            chain.accept_block(&a1).unwrap(),
+            AcceptOutcome::NewTip { height: 1, .. }
+        ));
+
+        // A database of another network is refused
+        let testnet = Chain::with_params(&db, &ChainParams::testnet());
+        assert!(matches!(
+            testnet.initialize(),
+            Err(ChainError::WrongGenesis { found, .. }) if found == expected
+        ));
+    }
+
+    #[test]
+    fn test_reorg_to_longer_branch() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let chain = Chain::new(&db);
+        let store = BlockStore::new(&db);
+        let utxos = UtxoSet::new(&db);
+
+        let g = genesis();
+        let a1 = child(&g, 1, 1, EASY_BITS, vec![]);
+        let a2 = child(&a1, 2, 1, EASY_BITS, vec![]);
+        let b1 = child(&g, 1, 2, EASY_BITS, vec![]);
+        let b2 = child(&b1, 2, 2, EASY_BITS, vec![]);
+        let b3 = child(&b2, 3, 2, EASY_BITS, vec![]);
+
+        for block in [&g, &a1, &a2] {
+            chain.accept_block(block).unwrap();
+        }
+        assert_eq!(chain.accept_block(&b1).unwrap(), AcceptOutcome::SideBranch);
+        assert_eq!(chain.accept_block(&b2).unwrap(), AcceptOutcome::SideBranch);
+        assert_eq!(store.get_best().unwrap(), Some(a2.hash()));
+
+        assert_eq!(
+            chain.accept_block(&b3).unwrap(),
+            AcceptOutcome::NewTip {
+                height: 3,
+                disconnected: vec![a2.hash(), a1.hash()],
+                connected: vec![b1.hash(), b2.hash(), b3.hash()],
+            }
+        );
+
+        assert_eq!(store.get_best().unwrap(), Some(b3.hash()));
+        assert_eq!(store.get_height().unwrap(), 3);
+        assert_eq!(store.get_by_height(1).unwrap().hash(), b1.hash());
+        assert!(!utxos.exists(&coinbase_outpoint(&a1)).unwrap());
+        assert!(utxos.exists(&coinbase_outpoint(&b1)).unwrap());
+    }
+
+    #[test]
+    fn test_more_work_beats_more_blocks() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let retarget = IntervalRetarget {
+            interval: 16,
+            ..IntervalRetarget::default()
+        };
+        let chain = Chain::with_difficulty(&db, retarget);
+        let store = BlockStore::new(&db);
+        let interval = retarget.interval;
+
+        // Fast blocks up to just before the first retarget
+        let mut prefix = genesis();
+        chain.accept_block(&prefix).unwrap();
+        for height in 1..interval - 1 {
+            let timestamp = prefix.header.timestamp + 1;
+            prefix = child_at(&prefix, height, 0, EASY_BITS, timestamp, vec![]);
+            chain.accept_block(&prefix).unwrap();
+        }
+
+        // Branch A stalls, so the retarget keeps the easiest difficulty
+        let slow = prefix.header.timestamp + interval * retarget.target_spacing;
+        let a1 = child_at(&prefix, interval - 1, 1, EASY_BITS, slow, vec![]);
+        chain.accept_block(&a1).unwrap();
+        let a_bits = chain
+            .next_required_bits(&chain.entry(&a1.hash()).unwrap())
+            .unwrap();
+        let a2 = child(&a1, interval, 1, a_bits, vec![]);
+        let a3 = child(&a2, interval + 1, 1, a_bits, vec![]);
+
+        // Branch B keeps its pace and gets a harder target: fewer blocks, more work
+        let b1 = child_at(
+            &prefix,
+            interval - 1,
+            2,
+            EASY_BITS,
+            prefix.header.timestamp + 1,
+            vec![],
+        );
+        chain.accept_block(&b1).unwrap();
+        let b_bits = chain
+            .next_required_bits(&chain.entry(&b1.hash()).unwrap())
+            .unwrap();
+        assert!(block_work(b_bits) > block_work(a_bits).saturating_add(block_work(a_bits)));
+        let b2 = child(&b1, interval, 2, b_bits, vec![]);
+
+        for block in [&a2, &a3, &b2] {
+            chain.accept_block(block).unwrap();
+        }
+
+        assert_eq!(store.get_best().unwrap(), Some(b2.hash()));
+        assert_eq!(store.get_height().unwrap(), interval);
+    }
+
+    #[test]
+    fn test_reject_wrong_bits() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let chain = Chain::new(&db);
+
+        let g = genesis();
+        let a1 = child(&g, 1, 1, 0x1f0fffff, vec![]);
+        chain.accept_block(&g).unwrap();
+
+        assert!(matches!(
+            chain.accept_block(&a1),
+            Err(ChainError::Invalid(ValidationError::BadDifficulty { expected, found }))
+                if expected == EASY_BITS && found == 0x1f0fffff
+        ));
+        assert!(matches!(
+            chain.accept_header(&a1.header),
+            Err(ChainError::Invalid(ValidationError::BadDifficulty { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_median_time_past() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let chain = Chain::new(&db);
+
+        let g = genesis();
+        let start = g.header.timestamp;
+        chain.accept_block(&g).unwrap();
+
+        let mut tip = g;
+        for height in 1..=10 {
+            tip = child(&tip, height, 0, EASY_BITS, vec![]);
+            chain.accept_block(&tip).unwrap();
+        }
+        let entry = chain.entry(&tip.hash()).unwrap();
+        assert_eq!(chain.median_time_past(&entry).unwrap(), start + 3000);
+
+        let early = child_at(&tip, 11, 1, EASY_BITS, start + 3000, vec![]);
+        assert!(matches!(
+            chain.accept_block(&early),
+            Err(ChainError::Invalid(ValidationError::TimestampTooEarly { median, .. }))
+                if median == start + 3000
+        ));
+
+        // Earlier than the parent is fine as long as it beats the median
+        let late = child_at(&tip, 11, 1, EASY_BITS, start + 3001, vec![]);
+        assert!(matches!(
+            chain.accept_block(&late).unwrap(),
+            AcceptOutcome::NewTip { height: 11, .. }
+        ));
+    }
+
+    #[test]
+    fn test_reject_future_block() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+
+        let g = genesis();
+        let now = Arc::new(AtomicU64::new(g.header.timestamp + 1000));
+        let clock = {
+            let now = now.clone();
+            move || now.load(Ordering::SeqCst)
+        };
+        let chain = Chain::new(&db).with_clock(clock).with_max_future_drift(600);
+        chain.accept_block(&g).unwrap();
+
+        let a1 = child_at(&g, 1, 1, EASY_BITS, g.header.timestamp + 1601, vec![]);
+        assert!(matches!(
+            chain.accept_header(&a1.header),
+            Err(ChainError::Invalid(ValidationError::TimestampTooFar { .. }))
+        ));
+        assert!(matches!(
+            chain.accept_block(&a1),
+            Err(ChainError::Invalid(ValidationError::TimestampTooFar { .. }))
+        ));
+
+        // Not marked invalid: the block is accepted once the clock catches up
+        now.fetch_add(1, Ordering::SeqCst);
+        assert!(matches!(
+            chain.accept_block(&a1).unwrap(),
+            AcceptOutcome::NewTip { height: 1, .. }
+        ));
+    }
+
+    #[test]
+    fn test_invalid_branch_restores_chain() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let chain = Chain::new(&db);
+        let store = BlockStore::new(&db);
+        let utxos = UtxoSet::new(&db);
+
+        let g = genesis();
+        let a1 = child(&g, 1, 1, EASY_BITS, vec![]);
+        let b1 = child(&g, 1, 2, EASY_BITS, vec![]);
+
+        // Spends an output that does not exist: only detectable when connecting
+        let bad_spend = Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output: Hash256::from_bytes([9u8; 32]),
+                output_index: 0,
+                signature: vec![],
+                public_key: vec![],
+            }],
+            outputs: vec![TransactionOutput {
+                amount: 1,
+                recipient: vec![3u8; 20],
+            }],
+            locktime: 0,
+        };
+        let b2 = child(&b1, 2, 2, EASY_BITS, vec![bad_spend]);
+        let b3 = child(&b2, 3, 2, EASY_BITS, vec![]);
+
+        for block in [&g, &a1, &b1] {
+            chain.accept_block(block).unwrap();
+        }
+        assert!(matches!(
+            chain.accept_block(&b2),
+            Err(ChainError::Invalid(ValidationError::MissingInput { .. }))
+        ));
+
+        assert_eq!(store.get_best().unwrap(), Some(a1.hash()));
+        assert!(utxos.exists(&coinbase_outpoint(&a1)).unwrap());
+        assert!(!utxos.exists(&coinbase_outpoint(&b1)).unwrap());
+        assert_eq!(
+            store.get_index(&b2.hash()).unwrap().unwrap().status,
+            BlockStatus::Invalid
+        );
+
+        assert!(matches!(
+            chain.accept_block(&b3),
+            Err(ChainError::KnownInvalid(hash)) if hash == b2.hash()
+        ));
+        assert!(matches!(
+            chain.accept_block(&b2),
+            Err(ChainError::KnownInvalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_headers_first() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let chain = Chain::new(&db);
+
+        let g = genesis();
+        let a1 = child(&g, 1, 1, EASY_BITS, vec![]);
+        let a2 = child(&a1, 2, 1, EASY_BITS, vec![]);
+
+        chain.accept_block(&g).unwrap();
+        for block in [&a1, &a2] {
+            let entry = chain.accept_header(&block.header).unwrap();
+            assert_eq!(entry.status, BlockStatus::HeaderOnly);
+        }
+
+        assert_eq!(chain.tip().unwrap().unwrap().hash(), g.hash());
+        assert_eq!(chain.best_header().unwrap().unwrap().hash(), a2.hash());
+        assert_eq!(chain.missing_blocks().unwrap(), vec![a1.hash(), a2.hash()]);
+        assert!(chain.get_block(&a1.hash()).unwrap().is_none());
+
+        // Bodies must arrive in order
+        assert!(matches!(
+            chain.accept_block(&a2),
+            Err(ChainError::UnknownParent(hash)) if hash == a1.hash()
+        ));
+        assert!(matches!(
+            chain.accept_block(&a1).unwrap(),
+            AcceptOutcome::NewTip { height: 1, .. }
+        ));
+        assert!(matches!(
+            chain.accept_block(&a2).unwrap(),
+            AcceptOutcome::NewTip { height: 2, .. }
+        ));
+        assert!(chain.missing_blocks().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_reject_bad_header() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let chain = Chain::new(&db);
+
+        let g = genesis();
+        let a1 = child(&g, 1, 1, EASY_BITS, vec![]);
+        chain.accept_block(&g).unwrap();
+
+        let mut bad_pow = a1.header.clone();
+        bad_pow.bits = 0x1d00ffff;
+        assert!(matches!(
+            chain.accept_header(&bad_pow),
+            Err(ChainError::Invalid(ValidationError::InvalidProofOfWork))
+        ));
+
+        let a2 = child(&a1, 2, 1, EASY_BITS, vec![]);
+        assert!(matches!(
+            chain.accept_header(&a2.header),
+            Err(ChainError::UnknownParent(hash)) if hash == a1.hash()
+        ));
+    }
+
+    #[test]
+    fn test_locator_and_headers_after() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let chain = Chain::new(&db);
+
+        let mut blocks = vec![genesis()];
+        for height in 1..=20 {
+            let block = child(blocks.last().unwrap(), height, 1, EASY_BITS, vec![]);
+            blocks.push(block);
+        }
+        for block in &blocks {
+            chain.accept_block(block).unwrap();
+        }
+
+        let locator = chain.locator().unwrap();
+        assert_eq!(locator[0], blocks[20].hash());
+        assert_eq!(*locator.last().unwrap(), blocks[0].hash());
+        assert!(locator.len() < blocks.len());
+
+        let headers = chain.headers_after(&[blocks[5].hash()], 3).unwrap();
+        assert_eq!(
+            headers.iter().map(|h| h.hash()).collect::<Vec<_>>(),
+            vec![blocks[6].hash(), blocks[7].hash(), blocks[8].hash()]
+        );
+
+        // Unknown locator starts from genesis
+        let headers = chain.headers_after(&[Hash256::zero()], 2).unwrap();
+        assert_eq!(headers[0].hash(), blocks[0].hash());
+
+        assert!(chain
+            .headers_after(&[blocks[20].hash()], 10)
+            .unwrap()
+            .is_empty());
+    }
+}
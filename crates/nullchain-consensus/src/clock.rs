@@ -0,0 +1,29 @@
+//! Time source for timestamp rules
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of the current Unix time in seconds
+///
+/// Any `Fn() -> u64` is a clock, which lets tests pin the time.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> u64;
+}
+
+/// The system wall clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+impl<F: Fn() -> u64 + Send + Sync> Clock for F {
+    fn now(&self) -> u64 {
+        self()
+    }
+}
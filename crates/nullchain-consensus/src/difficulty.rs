@@ -1,26 +1,245 @@
-//! Difficulty adjustment algorithm
+//! Difficulty adjustment algorithms
+//!
+//! [`DifficultyAlgorithm`] decides the compact bits a child block must
+//! carry. [`IntervalRetarget`] is the Bitcoin-style retarget every
+//! `DIFFICULTY_ADJUSTMENT_INTERVAL` blocks; [`Lwma`] retargets every block
+//! from a linearly weighted moving average, which follows the large hashrate
+//! swings of small test networks much faster. [`DifficultyRule`] selects
+//! one of them, or no retargeting at all, as a network parameter.
 
-use nullchain_types::constants;
+use nullchain_storage::{BlockIndexEntry, BlockStore, StorageError};
+use nullchain_types::{constants, Target, U256};
+
+/// Easiest difficulty a retarget may produce
+pub const MAX_TARGET_BITS: u32 = 0x20ffffff;
 
 /// Adjust difficulty based on actual vs target time
 /// Returns new bits value
+///
+/// The new target is `old_target * actual_time / target_time`, computed
+/// exactly in 256-bit arithmetic and capped at `MAX_TARGET_BITS`.
 pub fn adjust_difficulty(old_bits: u32, actual_time: u64, target_time: u64) -> u32 {
     // Prevent extreme adjustments (max 4x change)
     let actual_clamped = actual_time.clamp(target_time / 4, target_time * 4);
 
-    // Calculate adjustment factor
-    let adjustment = actual_clamped as f64 / target_time as f64;
+    let Some(old_target) = Target::from_compact(old_bits) else {
+        return old_bits;
+    };
+    let max_target = Target::from_compact(MAX_TARGET_BITS).expect("valid maximum target");
+
+    // Blocks too fast shrink the target (harder), too slow grow it (easier)
+    let new_target = old_target
+        .as_u256()
+        .mul_div(actual_clamped, target_time)
+        .map(Target::from_u256)
+        .unwrap_or(max_target);
+
+    new_target.min(max_target).to_compact()
+}
+
+/// Expected number of hashes needed to mine a block at `bits`,
+/// `2^256 / (target + 1)`
+///
+/// Used to compare branches by cumulative work. Undecodable bits count as
+/// no work.
+pub fn block_work(bits: u32) -> U256 {
+    Target::from_compact(bits).map_or(U256::ZERO, |target| target.work())
+}
+
+/// Rule for the compact difficulty of the next block
+pub trait DifficultyAlgorithm: Send + Sync {
+    /// Compact difficulty a child of `parent` must carry
+    fn next_required_bits(
+        &self,
+        store: &BlockStore,
+        parent: &BlockIndexEntry,
+    ) -> Result<u32, StorageError>;
+}
+
+/// Retarget once per fixed interval of blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntervalRetarget {
+    /// Blocks between adjustments
+    pub interval: u64,
+    /// Target seconds per block
+    pub target_spacing: u64,
+}
+
+impl Default for IntervalRetarget {
+    fn default() -> Self {
+        Self {
+            interval: constants::DIFFICULTY_ADJUSTMENT_INTERVAL,
+            target_spacing: constants::BLOCK_TIME_SECONDS,
+        }
+    }
+}
+
+impl DifficultyAlgorithm for IntervalRetarget {
+    /// Unchanged within an adjustment interval. At every `interval`
+    /// boundary the time taken by the previous interval is measured from
+    /// block timestamps along `parent`'s own branch, so side branches
+    /// retarget on their own history.
+    fn next_required_bits(
+        &self,
+        store: &BlockStore,
+        parent: &BlockIndexEntry,
+    ) -> Result<u32, StorageError> {
+        let height = parent.height + 1;
 
-    // Adjust difficulty (inverse relationship)
-    // If blocks too fast (actual < target), increase difficulty (lower bits)
-    // If blocks too slow (actual > target), decrease difficulty (higher bits)
+        if !height.is_multiple_of(self.interval) {
+            return Ok(parent.header.bits);
+        }
 
-    let exponent = (old_bits >> 24) as f64;
-    let mantissa = (old_bits & 0x00ffffff) as f64;
+        let first = ancestor(store, parent, height - self.interval)?;
+        let actual_time = parent
+            .header
+            .timestamp
+            .saturating_sub(first.header.timestamp);
 
-    let new_mantissa = (mantissa * adjustment).min(0x00ffffff as f64) as u32;
+        Ok(adjust_difficulty(
+            parent.header.bits,
+            actual_time,
+            self.interval * self.target_spacing,
+        ))
+    }
+}
 
-    ((exponent as u32) << 24) | (new_mantissa & 0x00ffffff)
+/// Linearly weighted moving average retarget, adjusted every block
+///
+/// The next target is the average target of the last `window` blocks,
+/// scaled by their solve times weighted linearly so the most recent block
+/// counts `window` times as much as the oldest. Each solve time is taken
+/// from the latest timestamp seen so far and clamped to
+/// `[1, 6 * target_spacing]`, so neither a single bad timestamp nor
+/// alternating future and backdated ones can swing the difficulty far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lwma {
+    /// Number of recent blocks averaged
+    pub window: u64,
+    /// Target seconds per block
+    pub target_spacing: u64,
+}
+
+impl Default for Lwma {
+    fn default() -> Self {
+        Self {
+            window: 45,
+            target_spacing: constants::BLOCK_TIME_SECONDS,
+        }
+    }
+}
+
+impl DifficultyAlgorithm for Lwma {
+    /// Carries the parent's bits over until `window` blocks of history exist
+    fn next_required_bits(
+        &self,
+        store: &BlockStore,
+        parent: &BlockIndexEntry,
+    ) -> Result<u32, StorageError> {
+        if self.window == 0 || parent.height < self.window {
+            return Ok(parent.header.bits);
+        }
+
+        // parent and its `window` predecessors, newest first
+        let mut blocks = Vec::with_capacity(self.window as usize + 1);
+        blocks.push(parent.clone());
+        for _ in 0..self.window {
+            let hash = blocks.last().expect("at least the parent").parent();
+            let entry = store
+                .get_index(&hash)?
+                .ok_or_else(|| StorageError::BlockNotFound(hash.to_string()))?;
+            blocks.push(entry);
+        }
+        blocks.reverse();
+
+        let max_solve_time = 6 * self.target_spacing;
+        let mut weighted_time = 0u64;
+        let mut average_target = U256::ZERO;
+        let mut previous_timestamp = blocks[0].header.timestamp;
+        for (weight, block) in (1..).zip(&blocks[1..]) {
+            // Measured against the latest timestamp so far, so a backdated
+            // block cannot undo the solve time granted to a future one
+            let timestamp = block.header.timestamp.max(previous_timestamp + 1);
+            let solve_time = (timestamp - previous_timestamp).min(max_solve_time);
+            previous_timestamp = timestamp;
+            weighted_time += weight * solve_time;
+
+            let Some(target) = Target::from_compact(block.header.bits) else {
+                return Ok(parent.header.bits);
+            };
+            let share = target
+                .as_u256()
+                .mul_div(1, self.window)
+                .expect("division does not grow");
+            average_target = average_target.saturating_add(share);
+        }
+
+        // Weighted time if every block had taken exactly target_spacing
+        let expected_time = self.target_spacing * self.window * (self.window + 1) / 2;
+
+        let max_target = Target::from_compact(MAX_TARGET_BITS).expect("valid maximum target");
+        let next = average_target
+            .mul_div(weighted_time, expected_time)
+            .map(Target::from_u256)
+            .unwrap_or(max_target);
+
+        Ok(next.min(max_target).to_compact())
+    }
+}
+
+/// Difficulty algorithm chosen by the network parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DifficultyRule {
+    Interval(IntervalRetarget),
+    Lwma(Lwma),
+    /// Never retarget: every block carries its parent's bits
+    Fixed,
+}
+
+impl Default for DifficultyRule {
+    fn default() -> Self {
+        DifficultyRule::Interval(IntervalRetarget::default())
+    }
+}
+
+impl DifficultyAlgorithm for DifficultyRule {
+    fn next_required_bits(
+        &self,
+        store: &BlockStore,
+        parent: &BlockIndexEntry,
+    ) -> Result<u32, StorageError> {
+        match self {
+            DifficultyRule::Interval(rule) => rule.next_required_bits(store, parent),
+            DifficultyRule::Lwma(rule) => rule.next_required_bits(store, parent),
+            DifficultyRule::Fixed => Ok(parent.header.bits),
+        }
+    }
+}
+
+/// Ancestor of `entry` at `height`
+fn ancestor(
+    store: &BlockStore,
+    entry: &BlockIndexEntry,
+    height: u64,
+) -> Result<BlockIndexEntry, StorageError> {
+    let index = |hash| {
+        store
+            .get_index(&hash)?
+            .ok_or_else(|| StorageError::BlockNotFound(hash.to_string()))
+    };
+
+    // Main-chain blocks can jump straight there through the height index
+    if store.get_hash_by_height(entry.height)? == Some(entry.hash()) {
+        if let Some(hash) = store.get_hash_by_height(height)? {
+            return index(hash);
+        }
+    }
+
+    let mut current = entry.clone();
+    while current.height > height {
+        current = index(current.parent())?;
+    }
+    Ok(current)
 }
 
 /// Calculate target time for difficulty adjustment period
@@ -31,6 +250,10 @@ pub fn calculate_target_time(num_blocks: u64) -> u64 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use nullchain_storage::{BlockStatus, Database};
+    use nullchain_test_utils::EASY_BITS;
+    use nullchain_types::Block;
+    use tempfile::TempDir;
 
     #[test]
     fn test_difficulty_increase_when_fast() {
@@ -56,6 +279,315 @@ mod tests {
         assert!(new_bits >= old_bits);
     }
 
+    #[test]
+    fn test_adjust_difficulty_exact() {
+        let target_time = calculate_target_time(constants::DIFFICULTY_ADJUSTMENT_INTERVAL);
+        let old = Target::from_compact(0x1d00ffff).unwrap().as_u256();
+
+        // Twice as fast halves the target, twice as slow doubles it
+        let faster = adjust_difficulty(0x1d00ffff, target_time / 2, target_time);
+        assert_eq!(
+            Target::from_compact(faster).unwrap().as_u256(),
+            old.mul_div(1, 2).unwrap()
+        );
+        let slower = adjust_difficulty(0x1d00ffff, target_time * 2, target_time);
+        assert_eq!(
+            Target::from_compact(slower).unwrap().as_u256(),
+            old.mul_div(2, 1).unwrap()
+        );
+
+        // Adjustments are clamped to a factor of four
+        assert_eq!(
+            adjust_difficulty(0x1d00ffff, target_time * 100, target_time),
+            adjust_difficulty(0x1d00ffff, target_time * 4, target_time)
+        );
+
+        // Unchanged timing keeps the target, in normalized encoding
+        let same = adjust_difficulty(0x1d00ffff, target_time, target_time);
+        assert_eq!(same, 0x1cffff00);
+        assert_eq!(Target::from_compact(same).unwrap().as_u256(), old);
+    }
+
+    #[test]
+    fn test_adjust_difficulty_capped() {
+        let target_time = calculate_target_time(constants::DIFFICULTY_ADJUSTMENT_INTERVAL);
+
+        assert_eq!(
+            adjust_difficulty(MAX_TARGET_BITS, target_time * 4, target_time),
+            MAX_TARGET_BITS
+        );
+        assert_eq!(
+            adjust_difficulty(0x203fffff, target_time * 4, target_time),
+            0x20fffffc
+        );
+    }
+
+    #[test]
+    fn test_block_work() {
+        // Harder targets mean more work
+        assert!(block_work(0x1d00ffff) > block_work(0x1f0fffff));
+        assert!(block_work(0x1f0fffff) > block_work(0x20ffffff));
+
+        // Target of 0xffff * 2^208 needs about 2^32 hashes
+        assert_eq!(block_work(0x1d00ffff), U256::from_u64(0x1_0001_0001));
+
+        assert_eq!(block_work(0x20ffffff), U256::ONE);
+        assert_eq!(block_work(0x203fffff), U256::from_u64(4));
+        assert_eq!(block_work(0x1d000000), U256::ZERO);
+        assert_eq!(block_work(0x03000001), U256::ONE << 255);
+        assert_eq!(block_work(0x22ffffff), U256::ZERO);
+    }
+
+    fn index_chain(store: &BlockStore, timestamps: &[u64]) -> Vec<BlockIndexEntry> {
+        let mut entries: Vec<BlockIndexEntry> = Vec::new();
+        for (height, timestamp) in timestamps.iter().enumerate() {
+            let mut header = Block::genesis().header;
+            header.bits = EASY_BITS;
+            header.timestamp = *timestamp;
+            if let Some(parent) = entries.last() {
+                header.previous_block = parent.hash();
+            }
+            let entry = BlockIndexEntry {
+                header,
+                height: height as u64,
+                chain_work: U256::from_u64(height as u64),
+                status: BlockStatus::HaveData,
+            };
+            store.put_index(&entry).unwrap();
+            entries.push(entry);
+        }
+        entries
+    }
+
+    #[test]
+    fn test_next_required_bits() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let store = BlockStore::new(&db);
+        let interval = constants::DIFFICULTY_ADJUSTMENT_INTERVAL as usize;
+
+        // Blocks arriving twice as fast as the target spacing
+        let spacing = constants::BLOCK_TIME_SECONDS / 2;
+        let timestamps: Vec<u64> = (0..interval as u64).map(|h| 1_000 + h * spacing).collect();
+        let entries = index_chain(&store, &timestamps);
+
+        // Inside the interval the parent's bits carry over
+        assert_eq!(
+            IntervalRetarget::default()
+                .next_required_bits(&store, &entries[interval - 2])
+                .unwrap(),
+            EASY_BITS
+        );
+
+        let actual = timestamps[interval - 1] - timestamps[0];
+        let expected = adjust_difficulty(
+            EASY_BITS,
+            actual,
+            calculate_target_time(constants::DIFFICULTY_ADJUSTMENT_INTERVAL),
+        );
+        let bits = IntervalRetarget::default()
+            .next_required_bits(&store, &entries[interval - 1])
+            .unwrap();
+        assert_eq!(bits, expected);
+        assert!(block_work(bits) > block_work(EASY_BITS));
+    }
+
+    #[test]
+    fn test_fixed_rule_never_retargets() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let store = BlockStore::new(&db);
+        let interval = constants::DIFFICULTY_ADJUSTMENT_INTERVAL;
+
+        // Instant blocks right up to an interval boundary
+        let timestamps: Vec<u64> = (0..interval).map(|h| 1_000 + h).collect();
+        let entries = index_chain(&store, &timestamps);
+
+        let parent = entries.last().unwrap();
+        assert_eq!(
+            DifficultyRule::Fixed
+                .next_required_bits(&store, parent)
+                .unwrap(),
+            EASY_BITS
+        );
+        assert_ne!(
+            DifficultyRule::default()
+                .next_required_bits(&store, parent)
+                .unwrap(),
+            EASY_BITS
+        );
+    }
+
+    /// Hashes per second the simulated networks start with
+    const BASE_HASHRATE: u64 = 1_000_000;
+
+    /// Genesis entry whose difficulty matches `hashrate` at the target spacing
+    fn steady_genesis(store: &BlockStore, hashrate: u64) -> BlockIndexEntry {
+        let work = U256::from_u64(hashrate * constants::BLOCK_TIME_SECONDS);
+        let mut header = Block::genesis().header;
+        header.bits = Target::from_u256(U256::MAX.div_rem(work).0).to_compact();
+        header.timestamp = 1_000_000;
+
+        let entry = BlockIndexEntry {
+            chain_work: block_work(header.bits),
+            header,
+            height: 0,
+            status: BlockStatus::HaveData,
+        };
+        store.put_index(&entry).unwrap();
+        entry
+    }
+
+    /// Extend `tip` by one block per hashrate, each solved in exactly the
+    /// expected time at that hashrate; returns the solve times
+    fn simulate(
+        store: &BlockStore,
+        algorithm: &dyn DifficultyAlgorithm,
+        tip: &mut BlockIndexEntry,
+        hashrates: impl IntoIterator<Item = u64>,
+    ) -> Vec<u64> {
+        let mut solve_times = Vec::new();
+        for hashrate in hashrates {
+            let bits = algorithm.next_required_bits(store, tip).unwrap();
+            let solve_time = block_work(bits)
+                .div_rem(U256::from_u64(hashrate))
+                .0
+                .low_u64()
+                .max(1);
+
+            let mut header = tip.header.clone();
+            header.previous_block = tip.hash();
+            header.timestamp += solve_time;
+            header.bits = bits;
+            let entry = BlockIndexEntry {
+                header,
+                height: tip.height + 1,
+                chain_work: tip.chain_work.saturating_add(block_work(bits)),
+                status: BlockStatus::HaveData,
+            };
+            store.put_index(&entry).unwrap();
+
+            *tip = entry;
+            solve_times.push(solve_time);
+        }
+        solve_times
+    }
+
+    fn mean(times: &[u64]) -> u64 {
+        times.iter().sum::<u64>() / times.len() as u64
+    }
+
+    #[test]
+    fn test_lwma_follows_hashrate_spike() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let store = BlockStore::new(&db);
+        let rule = DifficultyRule::Lwma(Lwma::default());
+        let spacing = constants::BLOCK_TIME_SECONDS;
+        let mut tip = steady_genesis(&store, BASE_HASHRATE);
+
+        let steady = simulate(&store, &rule, &mut tip, vec![BASE_HASHRATE; 100]);
+        assert!(steady.iter().all(|t| t.abs_diff(spacing) <= spacing / 20));
+
+        // Ten times the hashrate joins: blocks come fast, then settle
+        let spike = simulate(&store, &rule, &mut tip, vec![10 * BASE_HASHRATE; 300]);
+        assert!(spike[0] < spacing / 5);
+        assert!(mean(&spike[200..]).abs_diff(spacing) < spacing / 10);
+
+        // And leaves again: blocks come slow, then settle
+        let drop = simulate(&store, &rule, &mut tip, vec![BASE_HASHRATE; 300]);
+        assert!(drop[0] > 5 * spacing);
+        assert!(mean(&drop[200..]).abs_diff(spacing) < spacing / 10);
+    }
+
+    #[test]
+    fn test_lwma_resists_alternating_timestamps() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let store = BlockStore::new(&db);
+        let lwma = Lwma::default();
+        let spacing = constants::BLOCK_TIME_SECONDS;
+        let mut tip = steady_genesis(&store, BASE_HASHRATE);
+        let mut now = tip.header.timestamp;
+
+        // Steady hashrate, but every other block claims to be from far in
+        // the future and the ones in between are backdated
+        let mut solve_times = Vec::new();
+        for height in 1..=400 {
+            let bits = lwma.next_required_bits(&store, &tip).unwrap();
+            let solve_time = block_work(bits)
+                .div_rem(U256::from_u64(BASE_HASHRATE))
+                .0
+                .low_u64()
+                .max(1);
+            now += solve_time;
+
+            let mut header = tip.header.clone();
+            header.previous_block = tip.hash();
+            header.timestamp = if height % 2 == 0 {
+                now + constants::MAX_FUTURE_BLOCK_TIME
+            } else {
+                now - 6 * spacing
+            };
+            header.bits = bits;
+            let entry = BlockIndexEntry {
+                header,
+                height,
+                chain_work: tip.chain_work.saturating_add(block_work(bits)),
+                status: BlockStatus::HaveData,
+            };
+            store.put_index(&entry).unwrap();
+
+            tip = entry;
+            solve_times.push(solve_time);
+        }
+
+        assert!(mean(&solve_times[200..]).abs_diff(spacing) < spacing / 10);
+    }
+
+    #[test]
+    fn test_lwma_waits_for_window() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let store = BlockStore::new(&db);
+        let lwma = Lwma::default();
+        let mut tip = steady_genesis(&store, BASE_HASHRATE);
+        let genesis_bits = tip.header.bits;
+
+        simulate(
+            &store,
+            &lwma,
+            &mut tip,
+            vec![10 * BASE_HASHRATE; lwma.window as usize],
+        );
+        assert_eq!(tip.header.bits, genesis_bits);
+        assert_ne!(lwma.next_required_bits(&store, &tip).unwrap(), genesis_bits);
+    }
+
+    #[test]
+    fn test_interval_retarget_lags_hashrate_spike() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let store = BlockStore::new(&db);
+        let rule = DifficultyRule::default();
+        let interval = constants::DIFFICULTY_ADJUSTMENT_INTERVAL as usize;
+        let spacing = constants::BLOCK_TIME_SECONDS;
+        let mut tip = steady_genesis(&store, BASE_HASHRATE);
+
+        let times = simulate(
+            &store,
+            &rule,
+            &mut tip,
+            vec![10 * BASE_HASHRATE; interval + 100],
+        );
+
+        // The rest of the interval runs ten times too fast...
+        assert!(mean(&times[..interval - 1]).abs_diff(spacing / 10) <= 1);
+        // ...and one retarget only catches up by the 4x clamp
+        let after = mean(&times[interval - 1..]);
+        assert!(after > spacing / 3 && after < spacing / 2);
+    }
+
     #[test]
     fn test_calculate_target_time() {
         let target = calculate_target_time(constants::DIFFICULTY_ADJUSTMENT_INTERVAL);
@@ -1,7 +1,24 @@
 //! Consensus rules and proof-of-work for NullChain
 
+pub mod chain;
+pub mod clock;
 pub mod difficulty;
+pub mod mempool;
+pub mod params;
 pub mod pow;
+pub mod subsidy;
+pub mod template;
+pub mod validation;
 
-pub use difficulty::adjust_difficulty;
-pub use pow::mine_block;
+pub use chain::{AcceptOutcome, Chain, ChainError};
+pub use clock::{Clock, SystemClock};
+pub use difficulty::{
+    adjust_difficulty, block_work, DifficultyAlgorithm, DifficultyRule, IntervalRetarget, Lwma,
+    MAX_TARGET_BITS,
+};
+pub use mempool::{FeeRate, Mempool, MempoolEntry, MempoolError};
+pub use params::ChainParams;
+pub use pow::{mine_block, MiningProgress, ParallelMiner};
+pub use subsidy::block_subsidy;
+pub use template::{build_template, BlockTemplate};
+pub use validation::{validate_block, UtxoView, ValidationError};
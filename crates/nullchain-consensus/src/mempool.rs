@@ -0,0 +1,494 @@
+//! Pool of unconfirmed transactions waiting to be mined
+//!
+//! Transactions are admitted after the same input checks a block would run,
+//! against the active chain's UTXO set. Only confirmed outputs can be spent,
+//! coinbase outputs only once they are mature in the next block, and two pool
+//! entries never spend the same output. Entries are ordered by
+//! fee rate (fee per serialized byte); when the pool outgrows its size cap
+//! the lowest fee rates are evicted first.
+
+use nullchain_types::{Block, Hash256, OutPoint, Transaction};
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use thiserror::Error;
+
+use crate::validation::{check_spend, check_transaction, UtxoView, ValidationError};
+
+/// Default cap on the total serialized size of pooled transactions
+pub const DEFAULT_MAX_SIZE: usize = 300_000_000;
+
+#[derive(Error, Debug)]
+pub enum MempoolError {
+    #[error("coinbase transactions are only valid in blocks")]
+    Coinbase,
+
+    #[error("transaction {0} is already in the mempool")]
+    AlreadyKnown(Hash256),
+
+    #[error("output {}:{} is already spent by mempool transaction {by}", outpoint.txid, outpoint.index)]
+    Conflict { outpoint: OutPoint, by: Hash256 },
+
+    #[error("mempool full: fee rate too low to evict other transactions")]
+    PoolFull,
+
+    #[error("invalid transaction: {0}")]
+    Invalid(#[from] ValidationError),
+}
+
+/// Fee per serialized byte, compared exactly as a fraction
+#[derive(Debug, Clone, Copy)]
+pub struct FeeRate {
+    pub fee: u64,
+    pub size: u64,
+}
+
+impl Ord for FeeRate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let lhs = self.fee as u128 * other.size as u128;
+        let rhs = other.fee as u128 * self.size as u128;
+        lhs.cmp(&rhs)
+    }
+}
+
+impl PartialOrd for FeeRate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for FeeRate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for FeeRate {}
+
+/// A pooled transaction with its fee
+#[derive(Debug, Clone)]
+pub struct MempoolEntry {
+    pub tx: Transaction,
+    pub txid: Hash256,
+    pub fee: u64,
+    pub size: usize,
+}
+
+impl MempoolEntry {
+    pub fn fee_rate(&self) -> FeeRate {
+        FeeRate {
+            fee: self.fee,
+            size: self.size as u64,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Mempool {
+    entries: HashMap<Hash256, MempoolEntry>,
+    by_fee_rate: BTreeSet<(FeeRate, Hash256)>,
+    /// Outputs spent by pooled transactions
+    spent: HashMap<OutPoint, Hash256>,
+    total_size: usize,
+    max_size: usize,
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_SIZE)
+    }
+}
+
+impl Mempool {
+    /// Empty pool holding at most `max_size` bytes of serialized transactions
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            by_fee_rate: BTreeSet::new(),
+            spent: HashMap::new(),
+            total_size: 0,
+            max_size,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total serialized size of pooled transactions
+    pub fn total_size(&self) -> usize {
+        self.total_size
+    }
+
+    pub fn contains(&self, txid: &Hash256) -> bool {
+        self.entries.contains_key(txid)
+    }
+
+    pub fn get(&self, txid: &Hash256) -> Option<&MempoolEntry> {
+        self.entries.get(txid)
+    }
+
+    /// Entries from highest to lowest fee rate
+    pub fn iter_by_fee_rate(&self) -> impl Iterator<Item = &MempoolEntry> {
+        self.by_fee_rate
+            .iter()
+            .rev()
+            .map(|(_, txid)| &self.entries[txid])
+    }
+
+    /// Validate a transaction against the chain and the pool, then add it
+    ///
+    /// `height` is the height of the next block, the earliest the transaction
+    /// could be mined at. Lower fee-rate entries are evicted if the pool would
+    /// exceed its cap.
+    pub fn insert(
+        &mut self,
+        tx: Transaction,
+        utxos: &impl UtxoView,
+        height: u64,
+    ) -> Result<Hash256, MempoolError> {
+        if tx.is_coinbase() {
+            return Err(MempoolError::Coinbase);
+        }
+
+        let txid = tx.txid();
+        if self.entries.contains_key(&txid) {
+            return Err(MempoolError::AlreadyKnown(txid));
+        }
+
+        check_transaction(&tx, 0)?;
+
+        let mut inputs = HashSet::new();
+        for input in &tx.inputs {
+            let outpoint = input.outpoint();
+            if !inputs.insert(outpoint) {
+                return Err(ValidationError::DoubleSpend { outpoint }.into());
+            }
+            if let Some(by) = self.spent.get(&outpoint) {
+                return Err(MempoolError::Conflict { outpoint, by: *by });
+            }
+        }
+
+        let fee = check_spend(&tx, 0, height, utxos)?;
+        let entry = MempoolEntry {
+            size: tx.size(),
+            tx,
+            txid,
+            fee,
+        };
+
+        let evict = self.eviction_for(&entry)?;
+        for txid in evict {
+            tracing::debug!("Evicting {} from full mempool", txid);
+            self.remove(&txid);
+        }
+
+        self.add(entry);
+        Ok(txid)
+    }
+
+    /// Remove a transaction, returning it if it was pooled
+    pub fn remove(&mut self, txid: &Hash256) -> Option<MempoolEntry> {
+        let entry = self.entries.remove(txid)?;
+
+        self.by_fee_rate.remove(&(entry.fee_rate(), entry.txid));
+        for input in &entry.tx.inputs {
+            self.spent.remove(&input.outpoint());
+        }
+        self.total_size -= entry.size;
+
+        Some(entry)
+    }
+
+    /// Drop transactions confirmed by a newly connected block, along with
+    /// any pool entries that conflict with it
+    pub fn block_connected(&mut self, block: &Block) {
+        for tx in &block.transactions {
+            self.remove(&tx.txid());
+
+            if tx.is_coinbase() {
+                continue;
+            }
+            for input in &tx.inputs {
+                if let Some(conflict) = self.spent.get(&input.outpoint()).copied() {
+                    tracing::debug!("Dropping {}: input spent in block", conflict);
+                    self.remove(&conflict);
+                }
+            }
+        }
+    }
+
+    /// Return transactions of a disconnected block to the pool
+    ///
+    /// `utxos` must reflect the chain after the reorganization and `height`
+    /// is the height of its next block; transactions that are no longer valid
+    /// on it are dropped, along with pool entries that became invalid.
+    pub fn block_disconnected(&mut self, block: &Block, utxos: &impl UtxoView, height: u64) {
+        self.remove_invalid(utxos, height);
+        for tx in block.transactions.iter().skip(1) {
+            let txid = tx.txid();
+            if let Err(e) = self.insert(tx.clone(), utxos, height) {
+                tracing::debug!("Not returning {} to the mempool: {}", txid, e);
+            }
+        }
+    }
+
+    /// Drop entries whose inputs no longer check out against `utxos` at
+    /// `height`, such as spends of outputs created by a disconnected block
+    pub fn remove_invalid(&mut self, utxos: &impl UtxoView, height: u64) {
+        let invalid: Vec<Hash256> = self
+            .entries
+            .values()
+            .filter_map(|entry| {
+                let error = check_spend(&entry.tx, 0, height, utxos).err()?;
+                tracing::debug!("Dropping {}: {}", entry.txid, error);
+                Some(entry.txid)
+            })
+            .collect();
+
+        for txid in invalid {
+            self.remove(&txid);
+        }
+    }
+
+    fn add(&mut self, entry: MempoolEntry) {
+        for input in &entry.tx.inputs {
+            self.spent.insert(input.outpoint(), entry.txid);
+        }
+        self.by_fee_rate.insert((entry.fee_rate(), entry.txid));
+        self.total_size += entry.size;
+        self.entries.insert(entry.txid, entry);
+    }
+
+    /// Lowest fee-rate entries to evict so that `entry` fits, all of which
+    /// must pay a lower fee rate than `entry`
+    fn eviction_for(&self, entry: &MempoolEntry) -> Result<Vec<Hash256>, MempoolError> {
+        if entry.size > self.max_size {
+            return Err(MempoolError::PoolFull);
+        }
+
+        let mut evict = Vec::new();
+        let mut size = self.total_size + entry.size;
+        let mut lowest = self.by_fee_rate.iter();
+
+        while size > self.max_size {
+            match lowest.next() {
+                Some((rate, txid)) if *rate < entry.fee_rate() => {
+                    size -= self.entries[txid].size;
+                    evict.push(*txid);
+                }
+                _ => return Err(MempoolError::PoolFull),
+            }
+        }
+
+        Ok(evict)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nullchain_test_utils::{first_output, funded, funded_outpoint, owned_output};
+    use nullchain_types::constants::COINBASE_MATURITY;
+    use nullchain_types::{TransactionOutput, UtxoEntry};
+
+    /// Spend the funded outputs `inputs`, each worth `value`, paying `fee`
+    fn spend(inputs: &[u8], value: u64, fee: u64) -> Transaction {
+        let outpoints: Vec<OutPoint> = inputs.iter().map(|i| funded_outpoint(*i)).collect();
+        let output = TransactionOutput {
+            amount: value * inputs.len() as u64 - fee,
+            recipient: vec![1u8; 20],
+        };
+        nullchain_test_utils::spend(&outpoints, vec![output])
+    }
+
+    fn block_with(transactions: Vec<Transaction>) -> Block {
+        let mut block = Block::genesis();
+        block.transactions.extend(transactions);
+        block
+    }
+
+    #[test]
+    fn test_insert_valid() {
+        let utxos = funded(1, 1000);
+        let mut pool = Mempool::default();
+        let tx = spend(&[0], 1000, 10);
+
+        let txid = pool.insert(tx.clone(), &utxos, 1).unwrap();
+        assert_eq!(txid, tx.txid());
+        assert_eq!(pool.get(&txid).unwrap().fee, 10);
+        assert_eq!(pool.total_size(), tx.size());
+
+        assert!(matches!(
+            pool.insert(tx, &utxos, 1),
+            Err(MempoolError::AlreadyKnown(_))
+        ));
+        assert!(matches!(
+            pool.insert(Transaction::coinbase(vec![0u8; 20], 50, 1), &utxos, 1),
+            Err(MempoolError::Coinbase)
+        ));
+    }
+
+    #[test]
+    fn test_reject_invalid() {
+        let utxos = funded(1, 1000);
+        let mut pool = Mempool::default();
+
+        // Spends an output the chain does not have
+        assert!(matches!(
+            pool.insert(spend(&[5], 1000, 10), &utxos, 1),
+            Err(MempoolError::Invalid(ValidationError::MissingInput { .. }))
+        ));
+
+        let mut forged = spend(&[0], 1000, 10);
+        forged.outputs[0].amount -= 1;
+        assert!(matches!(
+            pool.insert(forged, &utxos, 1),
+            Err(MempoolError::Invalid(ValidationError::InvalidInput { .. }))
+        ));
+
+        assert!(matches!(
+            pool.insert(spend(&[0, 0], 1000, 10), &utxos, 1),
+            Err(MempoolError::Invalid(ValidationError::DoubleSpend { .. }))
+        ));
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn test_reject_immature_coinbase() {
+        let mut utxos = funded(1, 1000);
+        utxos.get_mut(&funded_outpoint(0)).unwrap().coinbase = true;
+        let mut pool = Mempool::default();
+
+        assert!(matches!(
+            pool.insert(spend(&[0], 1000, 10), &utxos, COINBASE_MATURITY - 1),
+            Err(MempoolError::Invalid(
+                ValidationError::ImmatureCoinbase { .. }
+            ))
+        ));
+        pool.insert(spend(&[0], 1000, 10), &utxos, COINBASE_MATURITY)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_reject_pool_double_spend() {
+        let utxos = funded(1, 1000);
+        let mut pool = Mempool::default();
+        let first = spend(&[0], 1000, 10);
+        let second = spend(&[0], 1000, 20);
+
+        pool.insert(first.clone(), &utxos, 1).unwrap();
+        assert!(matches!(
+            pool.insert(second, &utxos, 1),
+            Err(MempoolError::Conflict { by, .. }) if by == first.txid()
+        ));
+    }
+
+    #[test]
+    fn test_fee_rate_order() {
+        let utxos = funded(4, 1000);
+        let mut pool = Mempool::default();
+        let low = spend(&[0], 1000, 10);
+        let high = spend(&[1], 1000, 300);
+        // Higher fee than `low` but spread over more than twice the size
+        let big = spend(&[2, 3], 1000, 15);
+
+        pool.insert(low.clone(), &utxos, 1).unwrap();
+        pool.insert(high.clone(), &utxos, 1).unwrap();
+        pool.insert(big.clone(), &utxos, 1).unwrap();
+
+        let order: Vec<Hash256> = pool.iter_by_fee_rate().map(|e| e.txid).collect();
+        assert_eq!(order, vec![high.txid(), low.txid(), big.txid()]);
+
+        let a = FeeRate { fee: 1, size: 2 };
+        let b = FeeRate { fee: 2, size: 4 };
+        assert_eq!(a, b);
+        assert!(FeeRate { fee: 3, size: 4 } > a);
+    }
+
+    #[test]
+    fn test_eviction() {
+        let utxos = funded(3, 1000);
+        let low = spend(&[0], 1000, 10);
+        let mid = spend(&[1], 1000, 20);
+        let high = spend(&[2], 1000, 30);
+
+        // Room for exactly two transactions of this size
+        let mut pool = Mempool::new(low.size() * 2);
+        pool.insert(mid.clone(), &utxos, 1).unwrap();
+        pool.insert(high.clone(), &utxos, 1).unwrap();
+
+        assert!(matches!(
+            pool.insert(low.clone(), &utxos, 1),
+            Err(MempoolError::PoolFull)
+        ));
+
+        let mut pool = Mempool::new(low.size() * 2);
+        pool.insert(low.clone(), &utxos, 1).unwrap();
+        pool.insert(mid.clone(), &utxos, 1).unwrap();
+        pool.insert(high.clone(), &utxos, 1).unwrap();
+
+        assert!(!pool.contains(&low.txid()));
+        assert!(pool.contains(&mid.txid()));
+        assert!(pool.contains(&high.txid()));
+        assert!(pool.total_size() <= low.size() * 2);
+    }
+
+    #[test]
+    fn test_block_connected() {
+        let utxos = funded(2, 1000);
+        let mut pool = Mempool::default();
+        let confirmed = spend(&[0], 1000, 10);
+        let pending = spend(&[1], 1000, 10);
+        pool.insert(confirmed.clone(), &utxos, 1).unwrap();
+        pool.insert(pending.clone(), &utxos, 1).unwrap();
+
+        // A different spend of `pending`'s input confirms first
+        let conflicting = spend(&[1], 1000, 50);
+        pool.block_connected(&block_with(vec![confirmed.clone(), conflicting]));
+
+        assert!(pool.is_empty());
+        assert_eq!(pool.total_size(), 0);
+    }
+
+    #[test]
+    fn test_block_disconnected() {
+        let utxos = funded(1, 1000);
+        let mut pool = Mempool::default();
+        let tx = spend(&[0], 1000, 10);
+
+        pool.block_disconnected(&block_with(vec![tx.clone()]), &utxos, 1);
+        assert!(pool.contains(&tx.txid()));
+
+        // Spent again on the new chain: not returned
+        let mut pool = Mempool::default();
+        pool.block_disconnected(&block_with(vec![tx.clone()]), &HashMap::new(), 1);
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn test_block_disconnected_drops_stale_spends() {
+        let parent = nullchain_test_utils::spend(&[funded_outpoint(0)], vec![owned_output(990)]);
+        let child = nullchain_test_utils::spend(&[first_output(&parent)], vec![owned_output(980)]);
+
+        // The chain with block D, which confirmed `parent`
+        let mut utxos = HashMap::new();
+        let entry = UtxoEntry {
+            output: owned_output(990),
+            height: 1,
+            coinbase: false,
+        };
+        utxos.insert(first_output(&parent), entry);
+        let mut pool = Mempool::default();
+        pool.insert(child.clone(), &utxos, 2).unwrap();
+
+        // D is disconnected: `child` spends an output that no longer exists
+        pool.block_disconnected(&block_with(vec![parent.clone()]), &funded(1, 1000), 1);
+        assert!(!pool.contains(&child.txid()));
+        assert!(pool.contains(&parent.txid()));
+        assert_eq!(pool.total_size(), parent.size());
+    }
+}
@@ -0,0 +1,194 @@
+//! Network parameters
+//!
+//! Everything that differs between mainnet, testnet and regtest lives in
+//! [`ChainParams`]: the genesis block, how difficulty starts and retargets,
+//! and how nodes find and recognise each other.
+
+use nullchain_types::{Block, Network};
+
+use crate::difficulty::{DifficultyRule, IntervalRetarget, Lwma, MAX_TARGET_BITS};
+
+/// Timestamp of every genesis block (2026-10-18 00:00:00 UTC)
+const GENESIS_TIME: u64 = 1_792_281_600;
+
+// Proof-of-work solutions of the genesis blocks
+const MAINNET_NONCE: u64 = 4_482_422;
+const TESTNET_NONCE: u64 = 3_458;
+const REGTEST_NONCE: u64 = 0;
+
+/// Parameters of one network
+#[derive(Debug, Clone)]
+pub struct ChainParams {
+    pub network: Network,
+
+    /// First block of the chain
+    pub genesis: Block,
+
+    /// Tags the network's wire protocols so nodes of different networks
+    /// never exchange blocks
+    pub magic: [u8; 4],
+
+    /// Difficulty of the genesis block
+    pub initial_bits: u32,
+
+    /// How the difficulty of later blocks is derived
+    pub difficulty: DifficultyRule,
+
+    /// TCP port nodes listen on unless told otherwise
+    pub default_port: u16,
+
+    /// Chain database directory unless told otherwise
+    pub data_dir: &'static str,
+}
+
+impl ChainParams {
+    /// Production network, retargeting every `DIFFICULTY_ADJUSTMENT_INTERVAL` blocks
+    pub fn mainnet() -> Self {
+        Self::new(
+            Network::Mainnet,
+            "NullChain mainnet: privacy is not a crime",
+            MAINNET_NONCE,
+            [0xd9, 0x4e, 0x55, 0x4c],
+            0x1e0fffff,
+            DifficultyRule::Interval(IntervalRetarget::default()),
+            9333,
+            ".nullchain/mainnet",
+        )
+    }
+
+    /// Public test network, retargeting every block to follow hashrate swings
+    pub fn testnet() -> Self {
+        Self::new(
+            Network::Testnet,
+            "NullChain testnet",
+            TESTNET_NONCE,
+            [0x0b, 0x4e, 0x55, 0x4c],
+            0x1f0fffff,
+            DifficultyRule::Lwma(Lwma::default()),
+            19333,
+            ".nullchain/testnet",
+        )
+    }
+
+    /// Local test chains at the easiest difficulty, which never changes
+    pub fn regtest() -> Self {
+        Self::new(
+            Network::Regtest,
+            "NullChain regtest",
+            REGTEST_NONCE,
+            [0xfa, 0x4e, 0x55, 0x4c],
+            MAX_TARGET_BITS,
+            DifficultyRule::Fixed,
+            19444,
+            ".nullchain/regtest",
+        )
+    }
+
+    pub fn for_network(network: Network) -> Self {
+        match network {
+            Network::Mainnet => Self::mainnet(),
+            Network::Testnet => Self::testnet(),
+            Network::Regtest => Self::regtest(),
+        }
+    }
+
+    /// Human-readable prefix of addresses on this network
+    pub fn address_prefix(&self) -> &'static str {
+        self.network.address_prefix()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        network: Network,
+        genesis_message: &str,
+        genesis_nonce: u64,
+        magic: [u8; 4],
+        initial_bits: u32,
+        difficulty: DifficultyRule,
+        default_port: u16,
+        data_dir: &'static str,
+    ) -> Self {
+        let mut genesis =
+            Block::genesis_with_message(genesis_message.as_bytes(), GENESIS_TIME, initial_bits);
+        genesis.header.nonce = genesis_nonce;
+
+        Self {
+            network,
+            genesis,
+            magic,
+            initial_bits,
+            difficulty,
+            default_port,
+            data_dir,
+        }
+    }
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        Self::testnet()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validation::check_block;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_networks_are_distinct() {
+        let params: Vec<ChainParams> = Network::ALL
+            .into_iter()
+            .map(ChainParams::for_network)
+            .collect();
+
+        for (network, params) in Network::ALL.into_iter().zip(&params) {
+            assert_eq!(params.network, network);
+            assert_eq!(params.genesis.header.bits, params.initial_bits);
+        }
+
+        let distinct = |values: Vec<String>| values.into_iter().collect::<HashSet<_>>().len();
+        let field = |f: fn(&ChainParams) -> String| params.iter().map(f).collect::<Vec<_>>();
+        assert_eq!(distinct(field(|p| format!("{:?}", p.magic))), 3);
+        assert_eq!(distinct(field(|p| p.address_prefix().to_string())), 3);
+        assert_eq!(distinct(field(|p| p.default_port.to_string())), 3);
+        assert_eq!(distinct(field(|p| p.data_dir.to_string())), 3);
+        assert_eq!(distinct(field(|p| p.genesis.hash().to_string())), 3);
+    }
+
+    #[test]
+    fn test_genesis_blocks() {
+        for (params, hash) in [
+            (
+                ChainParams::mainnet(),
+                "a870f0a03a6be28cdd44e6a3cc01710beaa25f63f6f2a65c2aa2610f4b090000",
+            ),
+            (
+                ChainParams::testnet(),
+                "8c95786ce04c85b39d7d2d7b6d1f0a9b43ac43cd45a551fa06c1ae9fa1570d00",
+            ),
+            (
+                ChainParams::regtest(),
+                "645e7aafb83947fff3ad514a7c01c6581e068bdae144c33ea8ac26cad6bdd69a",
+            ),
+        ] {
+            let genesis = &params.genesis;
+            assert_eq!(genesis.hash().to_string(), hash, "{}", params.network);
+
+            assert_eq!(genesis.header.merkle_root, genesis.calculate_merkle_root());
+            assert!(genesis.meets_difficulty_target());
+            check_block(genesis).unwrap();
+
+            let message = &genesis.transactions[0].inputs[0].signature;
+            assert!(message.starts_with(b"NullChain"));
+        }
+    }
+
+    #[test]
+    fn test_regtest_is_trivial() {
+        let regtest = ChainParams::regtest();
+        assert_eq!(regtest.initial_bits, MAX_TARGET_BITS);
+        assert_eq!(regtest.difficulty, DifficultyRule::Fixed);
+    }
+}
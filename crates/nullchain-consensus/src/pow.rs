@@ -1,6 +1,13 @@
 //! Proof-of-Work mining
 
-use nullchain_types::{Block, Hash256};
+use nullchain_types::{Block, BlockHeader, Hash256, Target};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Hashes a worker tries between checks for cancellation
+const HASH_BATCH: u64 = 10_000;
 
 /// Mine a block by finding a valid nonce
 /// Returns the nonce and hash when found
@@ -25,6 +32,175 @@ pub fn mine_block(mut block: Block, max_iterations: Option<u64>) -> Option<(Bloc
     None
 }
 
+/// Hashing progress of a [`ParallelMiner`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MiningProgress {
+    /// Hashes tried so far, over all threads
+    pub hashes: u64,
+    pub elapsed: Duration,
+}
+
+impl MiningProgress {
+    /// Hashes per second since mining started
+    pub fn hashrate(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.hashes as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Proof-of-work search split over several threads
+///
+/// Each thread owns a contiguous slice of the nonce space. A thread that
+/// exhausts its slice bumps the timestamp by one second and starts over,
+/// so slices never overlap between threads.
+#[derive(Debug, Clone)]
+pub struct ParallelMiner {
+    threads: u64,
+    max_hashes: Option<u64>,
+    report_interval: Duration,
+    /// Nonces tried per timestamp, `u64::MAX` outside of tests
+    nonce_space: u64,
+}
+
+impl ParallelMiner {
+    /// Miner using `threads` worker threads (at least one)
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads: threads.max(1) as u64,
+            max_hashes: None,
+            report_interval: Duration::from_secs(1),
+            nonce_space: u64::MAX,
+        }
+    }
+
+    /// Give up after roughly this many hashes in total
+    pub fn with_max_hashes(mut self, max_hashes: Option<u64>) -> Self {
+        self.max_hashes = max_hashes;
+        self
+    }
+
+    /// How often the progress callback runs while mining
+    pub fn with_report_interval(mut self, interval: Duration) -> Self {
+        self.report_interval = interval;
+        self
+    }
+
+    /// Search for a header meeting the block's own difficulty target
+    ///
+    /// `on_progress` is called every report interval and once more when the
+    /// search ends. Returns `None` if `cancel` is set, the hash limit is
+    /// reached or the bits do not decode to a target.
+    pub fn mine(
+        &self,
+        mut block: Block,
+        cancel: &AtomicBool,
+        mut on_progress: impl FnMut(MiningProgress),
+    ) -> Option<(Block, Hash256)> {
+        let target = Target::from_compact(block.header.bits)?;
+        let started = Instant::now();
+        let search = Search {
+            target,
+            cancel,
+            stop: AtomicBool::new(false),
+            hashes: AtomicU64::new(0),
+            max_hashes: self.max_hashes.unwrap_or(u64::MAX),
+        };
+        let progress = || MiningProgress {
+            hashes: search.hashes.load(Ordering::Relaxed),
+            elapsed: started.elapsed(),
+        };
+
+        let found = thread::scope(|scope| {
+            let (found_tx, found_rx) = mpsc::channel();
+            for index in 0..self.threads {
+                let found_tx = found_tx.clone();
+                let header = block.header.clone();
+                let nonces = self.nonce_range(index);
+                let search = &search;
+                scope.spawn(move || {
+                    if let Some(header) = search.run(header, nonces) {
+                        let _ = found_tx.send(header);
+                    }
+                });
+            }
+            drop(found_tx);
+
+            let found = loop {
+                match found_rx.recv_timeout(self.report_interval) {
+                    Ok(header) => break Some(header),
+                    Err(RecvTimeoutError::Timeout) => on_progress(progress()),
+                    Err(RecvTimeoutError::Disconnected) => break None,
+                }
+            };
+            search.stop.store(true, Ordering::Relaxed);
+            found
+        });
+
+        on_progress(progress());
+
+        let header = found?;
+        block.header = header;
+        let hash = block.hash();
+        tracing::debug!("Block mined! Nonce: {}, Hash: {}", block.header.nonce, hash);
+        Some((block, hash))
+    }
+
+    /// Half-open slice of the nonce space owned by thread `index`
+    fn nonce_range(&self, index: u64) -> (u64, u64) {
+        let share = |i: u64| (self.nonce_space as u128 * i as u128 / self.threads as u128) as u64;
+        (share(index), share(index + 1))
+    }
+}
+
+/// State shared by the worker threads of one search
+struct Search<'a> {
+    target: Target,
+    cancel: &'a AtomicBool,
+    /// Set once any thread found a solution
+    stop: AtomicBool,
+    hashes: AtomicU64,
+    max_hashes: u64,
+}
+
+impl Search<'_> {
+    fn should_stop(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+            || self.cancel.load(Ordering::Relaxed)
+            || self.hashes.load(Ordering::Relaxed) >= self.max_hashes
+    }
+
+    fn run(&self, mut header: BlockHeader, (start, end): (u64, u64)) -> Option<BlockHeader> {
+        loop {
+            let mut nonce = start;
+            while nonce < end {
+                if self.should_stop() {
+                    return None;
+                }
+
+                let batch_end = end.min(nonce.saturating_add(HASH_BATCH));
+                for candidate in nonce..batch_end {
+                    header.nonce = candidate;
+                    if self.target.is_met_by(&header.hash()) {
+                        self.stop.store(true, Ordering::Relaxed);
+                        self.hashes
+                            .fetch_add(candidate - nonce + 1, Ordering::Relaxed);
+                        return Some(header);
+                    }
+                }
+                self.hashes.fetch_add(batch_end - nonce, Ordering::Relaxed);
+                nonce = batch_end;
+            }
+
+            // Nonce slice exhausted: a new timestamp gives a fresh one
+            header.timestamp += 1;
+        }
+    }
+}
+
 /// Check if a block's hash meets the difficulty target
 pub fn verify_pow(block: &Block) -> bool {
     block.meets_difficulty_target()
@@ -60,4 +236,69 @@ mod tests {
         // Verify function runs without panic
         let _ = verify_pow(&block);
     }
+
+    fn easy_block(bits: u32) -> Block {
+        let mut block = Block::genesis();
+        block.header.bits = bits;
+        block
+    }
+
+    #[test]
+    fn test_parallel_mining() {
+        let cancel = AtomicBool::new(false);
+        let (block, hash) = ParallelMiner::new(4)
+            .mine(easy_block(0x1f0fffff), &cancel, |_| {})
+            .unwrap();
+
+        assert!(verify_pow(&block));
+        assert_eq!(hash, block.hash());
+    }
+
+    #[test]
+    fn test_exhausted_nonces_bump_timestamp() {
+        let mut miner = ParallelMiner::new(2);
+        miner.nonce_space = 4;
+        assert_eq!(miner.nonce_range(0), (0, 2));
+        assert_eq!(miner.nonce_range(1), (2, 4));
+
+        let block = easy_block(0x1f0fffff);
+        let timestamp = block.header.timestamp;
+        let cancel = AtomicBool::new(false);
+        let (mined, _) = miner.mine(block, &cancel, |_| {}).unwrap();
+
+        assert!(verify_pow(&mined));
+        assert!(mined.header.nonce < 4);
+        assert!(mined.header.timestamp > timestamp);
+    }
+
+    #[test]
+    fn test_cancel_mining() {
+        let cancel = AtomicBool::new(false);
+        let miner = ParallelMiner::new(2);
+
+        let result = thread::scope(|scope| {
+            let mining = scope.spawn(|| miner.mine(easy_block(0x03000001), &cancel, |_| {}));
+            thread::sleep(Duration::from_millis(50));
+            cancel.store(true, Ordering::Relaxed);
+            mining.join().unwrap()
+        });
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_progress_reporting() {
+        let cancel = AtomicBool::new(false);
+        let mut reports = Vec::new();
+        let result = ParallelMiner::new(2)
+            .with_max_hashes(Some(50_000))
+            .with_report_interval(Duration::from_millis(1))
+            .mine(easy_block(0x03000001), &cancel, |progress| {
+                reports.push(progress)
+            });
+
+        assert!(result.is_none());
+        let last = reports.last().unwrap();
+        assert!(last.hashes >= 50_000);
+        assert!(reports.windows(2).all(|w| w[0].hashes <= w[1].hashes));
+    }
 }
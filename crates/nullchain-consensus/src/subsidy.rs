@@ -0,0 +1,61 @@
+//! Block subsidy schedule
+
+use nullchain_types::constants::{HALVING_INTERVAL, INITIAL_BLOCK_REWARD};
+
+/// Newly created coins a block at `height` may pay out, before fees
+///
+/// Starts at `INITIAL_BLOCK_REWARD` and halves every `HALVING_INTERVAL`
+/// blocks until it reaches zero.
+pub fn block_subsidy(height: u64) -> u64 {
+    let halvings = height / HALVING_INTERVAL;
+    if halvings >= u64::BITS as u64 {
+        return 0;
+    }
+    INITIAL_BLOCK_REWARD >> halvings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_initial_subsidy() {
+        assert_eq!(block_subsidy(0), INITIAL_BLOCK_REWARD);
+        assert_eq!(block_subsidy(1), INITIAL_BLOCK_REWARD);
+    }
+
+    #[test]
+    fn test_halving_boundaries() {
+        assert_eq!(block_subsidy(HALVING_INTERVAL - 1), INITIAL_BLOCK_REWARD);
+        assert_eq!(block_subsidy(HALVING_INTERVAL), INITIAL_BLOCK_REWARD / 2);
+        assert_eq!(
+            block_subsidy(2 * HALVING_INTERVAL - 1),
+            INITIAL_BLOCK_REWARD / 2
+        );
+        assert_eq!(
+            block_subsidy(2 * HALVING_INTERVAL),
+            INITIAL_BLOCK_REWARD / 4
+        );
+        assert_eq!(
+            block_subsidy(10 * HALVING_INTERVAL),
+            INITIAL_BLOCK_REWARD >> 10
+        );
+    }
+
+    #[test]
+    fn test_zero_subsidy_era() {
+        // 100 * 10^9 < 2^37, so the 37th halving is the first without subsidy
+        assert_eq!(block_subsidy(36 * HALVING_INTERVAL), 1);
+        assert_eq!(block_subsidy(37 * HALVING_INTERVAL), 0);
+        assert_eq!(block_subsidy(64 * HALVING_INTERVAL), 0);
+        assert_eq!(block_subsidy(u64::MAX), 0);
+    }
+
+    #[test]
+    fn test_total_supply() {
+        let total: u64 = (0..64)
+            .map(|era| block_subsidy(era * HALVING_INTERVAL) * HALVING_INTERVAL)
+            .sum();
+        assert!(total < 2 * INITIAL_BLOCK_REWARD * HALVING_INTERVAL);
+    }
+}
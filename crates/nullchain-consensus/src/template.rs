@@ -0,0 +1,327 @@
+//! Block templates: candidate blocks for miners to work on
+
+use nullchain_types::{constants, Block, BlockHeader, Hash256, OutPoint, Transaction, UtxoEntry};
+use std::collections::HashMap;
+
+use crate::chain::{Chain, ChainError};
+use crate::mempool::Mempool;
+use crate::subsidy::block_subsidy;
+use crate::validation::{check_spend, Overlay, ValidationError};
+
+/// Header version of newly built blocks
+const BLOCK_VERSION: u32 = 1;
+
+/// Unmined block on top of the active tip
+#[derive(Debug, Clone)]
+pub struct BlockTemplate {
+    /// Block with a zero nonce; only the nonce (and timestamp) should change
+    pub block: Block,
+    pub height: u64,
+    /// Fees collected from the included transactions
+    pub fees: u64,
+}
+
+/// Build a block on the active tip paying the subsidy and fees to `recipient`
+///
+/// Mempool transactions are taken in fee-rate order for as long as they fit
+/// within `MAX_BLOCK_SIZE`. Each one is checked against the active UTXO set
+/// plus the outputs of transactions already taken, and skipped if it no
+/// longer spends valid inputs at the template's height. The timestamp is `now`, but always after the
+/// tip's median time past. On an empty chain the template is a genesis block.
+pub fn build_template(
+    chain: &Chain,
+    mempool: &Mempool,
+    recipient: &[u8],
+    now: u64,
+) -> Result<BlockTemplate, ChainError> {
+    let (height, previous_block, bits, timestamp) = match chain.tip()? {
+        Some(tip) => (
+            tip.height + 1,
+            tip.hash(),
+            chain.next_required_bits(&tip)?,
+            now.max(chain.median_time_past(&tip)? + 1),
+        ),
+        None => (0, Hash256::zero(), chain.initial_bits(), now),
+    };
+
+    let mut block = Block {
+        header: BlockHeader {
+            version: BLOCK_VERSION,
+            previous_block,
+            merkle_root: Hash256::zero(),
+            timestamp,
+            bits,
+            nonce: 0,
+        },
+        transactions: vec![Transaction::coinbase(recipient.to_vec(), 0, height)],
+    };
+
+    // The coinbase amount is a fixed-width integer, so the size is final
+    let mut size = block.size();
+    let subsidy = block_subsidy(height);
+    let mut fees = 0u64;
+    let mut created: HashMap<OutPoint, UtxoEntry> = HashMap::new();
+    for entry in mempool.iter_by_fee_rate() {
+        if size + entry.size > constants::MAX_BLOCK_SIZE {
+            continue;
+        }
+
+        let view = Overlay {
+            created: &created,
+            base: chain.utxos(),
+        };
+        let index = block.transactions.len();
+        let fee = match check_spend(&entry.tx, index, height, &view) {
+            Ok(fee) => fee,
+            Err(ValidationError::Storage(e)) => return Err(e.into()),
+            Err(e) => {
+                tracing::debug!("Leaving {} out of the template: {}", entry.txid, e);
+                continue;
+            }
+        };
+        // The coinbase must be able to claim the subsidy plus all fees
+        let Some(total) = fees
+            .checked_add(fee)
+            .filter(|total| subsidy.checked_add(*total).is_some())
+        else {
+            continue;
+        };
+
+        for input in &entry.tx.inputs {
+            created.remove(&input.outpoint());
+        }
+        for (i, output) in entry.tx.outputs.iter().enumerate() {
+            let outpoint = OutPoint {
+                txid: entry.txid,
+                index: i as u32,
+            };
+            let utxo = UtxoEntry {
+                output: output.clone(),
+                height,
+                coinbase: false,
+            };
+            created.insert(outpoint, utxo);
+        }
+
+        size += entry.size;
+        fees = total;
+        block.transactions.push(entry.tx.clone());
+    }
+
+    block.transactions[0].outputs[0].amount = subsidy + fees;
+    block.header.merkle_root = block.calculate_merkle_root();
+
+    Ok(BlockTemplate {
+        block,
+        height,
+        fees,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::AcceptOutcome;
+    use crate::params::ChainParams;
+    use crate::pow::mine_block;
+    use nullchain_storage::Database;
+    use nullchain_test_utils::{owned_output, owner_hash, spend, EASY_BITS};
+    use nullchain_types::OutPoint;
+    use tempfile::TempDir;
+
+    fn mine(mut template: BlockTemplate) -> Block {
+        template.block.header.bits = EASY_BITS;
+        mine_block(template.block, Some(1_000_000)).unwrap().0
+    }
+
+    /// Extend the chain with empty blocks until the genesis coinbase can be
+    /// spent in the next one
+    fn mature_genesis(chain: &Chain) {
+        let empty = Mempool::default();
+        while chain.next_height().unwrap() < constants::COINBASE_MATURITY {
+            let block = mine(build_template(chain, &empty, &owner_hash(), 0).unwrap());
+            chain.accept_block(&block).unwrap();
+        }
+    }
+
+    /// Split `outpoint` worth `value` into `parts` outputs, paying `fee`
+    fn split(outpoint: OutPoint, value: u64, parts: u64, fee: u64) -> Transaction {
+        let outputs = (0..parts)
+            .map(|_| owned_output((value - fee) / parts))
+            .collect();
+        spend(&[outpoint], outputs)
+    }
+
+    #[test]
+    fn test_template_on_empty_chain() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let chain = Chain::new(&db);
+
+        let template = build_template(&chain, &Mempool::default(), &owner_hash(), 1000).unwrap();
+        assert_eq!(template.height, 0);
+        assert_eq!(template.block.header.previous_block, Hash256::zero());
+        assert_eq!(template.block.header.timestamp, 1000);
+
+        let genesis = mine(template);
+        assert!(matches!(
+            chain.accept_block(&genesis).unwrap(),
+            AcceptOutcome::NewTip { height: 0, .. }
+        ));
+    }
+
+    #[test]
+    fn test_regtest_templates_keep_initial_bits() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let params = ChainParams::regtest();
+        let chain = Chain::with_params(&db, &params);
+        chain.initialize().unwrap();
+
+        let start = params.genesis.header.timestamp;
+        for height in 1..=3 {
+            let template =
+                build_template(&chain, &Mempool::default(), &owner_hash(), start + height).unwrap();
+            assert_eq!(template.height, height);
+            assert_eq!(template.block.header.bits, params.initial_bits);
+
+            let (block, _) = mine_block(template.block, Some(1_000_000)).unwrap();
+            chain.accept_block(&block).unwrap();
+        }
+        assert_eq!(chain.tip().unwrap().unwrap().height, 3);
+    }
+
+    #[test]
+    fn test_template_includes_mempool() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let chain = Chain::new(&db);
+
+        let empty = Mempool::default();
+        let genesis = mine(build_template(&chain, &empty, &owner_hash(), 1000).unwrap());
+        chain.accept_block(&genesis).unwrap();
+        mature_genesis(&chain);
+        let tip = chain.tip().unwrap().unwrap();
+        let height = constants::COINBASE_MATURITY;
+
+        let coinbase = OutPoint {
+            txid: genesis.transactions[0].txid(),
+            index: 0,
+        };
+        let reward = genesis.transactions[0].outputs[0].amount;
+        let tx = split(coinbase, reward, 2, 500);
+
+        let mut mempool = Mempool::default();
+        mempool.insert(tx.clone(), chain.utxos(), height).unwrap();
+
+        // Clock behind the parent: timestamp is clamped past the median
+        let template = build_template(&chain, &mempool, &[1u8; 20], 10).unwrap();
+        assert_eq!(template.height, height);
+        assert_eq!(template.fees, 500);
+        assert_eq!(template.block.header.previous_block, tip.hash());
+        assert_eq!(
+            template.block.header.timestamp,
+            chain.median_time_past(&tip).unwrap() + 1
+        );
+        assert_eq!(template.block.header.bits, EASY_BITS);
+
+        let coinbase = &template.block.transactions[0];
+        assert_eq!(coinbase.outputs[0].amount, block_subsidy(height) + 500);
+        assert_eq!(coinbase.inputs[0].public_key, height.to_le_bytes());
+        assert_eq!(template.block.transactions[1].txid(), tx.txid());
+
+        let block = mine(template);
+        assert!(matches!(
+            chain.accept_block(&block).unwrap(),
+            AcceptOutcome::NewTip { height: h, .. } if h == height
+        ));
+    }
+
+    #[test]
+    fn test_template_skips_stale_entries() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let chain = Chain::new(&db);
+
+        let empty = Mempool::default();
+        let genesis = mine(build_template(&chain, &empty, &owner_hash(), 1000).unwrap());
+        chain.accept_block(&genesis).unwrap();
+        mature_genesis(&chain);
+
+        let coinbase = OutPoint {
+            txid: genesis.transactions[0].txid(),
+            index: 0,
+        };
+        let reward = genesis.transactions[0].outputs[0].amount;
+        let height = chain.next_height().unwrap();
+        let mut mempool = Mempool::default();
+        mempool
+            .insert(split(coinbase, reward, 2, 500), chain.utxos(), height)
+            .unwrap();
+
+        // A different spend of the same output confirms behind the pool's back
+        let mut other = Mempool::default();
+        other
+            .insert(split(coinbase, reward, 3, 100), chain.utxos(), height)
+            .unwrap();
+        let block = mine(build_template(&chain, &other, &owner_hash(), 2000).unwrap());
+        chain.accept_block(&block).unwrap();
+
+        let template = build_template(&chain, &mempool, &owner_hash(), 3000).unwrap();
+        assert_eq!(template.block.transactions.len(), 1);
+        assert_eq!(template.fees, 0);
+        assert!(matches!(
+            chain.accept_block(&mine(template)).unwrap(),
+            AcceptOutcome::NewTip { .. }
+        ));
+    }
+
+    #[test]
+    fn test_template_respects_size_limit() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let chain = Chain::new(&db);
+
+        let empty = Mempool::default();
+        let genesis = mine(build_template(&chain, &empty, &owner_hash(), 1000).unwrap());
+        chain.accept_block(&genesis).unwrap();
+        mature_genesis(&chain);
+        let reward = genesis.transactions[0].outputs[0].amount;
+
+        // Fan out into many outputs, then spend each with a large transaction
+        let fan_out = split(
+            OutPoint {
+                txid: genesis.transactions[0].txid(),
+                index: 0,
+            },
+            reward,
+            40,
+            0,
+        );
+        let mut mempool = Mempool::default();
+        mempool
+            .insert(fan_out.clone(), chain.utxos(), chain.next_height().unwrap())
+            .unwrap();
+        let block = mine(build_template(&chain, &mempool, &owner_hash(), 2000).unwrap());
+        chain.accept_block(&block).unwrap();
+
+        let mut mempool = Mempool::default();
+        let height = chain.next_height().unwrap();
+        let part = fan_out.outputs[0].amount;
+        for index in 0..40 {
+            let outpoint = OutPoint {
+                txid: fan_out.txid(),
+                index,
+            };
+            mempool
+                .insert(split(outpoint, part, 1000, 1000), chain.utxos(), height)
+                .unwrap();
+        }
+        assert!(mempool.total_size() > constants::MAX_BLOCK_SIZE);
+
+        let template = build_template(&chain, &mempool, &owner_hash(), 3000).unwrap();
+        assert!(template.block.size() <= constants::MAX_BLOCK_SIZE);
+        assert!(template.block.transactions.len() > 1);
+        assert!(template.block.transactions.len() < 41);
+    }
+}
@@ -0,0 +1,745 @@
+//! Block validation rules
+//!
+//! Every path that accepts a block (mining, storage import, network relay)
+//! runs the same checks through [`validate_block`].
+
+use nullchain_crypto::{InputError, TransactionExt};
+use nullchain_storage::{StorageError, UtxoSet};
+use nullchain_types::address::ADDRESS_HASH_LEN;
+use nullchain_types::{constants, Block, BlockHeader, Hash256, OutPoint, Transaction, UtxoEntry};
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+use crate::pow::verify_pow;
+use crate::subsidy::block_subsidy;
+
+/// Read-only access to unspent outputs used during validation
+pub trait UtxoView {
+    /// Look up an unspent output, `None` if it does not exist or is spent
+    fn get_utxo(&self, outpoint: &OutPoint) -> Result<Option<UtxoEntry>, StorageError>;
+}
+
+impl UtxoView for UtxoSet<'_> {
+    fn get_utxo(&self, outpoint: &OutPoint) -> Result<Option<UtxoEntry>, StorageError> {
+        self.get(outpoint)
+    }
+}
+
+impl UtxoView for HashMap<OutPoint, UtxoEntry> {
+    fn get_utxo(&self, outpoint: &OutPoint) -> Result<Option<UtxoEntry>, StorageError> {
+        Ok(self.get(outpoint).cloned())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ValidationError {
+    #[error("block size {size} exceeds maximum {max}")]
+    BlockTooLarge { size: usize, max: usize },
+
+    #[error("block has no transactions")]
+    NoTransactions,
+
+    #[error("first transaction is not a coinbase")]
+    MissingCoinbase,
+
+    #[error("unexpected coinbase at position {0}")]
+    UnexpectedCoinbase(usize),
+
+    #[error("merkle root mismatch: header {found}, computed {expected}")]
+    MerkleRootMismatch { expected: Hash256, found: Hash256 },
+
+    #[error("previous block mismatch: expected {expected}, found {found}")]
+    PreviousBlockMismatch { expected: Hash256, found: Hash256 },
+
+    #[error("timestamp {timestamp} is not after median time past {median}")]
+    TimestampTooEarly { timestamp: u64, median: u64 },
+
+    #[error("timestamp {timestamp} is too far in the future (limit {max})")]
+    TimestampTooFar { timestamp: u64, max: u64 },
+
+    #[error("block hash does not meet difficulty target")]
+    InvalidProofOfWork,
+
+    #[error("block bits {found:#010x} do not match required {expected:#010x}")]
+    BadDifficulty { expected: u32, found: u32 },
+
+    #[error("transaction {0} has no inputs")]
+    NoInputs(usize),
+
+    #[error("transaction {0} has no outputs")]
+    NoOutputs(usize),
+
+    #[error("transaction {tx} spends missing output {}:{}", outpoint.txid, outpoint.index)]
+    MissingInput { tx: usize, outpoint: OutPoint },
+
+    #[error(
+        "transaction {tx} spends coinbase output {}:{} from height {created} before it matures",
+        outpoint.txid, outpoint.index
+    )]
+    ImmatureCoinbase {
+        tx: usize,
+        outpoint: OutPoint,
+        created: u64,
+    },
+
+    #[error("output {}:{} spent twice in block", outpoint.txid, outpoint.index)]
+    DoubleSpend { outpoint: OutPoint },
+
+    #[error("transaction {0} value overflow")]
+    ValueOverflow(usize),
+
+    #[error("transaction {tx} output {output} pays to {len} bytes instead of an address hash")]
+    BadRecipient {
+        tx: usize,
+        output: usize,
+        len: usize,
+    },
+
+    #[error("transaction {tx} spends {outputs} but only has {inputs} in inputs")]
+    InsufficientInputs {
+        tx: usize,
+        inputs: u64,
+        outputs: u64,
+    },
+
+    #[error("transaction {tx}: {source}")]
+    InvalidInput { tx: usize, source: InputError },
+
+    #[error("coinbase does not encode block height {0}")]
+    BadCoinbaseHeight(u64),
+
+    #[error("coinbase pays {value} but subsidy plus fees is only {max}")]
+    CoinbaseTooLarge { value: u64, max: u64 },
+
+    #[error("storage error: {0}")]
+    Storage(#[from] StorageError),
+}
+
+/// Validate a block at `height` on top of `parent` against the given UTXO view
+pub fn validate_block(
+    block: &Block,
+    parent: &BlockHeader,
+    height: u64,
+    utxos: &impl UtxoView,
+) -> Result<(), ValidationError> {
+    check_block(block)?;
+    check_header_context(&block.header, parent)?;
+    check_coinbase_height(block, height)?;
+    let fees = check_transaction_inputs(block, height, utxos)?;
+    check_coinbase_value(block, height, fees)?;
+    Ok(())
+}
+
+/// Context-free checks: size, coinbase placement, merkle root and proof-of-work
+pub fn check_block(block: &Block) -> Result<(), ValidationError> {
+    let size = block.size();
+    if size > constants::MAX_BLOCK_SIZE {
+        return Err(ValidationError::BlockTooLarge {
+            size,
+            max: constants::MAX_BLOCK_SIZE,
+        });
+    }
+
+    let coinbase = block
+        .transactions
+        .first()
+        .ok_or(ValidationError::NoTransactions)?;
+    if !coinbase.is_coinbase() {
+        return Err(ValidationError::MissingCoinbase);
+    }
+    if let Some(pos) = block
+        .transactions
+        .iter()
+        .skip(1)
+        .position(|tx| tx.is_coinbase())
+    {
+        return Err(ValidationError::UnexpectedCoinbase(pos + 1));
+    }
+
+    for (i, tx) in block.transactions.iter().enumerate() {
+        check_transaction(tx, i)?;
+    }
+
+    let merkle_root = block.calculate_merkle_root();
+    if block.header.merkle_root != merkle_root {
+        return Err(ValidationError::MerkleRootMismatch {
+            expected: merkle_root,
+            found: block.header.merkle_root,
+        });
+    }
+
+    if !verify_pow(block) {
+        return Err(ValidationError::InvalidProofOfWork);
+    }
+
+    Ok(())
+}
+
+/// Context-free transaction checks; `index` is the position reported in errors
+pub fn check_transaction(tx: &Transaction, index: usize) -> Result<(), ValidationError> {
+    if tx.inputs.is_empty() {
+        return Err(ValidationError::NoInputs(index));
+    }
+    if tx.outputs.is_empty() {
+        return Err(ValidationError::NoOutputs(index));
+    }
+    for (output, out) in tx.outputs.iter().enumerate() {
+        if out.recipient.len() != ADDRESS_HASH_LEN {
+            return Err(ValidationError::BadRecipient {
+                tx: index,
+                output,
+                len: out.recipient.len(),
+            });
+        }
+    }
+    tx.outputs
+        .iter()
+        .try_fold(0u64, |sum, out| sum.checked_add(out.amount))
+        .ok_or(ValidationError::ValueOverflow(index))?;
+    Ok(())
+}
+
+/// Checks that depend on the parent header
+pub fn check_header_context(
+    header: &BlockHeader,
+    parent: &BlockHeader,
+) -> Result<(), ValidationError> {
+    let parent_hash = parent.hash();
+    if header.previous_block != parent_hash {
+        return Err(ValidationError::PreviousBlockMismatch {
+            expected: parent_hash,
+            found: header.previous_block,
+        });
+    }
+
+    Ok(())
+}
+
+/// Check the header timestamp against the median of recent blocks and the
+/// local clock
+///
+/// The timestamp must be strictly after `median_time_past` and at most
+/// `max_future_drift` seconds ahead of `now`.
+pub fn check_timestamp(
+    header: &BlockHeader,
+    median_time_past: u64,
+    now: u64,
+    max_future_drift: u64,
+) -> Result<(), ValidationError> {
+    if header.timestamp <= median_time_past {
+        return Err(ValidationError::TimestampTooEarly {
+            timestamp: header.timestamp,
+            median: median_time_past,
+        });
+    }
+
+    let max = now.saturating_add(max_future_drift);
+    if header.timestamp > max {
+        return Err(ValidationError::TimestampTooFar {
+            timestamp: header.timestamp,
+            max,
+        });
+    }
+
+    Ok(())
+}
+
+/// Check that every non-coinbase input exists, is spent once, is signed by the
+/// owner of the spent output and covers its outputs
+///
+/// Outputs created earlier in the same block may be spent by later transactions.
+/// Returns the total fees paid by the block's transactions.
+pub fn check_transaction_inputs(
+    block: &Block,
+    height: u64,
+    utxos: &impl UtxoView,
+) -> Result<u64, ValidationError> {
+    let mut fees = 0u64;
+    let mut created: HashMap<OutPoint, UtxoEntry> = HashMap::new();
+    let mut spent: HashSet<OutPoint> = HashSet::new();
+
+    for (i, tx) in block.transactions.iter().enumerate() {
+        if !tx.is_coinbase() {
+            for input in &tx.inputs {
+                let outpoint = input.outpoint();
+                if !spent.insert(outpoint) {
+                    return Err(ValidationError::DoubleSpend { outpoint });
+                }
+            }
+
+            let view = Overlay {
+                created: &created,
+                base: utxos,
+            };
+            let fee = check_spend(tx, i, height, &view)?;
+            fees = fees
+                .checked_add(fee)
+                .ok_or(ValidationError::ValueOverflow(i))?;
+        }
+
+        let txid = tx.txid();
+        for (index, output) in tx.outputs.iter().enumerate() {
+            let outpoint = OutPoint {
+                txid,
+                index: index as u32,
+            };
+            created.insert(
+                outpoint,
+                UtxoEntry {
+                    output: output.clone(),
+                    height,
+                    coinbase: tx.is_coinbase(),
+                },
+            );
+        }
+    }
+
+    Ok(fees)
+}
+
+/// Reject a coinbase that does not commit to `height`
+///
+/// The height is what keeps coinbases, and therefore txids, unique: without
+/// it a miner could repeat an earlier coinbase and overwrite its output.
+pub fn check_coinbase_height(block: &Block, height: u64) -> Result<(), ValidationError> {
+    let coinbase = block
+        .transactions
+        .first()
+        .ok_or(ValidationError::NoTransactions)?;
+    if coinbase.inputs[0].public_key != height.to_le_bytes() {
+        return Err(ValidationError::BadCoinbaseHeight(height));
+    }
+    Ok(())
+}
+
+/// Reject a coinbase paying out more than the subsidy at `height` plus `fees`
+pub fn check_coinbase_value(block: &Block, height: u64, fees: u64) -> Result<(), ValidationError> {
+    let coinbase = block
+        .transactions
+        .first()
+        .ok_or(ValidationError::NoTransactions)?;
+    let value = coinbase
+        .outputs
+        .iter()
+        .try_fold(0u64, |sum, out| sum.checked_add(out.amount))
+        .ok_or(ValidationError::ValueOverflow(0))?;
+
+    let max = block_subsidy(height).saturating_add(fees);
+    if value > max {
+        return Err(ValidationError::CoinbaseTooLarge { value, max });
+    }
+    Ok(())
+}
+
+/// Check that a non-coinbase transaction's inputs exist in `utxos`, are mature
+/// at `height`, are signed by the owners of the spent outputs and cover its
+/// outputs
+///
+/// Returns the fee: total input value minus total output value. `index` is
+/// the position reported in errors.
+pub fn check_spend(
+    tx: &Transaction,
+    index: usize,
+    height: u64,
+    utxos: &impl UtxoView,
+) -> Result<u64, ValidationError> {
+    let mut input_value = 0u64;
+    let mut spent_outputs = Vec::with_capacity(tx.inputs.len());
+
+    for input in &tx.inputs {
+        let outpoint = input.outpoint();
+        let entry = utxos
+            .get_utxo(&outpoint)?
+            .ok_or(ValidationError::MissingInput {
+                tx: index,
+                outpoint,
+            })?;
+        if !entry.is_spendable_at(height) {
+            return Err(ValidationError::ImmatureCoinbase {
+                tx: index,
+                outpoint,
+                created: entry.height,
+            });
+        }
+        let output = entry.output;
+
+        input_value = input_value
+            .checked_add(output.amount)
+            .ok_or(ValidationError::ValueOverflow(index))?;
+        spent_outputs.push(output);
+    }
+
+    let output_value: u64 = tx.outputs.iter().map(|out| out.amount).sum();
+    if output_value > input_value {
+        return Err(ValidationError::InsufficientInputs {
+            tx: index,
+            inputs: input_value,
+            outputs: output_value,
+        });
+    }
+
+    tx.verify_inputs(&spent_outputs)
+        .map_err(|source| ValidationError::InvalidInput { tx: index, source })?;
+
+    Ok(input_value - output_value)
+}
+
+/// Outputs created earlier in a block on top of the UTXO set
+pub(crate) struct Overlay<'a, V> {
+    pub(crate) created: &'a HashMap<OutPoint, UtxoEntry>,
+    pub(crate) base: &'a V,
+}
+
+impl<V: UtxoView> UtxoView for Overlay<'_, V> {
+    fn get_utxo(&self, outpoint: &OutPoint) -> Result<Option<UtxoEntry>, StorageError> {
+        match self.created.get(outpoint) {
+            Some(entry) => Ok(Some(entry.clone())),
+            None => self.base.get_utxo(outpoint),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pow::mine_block;
+    use nullchain_test_utils::{funded, funded_outpoint, owned_output, spend, EASY_BITS};
+    use nullchain_types::Transaction;
+
+    fn child_of(parent: &BlockHeader, transactions: Vec<Transaction>) -> Block {
+        let mut block = Block {
+            header: BlockHeader {
+                version: 1,
+                previous_block: parent.hash(),
+                merkle_root: Hash256::zero(),
+                timestamp: parent.timestamp + 600,
+                bits: EASY_BITS,
+                nonce: 0,
+            },
+            transactions,
+        };
+        block.header.merkle_root = block.calculate_merkle_root();
+        mine_block(block, Some(1_000_000)).unwrap().0
+    }
+
+    fn coinbase() -> Transaction {
+        Transaction::coinbase(vec![0u8; 20], constants::INITIAL_BLOCK_REWARD, 1)
+    }
+
+    #[test]
+    fn test_valid_block() {
+        let parent = Block::genesis().header;
+        let view = funded(1, 500);
+        let outpoint = funded_outpoint(0);
+        let block = child_of(
+            &parent,
+            vec![coinbase(), spend(&[outpoint], vec![owned_output(400)])],
+        );
+
+        validate_block(&block, &parent, 1, &view).unwrap();
+    }
+
+    #[test]
+    fn test_spend_output_created_in_same_block() {
+        let parent = Block::genesis().header;
+        let view = funded(1, 500);
+        let outpoint = funded_outpoint(0);
+        let first = spend(&[outpoint], vec![owned_output(400)]);
+        let second = spend(
+            &[OutPoint {
+                txid: first.txid(),
+                index: 0,
+            }],
+            vec![owned_output(300)],
+        );
+        let block = child_of(&parent, vec![coinbase(), first, second]);
+
+        validate_block(&block, &parent, 1, &view).unwrap();
+    }
+
+    #[test]
+    fn test_reject_bad_merkle_root() {
+        let parent = Block::genesis().header;
+        let mut block = child_of(&parent, vec![coinbase()]);
+        block.header.merkle_root = Hash256::zero();
+
+        assert!(matches!(
+            check_block(&block),
+            Err(ValidationError::MerkleRootMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_reject_missing_coinbase() {
+        let parent = Block::genesis().header;
+        let outpoint = funded_outpoint(0);
+        let block = child_of(&parent, vec![spend(&[outpoint], vec![owned_output(400)])]);
+
+        assert!(matches!(
+            check_block(&block),
+            Err(ValidationError::MissingCoinbase)
+        ));
+    }
+
+    #[test]
+    fn test_reject_second_coinbase() {
+        let parent = Block::genesis().header;
+        let block = child_of(&parent, vec![coinbase(), coinbase()]);
+
+        assert!(matches!(
+            check_block(&block),
+            Err(ValidationError::UnexpectedCoinbase(1))
+        ));
+    }
+
+    #[test]
+    fn test_reject_bad_recipient_length() {
+        let parent = Block::genesis().header;
+        let outpoint = funded_outpoint(0);
+        for len in [0, 19, 21, 32] {
+            let mut output = owned_output(400);
+            output.recipient = vec![7u8; len];
+            let block = child_of(
+                &parent,
+                vec![
+                    coinbase(),
+                    spend(&[outpoint], vec![owned_output(1), output]),
+                ],
+            );
+
+            assert!(matches!(
+                check_block(&block),
+                Err(ValidationError::BadRecipient {
+                    tx: 1,
+                    output: 1,
+                    len: found,
+                }) if found == len
+            ));
+        }
+
+        let mut coinbase = coinbase();
+        coinbase.outputs[0].recipient.push(0);
+        assert!(matches!(
+            check_block(&child_of(&parent, vec![coinbase])),
+            Err(ValidationError::BadRecipient { tx: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_reject_oversized_block() {
+        let parent = Block::genesis().header;
+        let mut cb = coinbase();
+        cb.inputs[0].signature = vec![0u8; constants::MAX_BLOCK_SIZE];
+        let block = child_of(&parent, vec![cb]);
+
+        assert!(matches!(
+            check_block(&block),
+            Err(ValidationError::BlockTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_reject_wrong_parent() {
+        let parent = Block::genesis().header;
+        let block = child_of(&parent, vec![coinbase()]);
+        let mut other = parent.clone();
+        other.nonce += 1;
+
+        assert!(matches!(
+            validate_block(&block, &other, 1, &HashMap::new()),
+            Err(ValidationError::PreviousBlockMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_reject_timestamp_not_after_median() {
+        let mut header = Block::genesis().header;
+        header.timestamp = 1000;
+
+        check_timestamp(&header, 999, 1000, 0).unwrap();
+        assert!(matches!(
+            check_timestamp(&header, 1000, 2000, 0),
+            Err(ValidationError::TimestampTooEarly { median: 1000, .. })
+        ));
+    }
+
+    #[test]
+    fn test_reject_future_timestamp() {
+        let mut header = Block::genesis().header;
+        header.timestamp = 1000 + 7200;
+
+        check_timestamp(&header, 0, 1000, 7200).unwrap();
+        assert!(matches!(
+            check_timestamp(&header, 0, 999, 7200),
+            Err(ValidationError::TimestampTooFar { max: 8199, .. })
+        ));
+    }
+
+    #[test]
+    fn test_reject_missing_input() {
+        let parent = Block::genesis().header;
+        let outpoint = funded_outpoint(0);
+        let block = child_of(
+            &parent,
+            vec![coinbase(), spend(&[outpoint], vec![owned_output(400)])],
+        );
+
+        assert!(matches!(
+            validate_block(&block, &parent, 1, &HashMap::new()),
+            Err(ValidationError::MissingInput { tx: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_reject_immature_coinbase() {
+        let parent = Block::genesis().header;
+        let mut view = funded(1, 500);
+        let outpoint = funded_outpoint(0);
+        view.get_mut(&outpoint).unwrap().coinbase = true;
+        let block = child_of(
+            &parent,
+            vec![coinbase(), spend(&[outpoint], vec![owned_output(400)])],
+        );
+
+        assert!(matches!(
+            validate_block(&block, &parent, 1, &view),
+            Err(ValidationError::ImmatureCoinbase {
+                tx: 1,
+                created: 0,
+                ..
+            })
+        ));
+        assert_eq!(
+            check_transaction_inputs(&block, constants::COINBASE_MATURITY, &view).unwrap(),
+            100
+        );
+    }
+
+    #[test]
+    fn test_reject_double_spend() {
+        let parent = Block::genesis().header;
+        let view = funded(1, 500);
+        let outpoint = funded_outpoint(0);
+        let block = child_of(
+            &parent,
+            vec![
+                coinbase(),
+                spend(&[outpoint], vec![owned_output(400)]),
+                spend(&[outpoint], vec![owned_output(300)]),
+            ],
+        );
+
+        assert!(matches!(
+            validate_block(&block, &parent, 1, &view),
+            Err(ValidationError::DoubleSpend { .. })
+        ));
+    }
+
+    #[test]
+    fn test_reject_overspend() {
+        let parent = Block::genesis().header;
+        let view = funded(1, 500);
+        let outpoint = funded_outpoint(0);
+        let block = child_of(
+            &parent,
+            vec![coinbase(), spend(&[outpoint], vec![owned_output(501)])],
+        );
+
+        assert!(matches!(
+            validate_block(&block, &parent, 1, &view),
+            Err(ValidationError::InsufficientInputs {
+                inputs: 500,
+                outputs: 501,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_reject_invalid_signature() {
+        let parent = Block::genesis().header;
+        let view = funded(1, 500);
+        let outpoint = funded_outpoint(0);
+        let mut tx = spend(&[outpoint], vec![owned_output(400)]);
+        tx.inputs[0].signature[0] ^= 0xff;
+        let block = child_of(&parent, vec![coinbase(), tx]);
+
+        assert!(matches!(
+            validate_block(&block, &parent, 1, &view),
+            Err(ValidationError::InvalidInput {
+                tx: 1,
+                source: InputError::InvalidSignature(0)
+            })
+        ));
+    }
+
+    #[test]
+    fn test_coinbase_may_claim_fees() {
+        let parent = Block::genesis().header;
+        let view = funded(1, 500);
+        let outpoint = funded_outpoint(0);
+        let cb = Transaction::coinbase(vec![0u8; 20], block_subsidy(1) + 100, 1);
+        let block = child_of(
+            &parent,
+            vec![cb, spend(&[outpoint], vec![owned_output(400)])],
+        );
+
+        validate_block(&block, &parent, 1, &view).unwrap();
+    }
+
+    #[test]
+    fn test_reject_coinbase_above_subsidy_and_fees() {
+        let parent = Block::genesis().header;
+        let view = funded(1, 500);
+        let outpoint = funded_outpoint(0);
+        let cb = Transaction::coinbase(vec![0u8; 20], block_subsidy(1) + 101, 1);
+        let block = child_of(
+            &parent,
+            vec![cb, spend(&[outpoint], vec![owned_output(400)])],
+        );
+
+        assert!(matches!(
+            validate_block(&block, &parent, 1, &view),
+            Err(ValidationError::CoinbaseTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_reject_coinbase_without_height() {
+        let parent = Block::genesis().header;
+        let cb = Transaction::coinbase(vec![0u8; 20], block_subsidy(2), 2);
+        let block = child_of(&parent, vec![cb]);
+
+        assert!(matches!(
+            validate_block(&block, &parent, 1, &HashMap::new()),
+            Err(ValidationError::BadCoinbaseHeight(1))
+        ));
+    }
+
+    #[test]
+    fn test_reject_duplicated_coinbase() {
+        let parent = Block::genesis().header;
+        let first = child_of(&parent, vec![coinbase()]);
+        validate_block(&first, &parent, 1, &HashMap::new()).unwrap();
+
+        // The same coinbase byte for byte one block later has the same txid
+        let repeat = child_of(&first.header, vec![coinbase()]);
+        assert_eq!(repeat.transactions[0].txid(), first.transactions[0].txid());
+        assert!(matches!(
+            validate_block(&repeat, &first.header, 2, &HashMap::new()),
+            Err(ValidationError::BadCoinbaseHeight(2))
+        ));
+    }
+
+    #[test]
+    fn test_coinbase_limit_follows_halving() {
+        let block = child_of(&Block::genesis().header, vec![coinbase()]);
+        let halving = constants::HALVING_INTERVAL;
+
+        check_coinbase_value(&block, halving - 1, 0).unwrap();
+        assert!(matches!(
+            check_coinbase_value(&block, halving, 0),
+            Err(ValidationError::CoinbaseTooLarge { max, .. }) if max == block_subsidy(halving)
+        ));
+    }
+}
@@ -20,6 +20,14 @@ pub fn hash_block_header(header: &[u8]) -> Hash256 {
     double_hash(header)
 }
 
+/// Hash a public key to the 20-byte form used as an output recipient
+pub fn pubkey_hash(public_key: &[u8]) -> [u8; 20] {
+    let hash = blake3::hash(public_key);
+    let mut out = [0u8; 20];
+    out.copy_from_slice(&hash.as_bytes()[0..20]);
+    out
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,256 @@
+//! Hierarchical deterministic keys from a mnemonic seed
+//!
+//! Mnemonics follow BIP39 (English word list, PBKDF2 seed stretching).
+//! Keys are derived from the seed with SLIP-0010 for Ed25519, which only
+//! defines hardened children: every path component is hardened.
+
+use ed25519_dalek::SigningKey;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha512;
+use std::fmt;
+use thiserror::Error;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// Flag marking a hardened child index
+pub const HARDENED: u32 = 0x8000_0000;
+
+/// BIP44 coin type used in NullChain wallet paths (unregistered)
+pub const COIN_TYPE: u32 = 0x4e4c;
+
+type HmacSha512 = Hmac<Sha512>;
+
+#[derive(Error, Debug)]
+pub enum HdError {
+    #[error("invalid mnemonic: {0}")]
+    InvalidMnemonic(String),
+
+    #[error("unsupported mnemonic length {0} (use 12, 15, 18, 21 or 24 words)")]
+    InvalidWordCount(usize),
+
+    #[error("child index {0} is out of range")]
+    InvalidIndex(u32),
+
+    #[error("invalid derivation path {0:?}")]
+    InvalidPath(String),
+}
+
+/// BIP39 mnemonic sentence
+pub struct Mnemonic(bip39::Mnemonic);
+
+impl Mnemonic {
+    /// Fresh mnemonic of `word_count` words from the system CSPRNG
+    pub fn generate(word_count: usize) -> Result<Self, HdError> {
+        if !(12..=24).contains(&word_count) || !word_count.is_multiple_of(3) {
+            return Err(HdError::InvalidWordCount(word_count));
+        }
+
+        // Every 3 words carry 32 bits of entropy
+        let mut entropy = Zeroizing::new(vec![0u8; word_count / 3 * 4]);
+        OsRng.fill_bytes(&mut entropy);
+        Self::from_entropy(&entropy)
+    }
+
+    pub fn from_entropy(entropy: &[u8]) -> Result<Self, HdError> {
+        bip39::Mnemonic::from_entropy(entropy)
+            .map(Self)
+            .map_err(|e| HdError::InvalidMnemonic(e.to_string()))
+    }
+
+    /// Parse a phrase, checking the word list and checksum
+    pub fn parse(phrase: &str) -> Result<Self, HdError> {
+        bip39::Mnemonic::parse(phrase)
+            .map(Self)
+            .map_err(|e| HdError::InvalidMnemonic(e.to_string()))
+    }
+
+    pub fn word_count(&self) -> usize {
+        self.0.word_count()
+    }
+
+    /// 64-byte seed, optionally protected by an extra passphrase
+    pub fn to_seed(&self, passphrase: &str) -> Zeroizing<[u8; 64]> {
+        Zeroizing::new(self.0.to_seed(passphrase))
+    }
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Private key and chain code of a node in the derivation tree
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct ExtendedKey {
+    key: [u8; 32],
+    chain_code: [u8; 32],
+}
+
+impl ExtendedKey {
+    /// Master key of `seed`
+    pub fn from_seed(seed: &[u8]) -> Self {
+        Self::from_hmac(b"ed25519 seed", &[seed])
+    }
+
+    /// Hardened child `index`, which must be below [`HARDENED`]
+    pub fn derive_child(&self, index: u32) -> Result<Self, HdError> {
+        if index >= HARDENED {
+            return Err(HdError::InvalidIndex(index));
+        }
+        Ok(Self::from_hmac(
+            &self.chain_code,
+            &[&[0], &self.key, &(index | HARDENED).to_be_bytes()],
+        ))
+    }
+
+    /// Descend along `path`, e.g. `m/44'/0'/0'`
+    ///
+    /// Components may be written with or without the hardened marker
+    /// (`'` or `h`); all of them are derived hardened.
+    pub fn derive_path(&self, path: &str) -> Result<Self, HdError> {
+        let invalid = || HdError::InvalidPath(path.to_string());
+
+        let mut components = path.split('/');
+        if components.next() != Some("m") {
+            return Err(invalid());
+        }
+
+        let mut key = self.clone();
+        for component in components {
+            let digits = component.trim_end_matches(['\'', 'h']);
+            let index: u32 = digits.parse().map_err(|_| invalid())?;
+            key = key.derive_child(index)?;
+        }
+        Ok(key)
+    }
+
+    pub fn signing_key(&self) -> SigningKey {
+        SigningKey::from_bytes(&self.key)
+    }
+
+    pub fn chain_code(&self) -> &[u8; 32] {
+        &self.chain_code
+    }
+
+    fn from_hmac(key: &[u8], data: &[&[u8]]) -> Self {
+        let mut mac = HmacSha512::new_from_slice(key).expect("HMAC accepts any key length");
+        for part in data {
+            mac.update(part);
+        }
+        let output = Zeroizing::new(<[u8; 64]>::from(mac.finalize().into_bytes()));
+
+        let mut extended = Self {
+            key: [0; 32],
+            chain_code: [0; 32],
+        };
+        extended.key.copy_from_slice(&output[..32]);
+        extended.chain_code.copy_from_slice(&output[32..]);
+        extended
+    }
+}
+
+/// Derivation path of the wallet key at `index`: `m/44'/COIN_TYPE'/0'/0'/index'`
+pub fn wallet_key_path(index: u32) -> String {
+    format!("m/44'/{}'/0'/0'/{}'", COIN_TYPE, index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bip39_vector() {
+        let mnemonic = Mnemonic::from_entropy(&[0u8; 16]).unwrap();
+        assert_eq!(
+            mnemonic.to_string(),
+            "abandon abandon abandon abandon abandon abandon abandon abandon \
+             abandon abandon abandon about"
+        );
+        assert_eq!(
+            hex::encode(*mnemonic.to_seed("TREZOR")),
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f\
+             09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+        );
+    }
+
+    #[test]
+    fn test_generate_and_parse() {
+        let mnemonic = Mnemonic::generate(24).unwrap();
+        assert_eq!(mnemonic.word_count(), 24);
+
+        let parsed = Mnemonic::parse(&mnemonic.to_string()).unwrap();
+        assert_eq!(*parsed.to_seed(""), *mnemonic.to_seed(""));
+
+        assert!(matches!(
+            Mnemonic::generate(13),
+            Err(HdError::InvalidWordCount(13))
+        ));
+    }
+
+    #[test]
+    fn test_reject_bad_checksum() {
+        let phrase = "abandon ".repeat(12);
+        assert!(matches!(
+            Mnemonic::parse(phrase.trim()),
+            Err(HdError::InvalidMnemonic(_))
+        ));
+    }
+
+    #[test]
+    fn test_slip10_vector() {
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let master = ExtendedKey::from_seed(&seed);
+        assert_eq!(
+            hex::encode(master.signing_key().to_bytes()),
+            "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7"
+        );
+        assert_eq!(
+            hex::encode(master.chain_code()),
+            "90046a93de5380a72b5e45010748567d5ea02bbf6522f979e05c0d8d8ca9fffb"
+        );
+
+        let child = master.derive_path("m/0'/1'/2'").unwrap();
+        assert_eq!(
+            hex::encode(child.signing_key().to_bytes()),
+            "92a5b23c0b8a99e37d07df3fb9966917f5d06e02ddbd909c7e184371463e9fc9"
+        );
+        assert_eq!(
+            hex::encode(child.chain_code()),
+            "2e69929e00b5ab250f49c3fb1c12f252de4fed2c1db88387094a0f8c4c9ccd6c"
+        );
+    }
+
+    #[test]
+    fn test_derivation_is_deterministic() {
+        let mnemonic = Mnemonic::generate(12).unwrap();
+        let master = ExtendedKey::from_seed(&*mnemonic.to_seed(""));
+        let restored =
+            ExtendedKey::from_seed(&*Mnemonic::parse(&mnemonic.to_string()).unwrap().to_seed(""));
+
+        let a = master.derive_path(&wallet_key_path(5)).unwrap();
+        let b = restored.derive_path(&wallet_key_path(5)).unwrap();
+        assert_eq!(a.signing_key().to_bytes(), b.signing_key().to_bytes());
+
+        let other = master.derive_path(&wallet_key_path(6)).unwrap();
+        assert_ne!(a.signing_key().to_bytes(), other.signing_key().to_bytes());
+    }
+
+    #[test]
+    fn test_reject_bad_path() {
+        let master = ExtendedKey::from_seed(&[1u8; 64]);
+        assert!(matches!(
+            master.derive_path("0'/1'"),
+            Err(HdError::InvalidPath(_))
+        ));
+        assert!(matches!(
+            master.derive_path("m/x'"),
+            Err(HdError::InvalidPath(_))
+        ));
+        assert!(matches!(
+            master.derive_child(HARDENED),
+            Err(HdError::InvalidIndex(_))
+        ));
+    }
+}
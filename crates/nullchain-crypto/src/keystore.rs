@@ -0,0 +1,406 @@
+//! Password-encrypted storage for secret keys
+//!
+//! A keystore is a versioned header followed by the ciphertext:
+//!
+//! ```text
+//! magic "NCKS" | version u8 | memory_kib u32 | iterations u32 | parallelism u32
+//!     | salt [16] | nonce [24] | ciphertext + tag
+//! ```
+//!
+//! Integers are little-endian. The encryption key is derived from the
+//! passphrase with Argon2id using the stored parameters and salt, and the
+//! secret is sealed with XChaCha20-Poly1305. The whole header is
+//! authenticated as associated data, so tampering with the parameters
+//! fails decryption just like a wrong passphrase.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+/// Current keystore format version
+pub const KEYSTORE_VERSION: u8 = 1;
+
+const MAGIC: &[u8; 4] = b"NCKS";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = MAGIC.len() + 1 + 12 + SALT_LEN + NONCE_LEN;
+const TAG_LEN: usize = 16;
+
+/// Largest accepted Argon2 memory cost: 4 GiB
+const MAX_MEMORY_KIB: u32 = 4 * 1024 * 1024;
+/// Largest accepted number of Argon2 passes
+const MAX_ITERATIONS: u32 = 64;
+/// Largest accepted number of Argon2 lanes
+const MAX_PARALLELISM: u32 = 16;
+
+/// Length of secret keys in the legacy XOR format
+const LEGACY_KEY_LEN: usize = 32;
+
+#[derive(Error, Debug)]
+pub enum KeystoreError {
+    #[error("malformed keystore: {0}")]
+    Malformed(&'static str),
+
+    #[error("unsupported keystore version {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("invalid key derivation parameters: {0}")]
+    Kdf(String),
+
+    #[error("wrong passphrase or corrupted keystore")]
+    Decryption,
+}
+
+/// Argon2id cost parameters, stored in the keystore header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    /// Memory cost in KiB
+    pub memory_kib: u32,
+    /// Number of passes over the memory
+    pub iterations: u32,
+    /// Degree of parallelism
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    /// 64 MiB, 3 passes, single lane
+    fn default() -> Self {
+        Self {
+            memory_kib: 64 * 1024,
+            iterations: 3,
+            parallelism: 1,
+        }
+    }
+}
+
+impl KdfParams {
+    /// Reject costs beyond what any wallet would choose, so a crafted
+    /// keystore cannot make unlocking exhaust memory or spin for hours
+    fn check_bounds(&self) -> Result<(), KeystoreError> {
+        if self.memory_kib > MAX_MEMORY_KIB {
+            return Err(KeystoreError::Kdf(format!(
+                "memory cost {} KiB exceeds {} KiB",
+                self.memory_kib, MAX_MEMORY_KIB
+            )));
+        }
+        if self.iterations > MAX_ITERATIONS {
+            return Err(KeystoreError::Kdf(format!(
+                "{} iterations exceed {}",
+                self.iterations, MAX_ITERATIONS
+            )));
+        }
+        if self.parallelism == 0 || self.parallelism > MAX_PARALLELISM {
+            return Err(KeystoreError::Kdf(format!(
+                "parallelism {} outside 1..={}",
+                self.parallelism, MAX_PARALLELISM
+            )));
+        }
+        Ok(())
+    }
+
+    fn derive_key(
+        &self,
+        passphrase: &[u8],
+        salt: &[u8],
+    ) -> Result<Zeroizing<[u8; 32]>, KeystoreError> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, Some(32))
+            .map_err(|e| KeystoreError::Kdf(e.to_string()))?;
+        let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+
+        let mut key = Zeroizing::new([0u8; 32]);
+        argon2
+            .hash_password_into(passphrase, salt, &mut *key)
+            .map_err(|e| KeystoreError::Kdf(e.to_string()))?;
+        Ok(key)
+    }
+}
+
+/// A secret sealed under a passphrase
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keystore {
+    params: KdfParams,
+    salt: [u8; SALT_LEN],
+    nonce: [u8; NONCE_LEN],
+    ciphertext: Vec<u8>,
+}
+
+impl Keystore {
+    /// Encrypt `secret` with a fresh random salt and nonce
+    pub fn encrypt(
+        secret: &[u8],
+        passphrase: &[u8],
+        params: KdfParams,
+    ) -> Result<Self, KeystoreError> {
+        params.check_bounds()?;
+
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut keystore = Self {
+            params,
+            salt,
+            nonce,
+            ciphertext: Vec::new(),
+        };
+
+        let key = params.derive_key(passphrase, &salt)?;
+        let header = keystore.header();
+        keystore.ciphertext = cipher(&key)
+            .encrypt(
+                &XNonce::from(nonce),
+                Payload {
+                    msg: secret,
+                    aad: &header,
+                },
+            )
+            .map_err(|_| KeystoreError::Decryption)?;
+
+        Ok(keystore)
+    }
+
+    /// Recover the secret, failing on a wrong passphrase or any tampering
+    pub fn decrypt(&self, passphrase: &[u8]) -> Result<Zeroizing<Vec<u8>>, KeystoreError> {
+        let key = self.params.derive_key(passphrase, &self.salt)?;
+        let header = self.header();
+        cipher(&key)
+            .decrypt(
+                &XNonce::from(self.nonce),
+                Payload {
+                    msg: &self.ciphertext,
+                    aad: &header,
+                },
+            )
+            .map(Zeroizing::new)
+            .map_err(|_| KeystoreError::Decryption)
+    }
+
+    pub fn params(&self) -> KdfParams {
+        self.params
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header();
+        bytes.extend_from_slice(&self.ciphertext);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, KeystoreError> {
+        if bytes.len() < MAGIC.len() + 1 || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(KeystoreError::Malformed("missing keystore header"));
+        }
+        let version = bytes[MAGIC.len()];
+        if version != KEYSTORE_VERSION {
+            return Err(KeystoreError::UnsupportedVersion(version));
+        }
+        if bytes.len() < HEADER_LEN + TAG_LEN {
+            return Err(KeystoreError::Malformed("truncated keystore"));
+        }
+
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes(bytes[offset..offset + 4].try_into().expect("4 bytes"))
+        };
+        let params_at = MAGIC.len() + 1;
+        let salt_at = params_at + 12;
+        let nonce_at = salt_at + SALT_LEN;
+
+        let params = KdfParams {
+            memory_kib: u32_at(params_at),
+            iterations: u32_at(params_at + 4),
+            parallelism: u32_at(params_at + 8),
+        };
+        params.check_bounds()?;
+
+        Ok(Self {
+            params,
+            salt: bytes[salt_at..nonce_at].try_into().expect("salt length"),
+            nonce: bytes[nonce_at..HEADER_LEN]
+                .try_into()
+                .expect("nonce length"),
+            ciphertext: bytes[HEADER_LEN..].to_vec(),
+        })
+    }
+
+    /// Whether `bytes` look like a key file from before keystores existed
+    pub fn is_legacy(bytes: &[u8]) -> bool {
+        bytes.len() == LEGACY_KEY_LEN
+    }
+
+    fn header(&self) -> Vec<u8> {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(MAGIC);
+        header.push(KEYSTORE_VERSION);
+        header.extend_from_slice(&self.params.memory_kib.to_le_bytes());
+        header.extend_from_slice(&self.params.iterations.to_le_bytes());
+        header.extend_from_slice(&self.params.parallelism.to_le_bytes());
+        header.extend_from_slice(&self.salt);
+        header.extend_from_slice(&self.nonce);
+        header
+    }
+}
+
+fn cipher(key: &[u8; 32]) -> XChaCha20Poly1305 {
+    XChaCha20Poly1305::new_from_slice(key).expect("32-byte key")
+}
+
+/// Undo the unauthenticated XOR encryption of legacy `key.enc` files
+///
+/// Only for migrating old wallets: any passphrase "succeeds", so the caller
+/// must check the result against the stored public key.
+pub fn decrypt_legacy(
+    bytes: &[u8],
+    passphrase: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, KeystoreError> {
+    if !Keystore::is_legacy(bytes) {
+        return Err(KeystoreError::Malformed("not a legacy key file"));
+    }
+
+    let key_hash = blake3::hash(passphrase);
+    let secret = bytes
+        .iter()
+        .zip(key_hash.as_bytes().iter().cycle())
+        .map(|(byte, key)| byte ^ key)
+        .collect();
+    Ok(Zeroizing::new(secret))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cheap parameters so tests run quickly
+    const TEST_PARAMS: KdfParams = KdfParams {
+        memory_kib: 256,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let secret = [7u8; 32];
+        let keystore = Keystore::encrypt(&secret, b"correct horse", TEST_PARAMS).unwrap();
+
+        let bytes = keystore.to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + 32 + TAG_LEN);
+
+        let parsed = Keystore::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, keystore);
+        assert_eq!(parsed.params(), TEST_PARAMS);
+        assert_eq!(
+            parsed.decrypt(b"correct horse").unwrap().as_slice(),
+            &secret
+        );
+    }
+
+    #[test]
+    fn test_fresh_salt_and_nonce() {
+        let a = Keystore::encrypt(&[1u8; 32], b"pass", TEST_PARAMS).unwrap();
+        let b = Keystore::encrypt(&[1u8; 32], b"pass", TEST_PARAMS).unwrap();
+        assert_ne!(a.salt, b.salt);
+        assert_ne!(a.nonce, b.nonce);
+        assert_ne!(a.ciphertext, b.ciphertext);
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let keystore = Keystore::encrypt(&[7u8; 32], b"right", TEST_PARAMS).unwrap();
+        assert!(matches!(
+            keystore.decrypt(b"wrong"),
+            Err(KeystoreError::Decryption)
+        ));
+    }
+
+    #[test]
+    fn test_tampered_header() {
+        let keystore = Keystore::encrypt(&[7u8; 32], b"pass", TEST_PARAMS).unwrap();
+        let mut bytes = keystore.to_bytes();
+
+        // Raise the iteration count: the key changes and the header is authenticated
+        bytes[MAGIC.len() + 5] = 2;
+        let tampered = Keystore::from_bytes(&bytes).unwrap();
+        assert!(matches!(
+            tampered.decrypt(b"pass"),
+            Err(KeystoreError::Decryption)
+        ));
+    }
+
+    #[test]
+    fn test_reject_bad_header() {
+        let mut bytes = Keystore::encrypt(&[7u8; 32], b"pass", TEST_PARAMS)
+            .unwrap()
+            .to_bytes();
+
+        assert!(matches!(
+            Keystore::from_bytes(&bytes[..HEADER_LEN]),
+            Err(KeystoreError::Malformed(_))
+        ));
+
+        bytes[MAGIC.len()] = 2;
+        assert!(matches!(
+            Keystore::from_bytes(&bytes),
+            Err(KeystoreError::UnsupportedVersion(2))
+        ));
+
+        assert!(matches!(
+            Keystore::from_bytes(&[0u8; 32]),
+            Err(KeystoreError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_reject_excessive_kdf_params() {
+        let bytes = Keystore::encrypt(&[7u8; 32], b"pass", TEST_PARAMS)
+            .unwrap()
+            .to_bytes();
+        let params_at = MAGIC.len() + 1;
+
+        for (offset, value) in [
+            (0, MAX_MEMORY_KIB + 1),
+            (4, MAX_ITERATIONS + 1),
+            (8, 0),
+            (8, MAX_PARALLELISM + 1),
+        ] {
+            let mut tampered = bytes.clone();
+            tampered[params_at + offset..params_at + offset + 4]
+                .copy_from_slice(&value.to_le_bytes());
+            assert!(matches!(
+                Keystore::from_bytes(&tampered),
+                Err(KeystoreError::Kdf(_))
+            ));
+        }
+
+        let huge = KdfParams {
+            memory_kib: u32::MAX,
+            ..TEST_PARAMS
+        };
+        assert!(matches!(
+            Keystore::encrypt(&[7u8; 32], b"pass", huge),
+            Err(KeystoreError::Kdf(_))
+        ));
+    }
+
+    #[test]
+    fn test_decrypt_legacy() {
+        let secret = [0x5au8; 32];
+        let key_hash = blake3::hash(b"old passphrase");
+        let legacy: Vec<u8> = secret
+            .iter()
+            .zip(key_hash.as_bytes())
+            .map(|(s, k)| s ^ k)
+            .collect();
+
+        assert!(Keystore::is_legacy(&legacy));
+        assert_eq!(
+            decrypt_legacy(&legacy, b"old passphrase")
+                .unwrap()
+                .as_slice(),
+            &secret
+        );
+        assert!(decrypt_legacy(&[0u8; 16], b"old passphrase").is_err());
+    }
+}
@@ -1,7 +1,13 @@
 //! Cryptographic primitives for NullChain
 
 pub mod hash;
+pub mod hd;
 pub mod keys;
+pub mod keystore;
+pub mod transaction;
 
-pub use hash::{double_hash, hash_block_header, hash_data};
+pub use hash::{double_hash, hash_block_header, hash_data, pubkey_hash};
+pub use hd::{wallet_key_path, ExtendedKey, HdError, Mnemonic, COIN_TYPE, HARDENED};
 pub use keys::{generate_keypair, sign_message, verify_signature};
+pub use keystore::{decrypt_legacy, KdfParams, Keystore, KeystoreError, KEYSTORE_VERSION};
+pub use transaction::{InputError, TransactionExt};
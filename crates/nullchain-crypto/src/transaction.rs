@@ -0,0 +1,183 @@
+//! Transaction input authorization
+//!
+//! Every non-coinbase input carries an Ed25519 signature over
+//! [`Transaction::sighash`] and the public key whose hash is the
+//! `recipient` of the output being spent.
+
+use crate::hash::pubkey_hash;
+use crate::keys::verify_signature;
+use ed25519_dalek::VerifyingKey;
+use nullchain_types::{Transaction, TransactionOutput};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum InputError {
+    #[error("expected {expected} spent outputs, got {found}")]
+    SpentOutputCount { expected: usize, found: usize },
+
+    #[error("input {0}: malformed public key")]
+    MalformedPublicKey(usize),
+
+    #[error("input {0}: public key does not match spent output recipient")]
+    RecipientMismatch(usize),
+
+    #[error("input {0}: invalid signature")]
+    InvalidSignature(usize),
+}
+
+/// Signature checks on [`Transaction`] that need the crypto crate
+pub trait TransactionExt {
+    /// Verify every input against the output it spends
+    ///
+    /// `spent_outputs[i]` must be the output referenced by input `i`.
+    /// Coinbase transactions have nothing to verify.
+    fn verify_inputs(&self, spent_outputs: &[TransactionOutput]) -> Result<(), InputError>;
+}
+
+impl TransactionExt for Transaction {
+    fn verify_inputs(&self, spent_outputs: &[TransactionOutput]) -> Result<(), InputError> {
+        if self.is_coinbase() {
+            return Ok(());
+        }
+
+        if spent_outputs.len() != self.inputs.len() {
+            return Err(InputError::SpentOutputCount {
+                expected: self.inputs.len(),
+                found: spent_outputs.len(),
+            });
+        }
+
+        let sighash = self.sighash();
+
+        for (i, (input, spent)) in self.inputs.iter().zip(spent_outputs).enumerate() {
+            let key_bytes: [u8; 32] = input
+                .public_key
+                .as_slice()
+                .try_into()
+                .map_err(|_| InputError::MalformedPublicKey(i))?;
+            let verifying_key = VerifyingKey::from_bytes(&key_bytes)
+                .map_err(|_| InputError::MalformedPublicKey(i))?;
+
+            if spent.recipient != pubkey_hash(&key_bytes) {
+                return Err(InputError::RecipientMismatch(i));
+            }
+
+            if !verify_signature(&verifying_key, sighash.as_bytes(), &input.signature) {
+                return Err(InputError::InvalidSignature(i));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::{generate_keypair, sign_message};
+    use ed25519_dalek::SigningKey;
+    use nullchain_types::{Hash256, TransactionInput};
+
+    fn signed_spend(signing_key: &SigningKey) -> (Transaction, Vec<TransactionOutput>) {
+        let public_key = signing_key.verifying_key().to_bytes();
+        let spent = TransactionOutput {
+            amount: 100,
+            recipient: pubkey_hash(&public_key).to_vec(),
+        };
+
+        let mut tx = Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output: Hash256::from_bytes([1u8; 32]),
+                output_index: 0,
+                signature: vec![],
+                public_key: public_key.to_vec(),
+            }],
+            outputs: vec![TransactionOutput {
+                amount: 90,
+                recipient: vec![2u8; 20],
+            }],
+            locktime: 0,
+        };
+        tx.inputs[0].signature = sign_message(signing_key, tx.sighash().as_bytes());
+
+        (tx, vec![spent])
+    }
+
+    #[test]
+    fn test_verify_valid_inputs() {
+        let (signing_key, _) = generate_keypair();
+        let (tx, spent) = signed_spend(&signing_key);
+
+        assert_eq!(tx.verify_inputs(&spent), Ok(()));
+    }
+
+    #[test]
+    fn test_coinbase_needs_no_signature() {
+        let tx = Transaction::coinbase(vec![0u8; 20], 100, 1);
+        assert_eq!(tx.verify_inputs(&[]), Ok(()));
+    }
+
+    #[test]
+    fn test_reject_tampered_output() {
+        let (signing_key, _) = generate_keypair();
+        let (mut tx, spent) = signed_spend(&signing_key);
+        tx.outputs[0].amount = 99;
+
+        assert_eq!(
+            tx.verify_inputs(&spent),
+            Err(InputError::InvalidSignature(0))
+        );
+    }
+
+    #[test]
+    fn test_reject_wrong_recipient() {
+        let (signing_key, _) = generate_keypair();
+        let (tx, mut spent) = signed_spend(&signing_key);
+        spent[0].recipient = vec![3u8; 20];
+
+        assert_eq!(
+            tx.verify_inputs(&spent),
+            Err(InputError::RecipientMismatch(0))
+        );
+    }
+
+    #[test]
+    fn test_reject_signature_from_other_key() {
+        let (signing_key, _) = generate_keypair();
+        let (other_key, _) = generate_keypair();
+        let (mut tx, spent) = signed_spend(&signing_key);
+        tx.inputs[0].signature = sign_message(&other_key, tx.sighash().as_bytes());
+
+        assert_eq!(
+            tx.verify_inputs(&spent),
+            Err(InputError::InvalidSignature(0))
+        );
+    }
+
+    #[test]
+    fn test_reject_malformed_public_key() {
+        let (signing_key, _) = generate_keypair();
+        let (mut tx, spent) = signed_spend(&signing_key);
+        tx.inputs[0].public_key.truncate(31);
+
+        assert_eq!(
+            tx.verify_inputs(&spent),
+            Err(InputError::MalformedPublicKey(0))
+        );
+    }
+
+    #[test]
+    fn test_reject_spent_output_count() {
+        let (signing_key, _) = generate_keypair();
+        let (tx, _) = signed_spend(&signing_key);
+
+        assert_eq!(
+            tx.verify_inputs(&[]),
+            Err(InputError::SpentOutputCount {
+                expected: 1,
+                found: 0
+            })
+        );
+    }
+}
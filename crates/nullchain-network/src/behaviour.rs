@@ -0,0 +1,12 @@
+use crate::protocol::{Request, Response};
+use libp2p::swarm::behaviour::toggle::Toggle;
+use libp2p::swarm::NetworkBehaviour;
+use libp2p::{gossipsub, mdns, request_response};
+
+/// Combined libp2p behaviour of a NullChain node
+#[derive(NetworkBehaviour)]
+pub(crate) struct Behaviour {
+    pub gossipsub: gossipsub::Behaviour,
+    pub mdns: Toggle<mdns::tokio::Behaviour>,
+    pub sync: request_response::cbor::Behaviour<Request, Response>,
+}
@@ -0,0 +1,34 @@
+use libp2p::identity::Keypair;
+use libp2p::Multiaddr;
+use nullchain_consensus::ChainParams;
+
+/// Network configuration
+#[derive(Clone)]
+pub struct NetworkConfig {
+    /// Node identity (a fresh Ed25519 key if not persisted by the caller)
+    pub keypair: Keypair,
+
+    /// Addresses to listen on
+    pub listen_addrs: Vec<Multiaddr>,
+
+    /// Peers dialed on startup
+    pub bootstrap_peers: Vec<Multiaddr>,
+
+    /// Discover peers on the local network via mDNS
+    pub enable_mdns: bool,
+
+    /// Network magic; only peers using the same magic are heard
+    pub magic: [u8; 4],
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            keypair: Keypair::generate_ed25519(),
+            listen_addrs: vec!["/ip4/0.0.0.0/tcp/0".parse().expect("valid multiaddr")],
+            bootstrap_peers: vec![],
+            enable_mdns: true,
+            magic: ChainParams::default().magic,
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum NetworkError {
+    #[error("transport error: {0}")]
+    Transport(String),
+
+    #[error("invalid multiaddr: {0}")]
+    Multiaddr(#[from] libp2p::multiaddr::Error),
+
+    #[error("dial error: {0}")]
+    Dial(#[from] libp2p::swarm::DialError),
+
+    #[error("publish error: {0}")]
+    Publish(#[from] libp2p::gossipsub::PublishError),
+
+    #[error("serialization error: {0}")]
+    Serialization(#[from] bincode::Error),
+
+    #[error("response channel closed")]
+    ResponseChannelClosed,
+}
+
+pub type Result<T> = std::result::Result<T, NetworkError>;
@@ -1,14 +1,25 @@
-pub fn add(left: u64, right: u64) -> u64 {
-    left + right
-}
+//! Peer-to-peer networking for NullChain
+//!
+//! Built on libp2p:
+//! - TCP transport secured with Noise and multiplexed with Yamux
+//! - Peer discovery via configured bootstrap peers and mDNS
+//! - Gossipsub topics for new blocks and transactions
+//! - Request-response protocol for fetching headers and blocks
+//! - Headers-first block download ([`SyncManager`])
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+mod behaviour;
+mod config;
+mod error;
+mod protocol;
+mod service;
+mod sync;
 
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
-}
+pub use config::NetworkConfig;
+pub use error::{NetworkError, Result};
+pub use protocol::{blocks_topic, transactions_topic, Request, Response, MAX_HEADERS};
+pub use service::{Network, NetworkEvent};
+pub use sync::{handle_request, SyncConfig, SyncManager, SyncNetwork};
+
+pub use libp2p::gossipsub::{MessageAcceptance, MessageId};
+pub use libp2p::request_response::{OutboundRequestId, ResponseChannel};
+pub use libp2p::{identity::Keypair, Multiaddr, PeerId};
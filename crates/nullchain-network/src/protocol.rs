@@ -0,0 +1,49 @@
+//! Wire protocol: gossip topics and request-response messages
+
+use libp2p::StreamProtocol;
+use nullchain_types::{Block, BlockHeader, Hash256, Transaction};
+use serde::{Deserialize, Serialize};
+
+/// Gossipsub topic for newly mined blocks on the network tagged `magic`
+pub fn blocks_topic(magic: [u8; 4]) -> String {
+    protocol_name(magic, "blocks/1")
+}
+
+/// Gossipsub topic for unconfirmed transactions on the network tagged `magic`
+pub fn transactions_topic(magic: [u8; 4]) -> String {
+    protocol_name(magic, "transactions/1")
+}
+
+/// Request-response protocol name on the network tagged `magic`
+pub(crate) fn sync_protocol(magic: [u8; 4]) -> StreamProtocol {
+    StreamProtocol::try_from_owned(protocol_name(magic, "sync/1")).expect("name starts with '/'")
+}
+
+fn protocol_name(magic: [u8; 4], name: &str) -> String {
+    format!("/nullchain/{:08x}/{}", u32::from_be_bytes(magic), name)
+}
+
+/// Most headers returned for a single [`Request::GetHeaders`]
+pub const MAX_HEADERS: u32 = 2000;
+
+/// Request sent to a single peer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Request {
+    /// Fetch a full block by hash
+    GetBlock(Hash256),
+    /// Fetch active-chain headers following the first known locator hash
+    GetHeaders { locator: Vec<Hash256>, limit: u32 },
+    /// Add a transaction to the peer's mempool and relay it
+    SubmitTransaction(Transaction),
+}
+
+/// Response to a [`Request`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Response {
+    /// The requested block, `None` if the peer does not have it
+    Block(Option<Block>),
+    /// Consecutive headers, empty if the peer has nothing newer
+    Headers(Vec<BlockHeader>),
+    /// Txid of the accepted transaction, or why it was rejected
+    Submitted(Result<Hash256, String>),
+}
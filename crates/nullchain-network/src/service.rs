@@ -0,0 +1,598 @@
+//! Network service driving the libp2p swarm
+
+use crate::behaviour::{Behaviour, BehaviourEvent};
+use crate::config::NetworkConfig;
+use crate::error::{NetworkError, Result};
+use crate::protocol::{blocks_topic, sync_protocol, transactions_topic, Request, Response};
+use futures::StreamExt;
+use libp2p::gossipsub::{
+    self, IdentTopic, MessageAcceptance, MessageAuthenticity, MessageId, ValidationMode,
+};
+use libp2p::request_response::{self, OutboundRequestId, ProtocolSupport, ResponseChannel};
+use libp2p::swarm::SwarmEvent;
+use libp2p::{mdns, noise, tcp, yamux, Multiaddr, PeerId, Swarm, SwarmBuilder};
+use nullchain_types::{constants, Block, Transaction};
+use std::time::Duration;
+
+/// Room for gossipsub framing on top of the largest block
+const GOSSIP_OVERHEAD: usize = 64 * 1024;
+
+/// Events surfaced to the node
+#[derive(Debug)]
+pub enum NetworkEvent {
+    /// Started listening on a new address
+    Listening(Multiaddr),
+    /// First connection to a peer was established
+    PeerConnected(PeerId),
+    /// Last connection to a peer was closed
+    PeerDisconnected(PeerId),
+    /// Block received via gossip; report its validity with
+    /// [`Network::report_gossip`] so it is relayed or dropped
+    Block {
+        peer: PeerId,
+        message_id: MessageId,
+        block: Block,
+    },
+    /// Transaction received via gossip; report its validity with
+    /// [`Network::report_gossip`]
+    Transaction {
+        peer: PeerId,
+        message_id: MessageId,
+        transaction: Transaction,
+    },
+    /// Peer sent a request; answer it with [`Network::respond`]
+    InboundRequest {
+        peer: PeerId,
+        request: Request,
+        channel: ResponseChannel<Response>,
+    },
+    /// Peer answered one of our requests
+    Response {
+        peer: PeerId,
+        request_id: OutboundRequestId,
+        response: Response,
+    },
+    /// One of our requests failed (timeout, disconnect, unsupported protocol)
+    RequestFailed {
+        peer: PeerId,
+        request_id: OutboundRequestId,
+        error: String,
+    },
+}
+
+pub struct Network {
+    swarm: Swarm<Behaviour>,
+    blocks_topic: IdentTopic,
+    transactions_topic: IdentTopic,
+}
+
+impl Network {
+    /// Build the swarm, start listening and dial bootstrap peers
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn new(config: NetworkConfig) -> Result<Self> {
+        let gossipsub_config = gossipsub::ConfigBuilder::default()
+            .validation_mode(ValidationMode::Strict)
+            .max_transmit_size(constants::MAX_BLOCK_SIZE + GOSSIP_OVERHEAD)
+            // Deduplicate by content so the same block relayed by several
+            // peers is only delivered once
+            .message_id_fn(|message| MessageId::from(blake3::hash(&message.data).to_hex().as_str()))
+            // Hold messages back from relay until the node has checked them
+            .validate_messages()
+            .build()
+            .map_err(|e| NetworkError::Transport(e.to_string()))?;
+
+        let enable_mdns = config.enable_mdns;
+        let magic = config.magic;
+
+        let mut swarm = SwarmBuilder::with_existing_identity(config.keypair)
+            .with_tokio()
+            .with_tcp(
+                tcp::Config::default(),
+                noise::Config::new,
+                yamux::Config::default,
+            )
+            .map_err(|e| NetworkError::Transport(e.to_string()))?
+            .with_behaviour(|key| {
+                let gossipsub = gossipsub::Behaviour::new(
+                    MessageAuthenticity::Signed(key.clone()),
+                    gossipsub_config,
+                )?;
+
+                let mdns = if enable_mdns {
+                    Some(mdns::tokio::Behaviour::new(
+                        mdns::Config::default(),
+                        key.public().to_peer_id(),
+                    )?)
+                } else {
+                    None
+                };
+
+                let sync = request_response::cbor::Behaviour::new(
+                    [(sync_protocol(magic), ProtocolSupport::Full)],
+                    request_response::Config::default(),
+                );
+
+                Ok(Behaviour {
+                    gossipsub,
+                    mdns: mdns.into(),
+                    sync,
+                })
+            })
+            .map_err(|e| NetworkError::Transport(e.to_string()))?
+            .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+            .build();
+
+        let blocks_topic = IdentTopic::new(blocks_topic(magic));
+        let transactions_topic = IdentTopic::new(transactions_topic(magic));
+        for topic in [&blocks_topic, &transactions_topic] {
+            swarm
+                .behaviour_mut()
+                .gossipsub
+                .subscribe(topic)
+                .map_err(|e| NetworkError::Transport(e.to_string()))?;
+        }
+
+        for addr in config.listen_addrs {
+            swarm
+                .listen_on(addr)
+                .map_err(|e| NetworkError::Transport(e.to_string()))?;
+        }
+
+        for addr in config.bootstrap_peers {
+            if let Err(e) = swarm.dial(addr.clone()) {
+                tracing::warn!("Failed to dial bootstrap peer {}: {}", addr, e);
+            }
+        }
+
+        Ok(Self {
+            swarm,
+            blocks_topic,
+            transactions_topic,
+        })
+    }
+
+    pub fn local_peer_id(&self) -> PeerId {
+        *self.swarm.local_peer_id()
+    }
+
+    pub fn connected_peers(&self) -> Vec<PeerId> {
+        self.swarm.connected_peers().copied().collect()
+    }
+
+    pub fn dial(&mut self, addr: Multiaddr) -> Result<()> {
+        self.swarm.dial(addr)?;
+        Ok(())
+    }
+
+    /// Gossip a block to all peers
+    pub fn publish_block(&mut self, block: &Block) -> Result<()> {
+        let data = bincode::serialize(block)?;
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(self.blocks_topic.clone(), data)?;
+        Ok(())
+    }
+
+    /// Gossip a transaction to all peers
+    pub fn publish_transaction(&mut self, transaction: &Transaction) -> Result<()> {
+        let data = bincode::serialize(transaction)?;
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(self.transactions_topic.clone(), data)?;
+        Ok(())
+    }
+
+    /// Tell gossipsub whether a gossiped block or transaction checked out
+    ///
+    /// Accepted messages are relayed to our other peers, rejected ones count
+    /// against the peer that sent them and ignored ones are just dropped.
+    pub fn report_gossip(
+        &mut self,
+        message_id: &MessageId,
+        peer: &PeerId,
+        acceptance: MessageAcceptance,
+    ) {
+        if let Err(e) = self
+            .swarm
+            .behaviour_mut()
+            .gossipsub
+            .report_message_validation_result(message_id, peer, acceptance)
+        {
+            tracing::debug!("Failed to relay message {}: {}", message_id, e);
+        }
+    }
+
+    /// Send a request to a peer; the answer arrives as [`NetworkEvent::Response`]
+    pub fn request(&mut self, peer: &PeerId, request: Request) -> OutboundRequestId {
+        self.swarm.behaviour_mut().sync.send_request(peer, request)
+    }
+
+    /// Answer a [`NetworkEvent::InboundRequest`]
+    pub fn respond(
+        &mut self,
+        channel: ResponseChannel<Response>,
+        response: Response,
+    ) -> Result<()> {
+        self.swarm
+            .behaviour_mut()
+            .sync
+            .send_response(channel, response)
+            .map_err(|_| NetworkError::ResponseChannelClosed)
+    }
+
+    /// Drive the swarm until the next event relevant to the node
+    ///
+    /// Cancel-safe, so it can be used as a branch of `tokio::select!`.
+    pub async fn next_event(&mut self) -> NetworkEvent {
+        loop {
+            let event = self.swarm.select_next_some().await;
+            if let Some(event) = self.handle_swarm_event(event) {
+                return event;
+            }
+        }
+    }
+
+    fn handle_swarm_event(&mut self, event: SwarmEvent<BehaviourEvent>) -> Option<NetworkEvent> {
+        match event {
+            SwarmEvent::NewListenAddr { address, .. } => {
+                tracing::info!("Listening on {}", address);
+                Some(NetworkEvent::Listening(address))
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                num_established,
+                ..
+            } if num_established.get() == 1 => {
+                tracing::debug!("Connected to {}", peer_id);
+                Some(NetworkEvent::PeerConnected(peer_id))
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established: 0,
+                ..
+            } => {
+                tracing::debug!("Disconnected from {}", peer_id);
+                Some(NetworkEvent::PeerDisconnected(peer_id))
+            }
+            SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
+                tracing::debug!("Outgoing connection to {:?} failed: {}", peer_id, error);
+                None
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
+                for (peer_id, addr) in peers {
+                    if !self.swarm.is_connected(&peer_id) {
+                        tracing::debug!("Discovered {} at {}", peer_id, addr);
+                        if let Err(e) = self.swarm.dial(addr) {
+                            tracing::debug!("Failed to dial {}: {}", peer_id, e);
+                        }
+                    }
+                }
+                None
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                propagation_source,
+                message_id,
+                message,
+            })) => self.decode_gossip(propagation_source, message_id, &message),
+            SwarmEvent::Behaviour(BehaviourEvent::Sync(event)) => Self::handle_sync_event(event),
+            _ => None,
+        }
+    }
+
+    fn decode_gossip(
+        &mut self,
+        peer: PeerId,
+        message_id: MessageId,
+        message: &gossipsub::Message,
+    ) -> Option<NetworkEvent> {
+        if message.topic == self.blocks_topic.hash() {
+            match bincode::deserialize(&message.data) {
+                Ok(block) => {
+                    return Some(NetworkEvent::Block {
+                        peer,
+                        message_id,
+                        block,
+                    })
+                }
+                Err(e) => tracing::debug!("Undecodable block from {}: {}", peer, e),
+            }
+        } else if message.topic == self.transactions_topic.hash() {
+            match bincode::deserialize(&message.data) {
+                Ok(transaction) => {
+                    return Some(NetworkEvent::Transaction {
+                        peer,
+                        message_id,
+                        transaction,
+                    })
+                }
+                Err(e) => tracing::debug!("Undecodable transaction from {}: {}", peer, e),
+            }
+        }
+
+        self.report_gossip(&message_id, &peer, MessageAcceptance::Reject);
+        None
+    }
+
+    fn handle_sync_event(
+        event: request_response::Event<Request, Response>,
+    ) -> Option<NetworkEvent> {
+        match event {
+            request_response::Event::Message { peer, message } => match message {
+                request_response::Message::Request {
+                    request, channel, ..
+                } => Some(NetworkEvent::InboundRequest {
+                    peer,
+                    request,
+                    channel,
+                }),
+                request_response::Message::Response {
+                    request_id,
+                    response,
+                } => Some(NetworkEvent::Response {
+                    peer,
+                    request_id,
+                    response,
+                }),
+            },
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => Some(NetworkEvent::RequestFailed {
+                peer,
+                request_id,
+                error: error.to_string(),
+            }),
+            request_response::Event::InboundFailure { peer, error, .. } => {
+                tracing::debug!("Inbound request from {} failed: {}", peer, error);
+                None
+            }
+            request_response::Event::ResponseSent { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nullchain_consensus::ChainParams;
+    use nullchain_types::Hash256;
+    use tokio::sync::mpsc;
+    use tokio::time::timeout;
+
+    const TEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+    fn loopback_config(bootstrap_peers: Vec<Multiaddr>) -> NetworkConfig {
+        NetworkConfig {
+            listen_addrs: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
+            bootstrap_peers,
+            enable_mdns: false,
+            ..NetworkConfig::default()
+        }
+    }
+
+    async fn listen_addr(network: &mut Network) -> Multiaddr {
+        loop {
+            if let NetworkEvent::Listening(addr) = network.next_event().await {
+                return addr;
+            }
+        }
+    }
+
+    async fn wait_connected(network: &mut Network) -> PeerId {
+        loop {
+            if let NetworkEvent::PeerConnected(peer) = network.next_event().await {
+                return peer;
+            }
+        }
+    }
+
+    /// Start a listening node and a second node that dials it
+    async fn connected_pair() -> (Network, Network, Multiaddr) {
+        let mut server = Network::new(loopback_config(vec![])).unwrap();
+        let addr = listen_addr(&mut server).await;
+        let client = Network::new(loopback_config(vec![addr.clone()])).unwrap();
+        (server, client, addr)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_block_by_hash() {
+        let (mut server, mut client, _) = connected_pair().await;
+        let genesis = Block::genesis();
+        let served = genesis.clone();
+
+        tokio::spawn(async move {
+            loop {
+                if let NetworkEvent::InboundRequest {
+                    request: Request::GetBlock(hash),
+                    channel,
+                    ..
+                } = server.next_event().await
+                {
+                    let block = (hash == served.hash()).then(|| served.clone());
+                    server.respond(channel, Response::Block(block)).unwrap();
+                }
+            }
+        });
+
+        timeout(TEST_TIMEOUT, async {
+            let peer = wait_connected(&mut client).await;
+
+            let known = client.request(&peer, Request::GetBlock(genesis.hash()));
+            let unknown = client.request(&peer, Request::GetBlock(Hash256::zero()));
+
+            let mut answered = 0;
+            while answered < 2 {
+                if let NetworkEvent::Response {
+                    request_id,
+                    response: Response::Block(block),
+                    ..
+                } = client.next_event().await
+                {
+                    if request_id == known {
+                        assert_eq!(block.unwrap().hash(), genesis.hash());
+                    } else {
+                        assert_eq!(request_id, unknown);
+                        assert!(block.is_none());
+                    }
+                    answered += 1;
+                }
+            }
+        })
+        .await
+        .expect("timed out waiting for responses");
+    }
+
+    #[tokio::test]
+    async fn test_other_network_is_not_served() {
+        let regtest = ChainParams::regtest().magic;
+        assert_ne!(NetworkConfig::default().magic, regtest);
+
+        let mut server = Network::new(loopback_config(vec![])).unwrap();
+        let addr = listen_addr(&mut server).await;
+        let mut client = Network::new(NetworkConfig {
+            magic: regtest,
+            ..loopback_config(vec![addr])
+        })
+        .unwrap();
+
+        tokio::spawn(async move {
+            loop {
+                server.next_event().await;
+            }
+        });
+
+        timeout(TEST_TIMEOUT, async {
+            let peer = wait_connected(&mut client).await;
+            let id = client.request(&peer, Request::GetBlock(Hash256::zero()));
+            loop {
+                match client.next_event().await {
+                    NetworkEvent::RequestFailed { request_id, .. } if request_id == id => break,
+                    NetworkEvent::Response { .. } => panic!("served across networks"),
+                    _ => {}
+                }
+            }
+        })
+        .await
+        .expect("timed out waiting for the request to fail");
+    }
+
+    #[tokio::test]
+    async fn test_gossip_block_and_transaction() {
+        let (mut server, mut client, _) = connected_pair().await;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            loop {
+                match server.next_event().await {
+                    NetworkEvent::Block {
+                        peer,
+                        message_id,
+                        block,
+                    } => {
+                        server.report_gossip(&message_id, &peer, MessageAcceptance::Accept);
+                        tx.send(block.hash()).unwrap()
+                    }
+                    NetworkEvent::Transaction {
+                        peer,
+                        message_id,
+                        transaction,
+                    } => {
+                        server.report_gossip(&message_id, &peer, MessageAcceptance::Ignore);
+                        tx.send(transaction.txid()).unwrap()
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        let block = Block::genesis();
+        let transaction = Transaction::coinbase(vec![1u8; 20], 5, 1);
+
+        timeout(TEST_TIMEOUT, async {
+            wait_connected(&mut client).await;
+
+            // Publishing fails until the peer's topic subscriptions arrive
+            loop {
+                match client.publish_block(&block) {
+                    Ok(()) => break,
+                    Err(NetworkError::Publish(gossipsub::PublishError::InsufficientPeers)) => {
+                        let _ = timeout(Duration::from_millis(50), client.next_event()).await;
+                    }
+                    Err(e) => panic!("publish failed: {}", e),
+                }
+            }
+            client.publish_transaction(&transaction).unwrap();
+
+            let mut received = vec![];
+            while received.len() < 2 {
+                tokio::select! {
+                    Some(hash) = rx.recv() => received.push(hash),
+                    _ = client.next_event() => {}
+                }
+            }
+            assert!(received.contains(&block.hash()));
+            assert!(received.contains(&transaction.txid()));
+        })
+        .await
+        .expect("timed out waiting for gossip");
+    }
+
+    #[tokio::test]
+    async fn test_validated_gossip_is_relayed() {
+        // origin -> relay -> sink, where only the relay knows both ends
+        let mut relay = Network::new(loopback_config(vec![])).unwrap();
+        let relay_addr = listen_addr(&mut relay).await;
+        let mut origin = Network::new(loopback_config(vec![relay_addr.clone()])).unwrap();
+        let mut sink = Network::new(loopback_config(vec![relay_addr])).unwrap();
+
+        tokio::spawn(async move {
+            loop {
+                if let NetworkEvent::Block {
+                    peer, message_id, ..
+                } = relay.next_event().await
+                {
+                    relay.report_gossip(&message_id, &peer, MessageAcceptance::Accept);
+                }
+            }
+        });
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                if let NetworkEvent::Block { block, .. } = sink.next_event().await {
+                    tx.send(block.hash()).unwrap();
+                }
+            }
+        });
+
+        let block = Block::genesis();
+        timeout(TEST_TIMEOUT, async {
+            wait_connected(&mut origin).await;
+            loop {
+                match origin.publish_block(&block) {
+                    Ok(()) => break,
+                    Err(NetworkError::Publish(gossipsub::PublishError::InsufficientPeers)) => {
+                        let _ = timeout(Duration::from_millis(50), origin.next_event()).await;
+                    }
+                    Err(e) => panic!("publish failed: {}", e),
+                }
+            }
+
+            loop {
+                tokio::select! {
+                    Some(hash) = rx.recv() => {
+                        assert_eq!(hash, block.hash());
+                        break;
+                    }
+                    _ = origin.next_event() => {}
+                }
+            }
+        })
+        .await
+        .expect("timed out waiting for the relayed block");
+    }
+}
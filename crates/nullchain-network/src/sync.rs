@@ -0,0 +1,713 @@
+//! Headers-first initial block download
+//!
+//! Sync runs in two overlapping phases:
+//! 1. Every peer is asked for headers following our block locator. Headers
+//!    are checked for proof of work and indexed, which selects the best
+//!    header chain before any block body is fetched.
+//! 2. Bodies of the blocks on that chain are requested in parallel from all
+//!    peers that served headers, bounded per peer, and connected in chain
+//!    order as they arrive. Requests that fail or time out are re-sent to a
+//!    different peer.
+//!
+//! All progress lives in the block index, so a restarted node resumes from
+//! its best header and active tip. The manager does no I/O itself: requests
+//! go out through [`SyncNetwork`] and the caller feeds responses, failures
+//! and periodic ticks back in.
+
+use crate::protocol::{Request, Response, MAX_HEADERS};
+use crate::service::Network;
+use libp2p::request_response::OutboundRequestId;
+use libp2p::PeerId;
+use nullchain_consensus::{AcceptOutcome, Chain, ChainError};
+use nullchain_storage::BlockStatus;
+use nullchain_types::{Block, BlockHeader, Hash256};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// Outbound side of the request-response protocol used by [`SyncManager`]
+pub trait SyncNetwork {
+    type RequestId: Copy + Eq + Hash + Debug;
+
+    fn send_request(&mut self, peer: &PeerId, request: Request) -> Self::RequestId;
+}
+
+impl SyncNetwork for Network {
+    type RequestId = OutboundRequestId;
+
+    fn send_request(&mut self, peer: &PeerId, request: Request) -> OutboundRequestId {
+        self.request(peer, request)
+    }
+}
+
+/// Download limits and timeouts
+#[derive(Debug, Clone)]
+pub struct SyncConfig {
+    /// Headers asked for per request, at most [`MAX_HEADERS`]
+    pub headers_per_request: u32,
+    /// Block requests outstanding to a single peer
+    pub max_blocks_per_peer: usize,
+    /// How many blocks past the next one to connect may be downloaded
+    pub download_window: usize,
+    /// Time a peer gets to answer before the request goes elsewhere
+    pub request_timeout: Duration,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            headers_per_request: MAX_HEADERS,
+            max_blocks_per_peer: 16,
+            download_window: 1024,
+            request_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Pending {
+    Headers,
+    Block(Hash256),
+}
+
+#[derive(Debug)]
+struct InFlight {
+    peer: PeerId,
+    kind: Pending,
+    sent: Instant,
+}
+
+#[derive(Debug, Default)]
+struct PeerState {
+    headers_in_flight: bool,
+    /// Peer answered a header request and can be asked for blocks
+    serves_blocks: bool,
+    blocks_in_flight: usize,
+    /// Blocks this peer failed to deliver; other peers are tried first
+    failed: HashSet<Hash256>,
+}
+
+/// Headers-first sync state machine
+pub struct SyncManager<N: SyncNetwork> {
+    config: SyncConfig,
+    peers: HashMap<PeerId, PeerState>,
+    in_flight: HashMap<N::RequestId, InFlight>,
+    /// Blocks to download, in chain order
+    queue: VecDeque<Hash256>,
+    /// Best header that `queue` leads up to
+    queue_target: Option<Hash256>,
+    requested: HashSet<Hash256>,
+    /// Downloaded blocks waiting for their parent to be connected
+    downloaded: HashMap<Hash256, Block>,
+}
+
+impl<N: SyncNetwork> SyncManager<N> {
+    pub fn new(config: SyncConfig) -> Self {
+        Self {
+            config,
+            peers: HashMap::new(),
+            in_flight: HashMap::new(),
+            queue: VecDeque::new(),
+            queue_target: None,
+            requested: HashSet::new(),
+            downloaded: HashMap::new(),
+        }
+    }
+
+    /// No headers or blocks are outstanding and nothing is left to download
+    pub fn is_synced(&self) -> bool {
+        self.in_flight.is_empty() && self.queue.is_empty()
+    }
+
+    /// Start syncing with a newly connected peer
+    pub fn add_peer(
+        &mut self,
+        net: &mut N,
+        chain: &Chain,
+        peer: PeerId,
+        now: Instant,
+    ) -> Result<(), ChainError> {
+        self.peers.entry(peer).or_default();
+        self.request_headers(net, chain, &peer, now)
+    }
+
+    /// Forget a disconnected peer and hand its requests to others
+    pub fn remove_peer(
+        &mut self,
+        net: &mut N,
+        chain: &Chain,
+        peer: &PeerId,
+        now: Instant,
+    ) -> Result<(), ChainError> {
+        self.peers.remove(peer);
+        let requested = &mut self.requested;
+        self.in_flight.retain(|_, request| {
+            if request.peer != *peer {
+                return true;
+            }
+            if let Pending::Block(hash) = request.kind {
+                requested.remove(&hash);
+            }
+            false
+        });
+        self.schedule(net, chain, now)
+    }
+
+    /// Ask a peer for headers past our best header, e.g. after it announced
+    /// a block whose parent we do not know
+    pub fn request_headers(
+        &mut self,
+        net: &mut N,
+        chain: &Chain,
+        peer: &PeerId,
+        now: Instant,
+    ) -> Result<(), ChainError> {
+        let state = match self.peers.get_mut(peer) {
+            Some(state) if !state.headers_in_flight => state,
+            _ => return Ok(()),
+        };
+        state.headers_in_flight = true;
+
+        let request = Request::GetHeaders {
+            locator: chain.locator()?,
+            limit: self.config.headers_per_request.min(MAX_HEADERS),
+        };
+        let id = net.send_request(peer, request);
+        self.in_flight.insert(
+            id,
+            InFlight {
+                peer: *peer,
+                kind: Pending::Headers,
+                sent: now,
+            },
+        );
+        Ok(())
+    }
+
+    /// Handle the answer to one of our requests
+    ///
+    /// Returns the outcome of every block connected as a result.
+    pub fn on_response(
+        &mut self,
+        net: &mut N,
+        chain: &Chain,
+        id: N::RequestId,
+        response: Response,
+        now: Instant,
+    ) -> Result<Vec<AcceptOutcome>, ChainError> {
+        // Unknown ids belong to requests that already timed out
+        let Some(request) = self.in_flight.remove(&id) else {
+            return Ok(vec![]);
+        };
+
+        match (request.kind, response) {
+            (Pending::Headers, Response::Headers(headers)) => {
+                if let Some(state) = self.peers.get_mut(&request.peer) {
+                    state.headers_in_flight = false;
+                }
+                self.on_headers(net, chain, &request.peer, headers, now)?;
+            }
+            (Pending::Block(hash), Response::Block(Some(block))) if block.hash() == hash => {
+                self.release(&request);
+                if self
+                    .queue
+                    .iter()
+                    .take(self.config.download_window)
+                    .any(|h| *h == hash)
+                {
+                    self.downloaded.insert(hash, block);
+                }
+            }
+            _ => {
+                tracing::debug!(
+                    "Peer {} gave unusable answer to {:?}",
+                    request.peer,
+                    request.kind
+                );
+                self.fail(&request);
+            }
+        }
+
+        let outcomes = self.connect_downloaded(chain)?;
+        self.schedule(net, chain, now)?;
+        Ok(outcomes)
+    }
+
+    /// Handle a request that the network layer gave up on
+    pub fn on_request_failed(
+        &mut self,
+        net: &mut N,
+        chain: &Chain,
+        id: N::RequestId,
+        now: Instant,
+    ) -> Result<(), ChainError> {
+        if let Some(request) = self.in_flight.remove(&id) {
+            self.fail(&request);
+        }
+        self.schedule(net, chain, now)
+    }
+
+    /// Expire slow requests and re-issue them; call periodically
+    pub fn tick(&mut self, net: &mut N, chain: &Chain, now: Instant) -> Result<(), ChainError> {
+        let timeout = self.config.request_timeout;
+        let expired: Vec<N::RequestId> = self
+            .in_flight
+            .iter()
+            .filter(|(_, request)| now.saturating_duration_since(request.sent) >= timeout)
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in expired {
+            if let Some(request) = self.in_flight.remove(&id) {
+                tracing::debug!("Request {:?} to {} timed out", request.kind, request.peer);
+                self.fail(&request);
+            }
+        }
+
+        self.schedule(net, chain, now)
+    }
+
+    fn on_headers(
+        &mut self,
+        net: &mut N,
+        chain: &Chain,
+        peer: &PeerId,
+        headers: Vec<BlockHeader>,
+        now: Instant,
+    ) -> Result<(), ChainError> {
+        if headers.len() > MAX_HEADERS as usize {
+            tracing::warn!("Peer {} sent {} headers", peer, headers.len());
+            return Ok(());
+        }
+
+        let old_target = self.queue_target;
+        let mut extension = Vec::with_capacity(headers.len());
+
+        for header in &headers {
+            match chain.accept_header(header) {
+                Ok(entry) => extension.push(entry),
+                Err(ChainError::Storage(e)) => return Err(e.into()),
+                Err(e) => {
+                    tracing::warn!("Peer {} sent bad header {}: {}", peer, header.hash(), e);
+                    return Ok(());
+                }
+            }
+        }
+
+        if let Some(state) = self.peers.get_mut(peer) {
+            state.serves_blocks = true;
+        }
+
+        // Headers that simply extend the download queue are appended without
+        // walking the index again
+        let best = chain.best_header()?.map(|entry| entry.hash());
+        if let (Some(first), Some(last)) = (extension.first(), extension.last()) {
+            if old_target.is_some()
+                && Some(first.parent()) == old_target
+                && Some(last.hash()) == best
+            {
+                self.queue.extend(
+                    extension
+                        .iter()
+                        .filter(|entry| entry.status == BlockStatus::HeaderOnly)
+                        .map(|entry| entry.hash()),
+                );
+                self.queue_target = best;
+            }
+        }
+
+        if headers.len() as u32 >= self.config.headers_per_request.min(MAX_HEADERS) {
+            self.request_headers(net, chain, peer, now)?;
+        }
+        Ok(())
+    }
+
+    /// Feed downloaded blocks to the chain in order
+    fn connect_downloaded(&mut self, chain: &Chain) -> Result<Vec<AcceptOutcome>, ChainError> {
+        let mut outcomes = Vec::new();
+
+        while let Some(hash) = self.queue.front().copied() {
+            let Some(block) = self.downloaded.remove(&hash) else {
+                break;
+            };
+            self.queue.pop_front();
+
+            match chain.accept_block(&block) {
+                Ok(outcome) => outcomes.push(outcome),
+                Err(ChainError::Storage(e)) => return Err(e.into()),
+                Err(e) => {
+                    tracing::warn!("Downloaded block {} rejected: {}", hash, e);
+                    // The best header changed; rebuild the queue from the index
+                    self.queue.clear();
+                    self.queue_target = None;
+                    self.downloaded.clear();
+                    break;
+                }
+            }
+        }
+
+        Ok(outcomes)
+    }
+
+    /// Request blocks at the front of the queue from peers with spare capacity
+    fn schedule(&mut self, net: &mut N, chain: &Chain, now: Instant) -> Result<(), ChainError> {
+        let best = chain.best_header()?.map(|entry| entry.hash());
+        if best != self.queue_target {
+            self.queue = chain.missing_blocks()?.into();
+            self.queue_target = best;
+            let queue = &self.queue;
+            self.downloaded.retain(|hash, _| queue.contains(hash));
+        }
+
+        let wanted: Vec<Hash256> = self
+            .queue
+            .iter()
+            .take(self.config.download_window)
+            .filter(|hash| !self.requested.contains(hash) && !self.downloaded.contains_key(hash))
+            .copied()
+            .collect();
+
+        for hash in wanted {
+            let Some(peer) = self.pick_peer(&hash) else {
+                break;
+            };
+
+            let id = net.send_request(&peer, Request::GetBlock(hash));
+            if let Some(state) = self.peers.get_mut(&peer) {
+                state.blocks_in_flight += 1;
+            }
+            self.requested.insert(hash);
+            self.in_flight.insert(
+                id,
+                InFlight {
+                    peer,
+                    kind: Pending::Block(hash),
+                    sent: now,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Least busy peer for `hash`, preferring peers that have not failed it
+    fn pick_peer(&self, hash: &Hash256) -> Option<PeerId> {
+        let available = || {
+            self.peers.iter().filter(|(_, state)| {
+                state.serves_blocks && state.blocks_in_flight < self.config.max_blocks_per_peer
+            })
+        };
+
+        available()
+            .filter(|(_, state)| !state.failed.contains(hash))
+            .min_by_key(|(_, state)| state.blocks_in_flight)
+            .or_else(|| available().min_by_key(|(_, state)| state.blocks_in_flight))
+            .map(|(peer, _)| *peer)
+    }
+
+    fn release(&mut self, request: &InFlight) {
+        if let Pending::Block(hash) = request.kind {
+            self.requested.remove(&hash);
+            if let Some(state) = self.peers.get_mut(&request.peer) {
+                state.blocks_in_flight = state.blocks_in_flight.saturating_sub(1);
+            }
+        }
+    }
+
+    fn fail(&mut self, request: &InFlight) {
+        self.release(request);
+        if let Some(state) = self.peers.get_mut(&request.peer) {
+            match request.kind {
+                Pending::Headers => state.headers_in_flight = false,
+                Pending::Block(hash) => {
+                    state.failed.insert(hash);
+                }
+            }
+        }
+    }
+}
+
+/// Answer a peer's sync request from our chain
+pub fn handle_request(chain: &Chain, request: &Request) -> Result<Response, ChainError> {
+    match request {
+        Request::GetBlock(hash) => Ok(Response::Block(chain.get_block(hash)?)),
+        Request::GetHeaders { locator, limit } => {
+            let limit = (*limit).min(MAX_HEADERS) as usize;
+            Ok(Response::Headers(chain.headers_after(locator, limit)?))
+        }
+        // Needs a mempool; nodes answer it themselves
+        Request::SubmitTransaction(_) => Ok(Response::Submitted(Err(
+            "transactions are not accepted by this peer".to_string(),
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nullchain_consensus::mine_block;
+    use nullchain_storage::Database;
+    use nullchain_test_utils::EASY_BITS;
+    use nullchain_types::Transaction;
+    use tempfile::TempDir;
+
+    #[derive(Default)]
+    struct MockNetwork {
+        next_id: u64,
+        sent: VecDeque<(u64, PeerId, Request)>,
+    }
+
+    impl SyncNetwork for MockNetwork {
+        type RequestId = u64;
+
+        fn send_request(&mut self, peer: &PeerId, request: Request) -> u64 {
+            self.next_id += 1;
+            self.sent.push_back((self.next_id, *peer, request));
+            self.next_id
+        }
+    }
+
+    fn build_chain(length: u64) -> Vec<Block> {
+        let mut genesis = Block::genesis();
+        genesis.header.bits = EASY_BITS;
+        genesis.header.merkle_root = genesis.calculate_merkle_root();
+        let mut blocks = vec![mine_block(genesis, Some(1_000_000)).unwrap().0];
+
+        for height in 1..=length {
+            let parent = blocks.last().unwrap();
+            let mut block = Block {
+                header: parent.header.clone(),
+                transactions: vec![Transaction::coinbase(vec![1u8; 20], 50, height)],
+            };
+            block.header.previous_block = parent.hash();
+            block.header.timestamp += 600;
+            block.header.merkle_root = block.calculate_merkle_root();
+            blocks.push(mine_block(block, Some(1_000_000)).unwrap().0);
+        }
+        blocks
+    }
+
+    /// Answer every queued request accepted by `serve`; others stay queued
+    fn respond(
+        sync: &mut SyncManager<MockNetwork>,
+        net: &mut MockNetwork,
+        local: &Chain,
+        remote: &Chain,
+        serve: impl Fn(&PeerId, &Request) -> bool,
+        now: Instant,
+    ) {
+        let mut ignored = VecDeque::new();
+        while let Some((id, peer, request)) = net.sent.pop_front() {
+            if serve(&peer, &request) {
+                let response = handle_request(remote, &request).unwrap();
+                sync.on_response(net, local, id, response, now).unwrap();
+            } else {
+                ignored.push_back((id, peer, request));
+            }
+        }
+        net.sent = ignored;
+    }
+
+    fn is_headers(_: &PeerId, request: &Request) -> bool {
+        matches!(request, Request::GetHeaders { .. })
+    }
+
+    fn small_config() -> SyncConfig {
+        SyncConfig {
+            headers_per_request: 4,
+            max_blocks_per_peer: 3,
+            download_window: 8,
+            request_timeout: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn test_sync_from_peer() {
+        let remote_dir = TempDir::new().unwrap();
+        let remote_db = Database::open(remote_dir.path()).unwrap();
+        let remote = Chain::new(&remote_db);
+        let local_dir = TempDir::new().unwrap();
+        let local_db = Database::open(local_dir.path()).unwrap();
+        let local = Chain::new(&local_db);
+
+        let blocks = build_chain(12);
+        for block in &blocks {
+            remote.accept_block(block).unwrap();
+        }
+        local.accept_block(&blocks[0]).unwrap();
+
+        let mut net = MockNetwork::default();
+        let mut sync = SyncManager::new(small_config());
+        let peer = PeerId::random();
+        let now = Instant::now();
+
+        sync.add_peer(&mut net, &local, peer, now).unwrap();
+        respond(&mut sync, &mut net, &local, &remote, |_, _| true, now);
+
+        assert!(sync.is_synced());
+        assert_eq!(local.tip().unwrap().unwrap().hash(), blocks[12].hash());
+    }
+
+    #[test]
+    fn test_sync_empty_chain_downloads_genesis() {
+        let remote_dir = TempDir::new().unwrap();
+        let remote_db = Database::open(remote_dir.path()).unwrap();
+        let remote = Chain::new(&remote_db);
+        let local_dir = TempDir::new().unwrap();
+        let local_db = Database::open(local_dir.path()).unwrap();
+        let local = Chain::new(&local_db);
+
+        let blocks = build_chain(3);
+        for block in &blocks {
+            remote.accept_block(block).unwrap();
+        }
+
+        let mut net = MockNetwork::default();
+        let mut sync = SyncManager::new(SyncConfig::default());
+        let now = Instant::now();
+
+        sync.add_peer(&mut net, &local, PeerId::random(), now)
+            .unwrap();
+        respond(&mut sync, &mut net, &local, &remote, |_, _| true, now);
+
+        assert_eq!(local.tip().unwrap().unwrap().hash(), blocks[3].hash());
+    }
+
+    #[test]
+    fn test_timed_out_blocks_go_to_other_peer() {
+        let remote_dir = TempDir::new().unwrap();
+        let remote_db = Database::open(remote_dir.path()).unwrap();
+        let remote = Chain::new(&remote_db);
+        let local_dir = TempDir::new().unwrap();
+        let local_db = Database::open(local_dir.path()).unwrap();
+        let local = Chain::new(&local_db);
+
+        let blocks = build_chain(3);
+        for block in &blocks {
+            remote.accept_block(block).unwrap();
+        }
+        local.accept_block(&blocks[0]).unwrap();
+
+        let mut net = MockNetwork::default();
+        let mut sync = SyncManager::new(small_config());
+        let slow = PeerId::random();
+        let fast = PeerId::random();
+        let start = Instant::now();
+
+        // Only the slow peer is known when the headers arrive, so it gets every block
+        sync.add_peer(&mut net, &local, slow, start).unwrap();
+        respond(&mut sync, &mut net, &local, &remote, is_headers, start);
+        assert_eq!(net.sent.len(), 3);
+        assert!(net.sent.iter().all(|(_, peer, _)| *peer == slow));
+
+        sync.add_peer(&mut net, &local, fast, start).unwrap();
+        respond(
+            &mut sync,
+            &mut net,
+            &local,
+            &remote,
+            |p, _| *p == fast,
+            start,
+        );
+        assert_eq!(local.tip().unwrap().unwrap().height, 0);
+
+        let later = start + Duration::from_secs(6);
+        sync.tick(&mut net, &local, later).unwrap();
+        assert!(net
+            .sent
+            .iter()
+            .any(|(_, peer, request)| *peer == fast && matches!(request, Request::GetBlock(_))));
+        respond(
+            &mut sync,
+            &mut net,
+            &local,
+            &remote,
+            |p, _| *p == fast,
+            later,
+        );
+
+        assert_eq!(local.tip().unwrap().unwrap().hash(), blocks[3].hash());
+
+        // Late answers from the slow peer are ignored
+        respond(&mut sync, &mut net, &local, &remote, |_, _| true, later);
+        assert!(sync.is_synced());
+    }
+
+    #[test]
+    fn test_resume_after_restart() {
+        let remote_dir = TempDir::new().unwrap();
+        let remote_db = Database::open(remote_dir.path()).unwrap();
+        let remote = Chain::new(&remote_db);
+        let local_dir = TempDir::new().unwrap();
+        let local_db = Database::open(local_dir.path()).unwrap();
+        let local = Chain::new(&local_db);
+
+        let blocks = build_chain(10);
+        for block in &blocks {
+            remote.accept_block(block).unwrap();
+        }
+        local.accept_block(&blocks[0]).unwrap();
+
+        let peer = PeerId::random();
+        let now = Instant::now();
+
+        // First run: fetch all headers, then stop before any body arrives
+        {
+            let mut net = MockNetwork::default();
+            let mut sync = SyncManager::new(small_config());
+            sync.add_peer(&mut net, &local, peer, now).unwrap();
+            respond(&mut sync, &mut net, &local, &remote, is_headers, now);
+        }
+        assert_eq!(local.tip().unwrap().unwrap().height, 0);
+        assert_eq!(
+            local.best_header().unwrap().unwrap().hash(),
+            blocks[10].hash()
+        );
+
+        // Second run: the locator starts at the stored best header
+        let mut net = MockNetwork::default();
+        let mut sync = SyncManager::new(small_config());
+        sync.add_peer(&mut net, &local, peer, now).unwrap();
+        match &net.sent[0].2 {
+            Request::GetHeaders { locator, .. } => assert_eq!(locator[0], blocks[10].hash()),
+            other => panic!("unexpected request {:?}", other),
+        }
+        respond(&mut sync, &mut net, &local, &remote, |_, _| true, now);
+
+        assert!(sync.is_synced());
+        assert_eq!(local.tip().unwrap().unwrap().hash(), blocks[10].hash());
+    }
+
+    #[test]
+    fn test_handle_request() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let chain = Chain::new(&db);
+
+        let blocks = build_chain(2);
+        for block in &blocks {
+            chain.accept_block(block).unwrap();
+        }
+
+        match handle_request(&chain, &Request::GetBlock(blocks[1].hash())).unwrap() {
+            Response::Block(Some(block)) => assert_eq!(block.hash(), blocks[1].hash()),
+            other => panic!("unexpected response {:?}", other),
+        }
+
+        let request = Request::GetHeaders {
+            locator: vec![blocks[0].hash()],
+            limit: u32::MAX,
+        };
+        match handle_request(&chain, &request).unwrap() {
+            Response::Headers(headers) => assert_eq!(headers.len(), 2),
+            other => panic!("unexpected response {:?}", other),
+        }
+
+        let request = Request::SubmitTransaction(blocks[1].transactions[0].clone());
+        assert!(matches!(
+            handle_request(&chain, &request).unwrap(),
+            Response::Submitted(Err(_))
+        ));
+    }
+}
@@ -1,5 +1,7 @@
 use colored::Colorize;
-use nullchain_storage::{BlockStore, Database};
+use nullchain_consensus::ChainParams;
+use nullchain_storage::{BlockStore, Database, StorageError};
+use nullchain_types::{Block, Hash256};
 use std::path::Path;
 
 pub fn chain_info(datadir: String) {
@@ -62,3 +64,142 @@ pub fn get_block(datadir: String, height: u64) {
         }
     }
 }
+
+pub fn get_txproof(datadir: String, txid: String, height: Option<u64>, params: &ChainParams) {
+    let txid = parse_txid(&txid);
+    let db = open_database(&datadir, params);
+    let store = BlockStore::new(&db);
+
+    let (height, block, _) = find_transaction(&store, &txid, height);
+    let proof = block
+        .merkle_proof(&txid)
+        .expect("transaction is in the block");
+
+    let json = serde_json::json!({
+        "txid": txid.to_string(),
+        "block": block.hash().to_string(),
+        "height": height,
+        "merkle_root": block.header.merkle_root.to_string(),
+        "index": proof.index,
+        "leaf_count": proof.leaf_count,
+        "siblings": proof.siblings.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+    });
+    println!("{}", serde_json::to_string_pretty(&json).unwrap());
+}
+
+pub fn get_tx(datadir: String, txid: String, params: &ChainParams) {
+    let txid = parse_txid(&txid);
+    let db = open_database(&datadir, params);
+    let store = BlockStore::new(&db);
+
+    let (height, block, position) = find_transaction(&store, &txid, None);
+    let tip = store.get_height().unwrap_or(height);
+
+    let json = serde_json::json!({
+        "txid": txid.to_string(),
+        "block": block.hash().to_string(),
+        "height": height,
+        "position": position,
+        "confirmations": tip - height + 1,
+        "transaction": block.transactions[position],
+    });
+    println!("{}", serde_json::to_string_pretty(&json).unwrap());
+}
+
+fn parse_txid(txid: &str) -> Hash256 {
+    match hex::decode(txid)
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+    {
+        Some(bytes) => Hash256::from_bytes(bytes),
+        None => {
+            eprintln!(
+                "{}: invalid txid (expected 64 hex characters)",
+                "error".red()
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Open an existing chain database, checking it belongs to `params`' network
+fn open_database(datadir: &str, params: &ChainParams) -> Database {
+    let path = Path::new(datadir);
+    if !path.exists() {
+        eprintln!("{}: database not found at {}", "error".red(), datadir);
+        std::process::exit(1);
+    }
+
+    let db = match Database::open(path) {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("{}: {}", "error".red(), e);
+            std::process::exit(1);
+        }
+    };
+
+    match BlockStore::new(&db).get_hash_by_height(0) {
+        Ok(Some(genesis)) if genesis != params.genesis.hash() => {
+            eprintln!(
+                "{}: database at {} is not a {} chain (genesis {})",
+                "error".red(),
+                datadir,
+                params.network,
+                genesis
+            );
+            std::process::exit(1);
+        }
+        Ok(_) => db,
+        Err(e) => {
+            eprintln!("{}: {}", "error".red(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Find a main-chain transaction, returning its block, height and position
+///
+/// Uses the transaction index when enabled, otherwise searches the block at
+/// `height` or the whole chain from the tip down.
+fn find_transaction(
+    store: &BlockStore,
+    txid: &Hash256,
+    height: Option<u64>,
+) -> (u64, Block, usize) {
+    let exit = |e: StorageError| -> ! {
+        eprintln!("{}: {}", "error".red(), e);
+        std::process::exit(1);
+    };
+
+    if height.is_none() && store.tx_index_enabled().unwrap_or_else(|e| exit(e)) {
+        let location = store.get_tx_location(txid).unwrap_or_else(|e| exit(e));
+        if let Some(location) = location {
+            let block = store
+                .get_by_hash(&location.block)
+                .unwrap_or_else(|e| exit(e));
+            let height = match store.get_index(&location.block) {
+                Ok(Some(entry)) => entry.height,
+                Ok(None) => exit(StorageError::BlockNotFound(location.block.to_string())),
+                Err(e) => exit(e),
+            };
+            return (height, block, location.position as usize);
+        }
+    } else if store.get_best().unwrap_or_else(|e| exit(e)).is_some() {
+        // Empty when `height` is past the tip
+        let tip = store.get_height().unwrap_or_else(|e| exit(e));
+        let heights = match height {
+            Some(height) => height..=height.min(tip),
+            None => 0..=tip,
+        };
+
+        for height in heights.rev() {
+            let block = store.get_by_height(height).unwrap_or_else(|e| exit(e));
+            if let Some(position) = block.transactions.iter().position(|tx| tx.txid() == *txid) {
+                return (height, block, position);
+            }
+        }
+    }
+
+    eprintln!("{}: transaction {} not found", "error".red(), txid);
+    std::process::exit(1);
+}
@@ -1,19 +1,24 @@
 use colored::Colorize;
-use nullchain_types::Block;
+use nullchain_consensus::ChainParams;
 
-pub fn genesis() {
-    let genesis = Block::genesis();
+pub fn genesis(params: &ChainParams) {
+    let genesis = &params.genesis;
     let hash = genesis.hash();
 
-    eprintln!("Genesis block");
+    eprintln!("Genesis block ({})", params.network);
     eprintln!("  version:     {}", genesis.header.version);
     eprintln!("  timestamp:   {}", genesis.header.timestamp);
     eprintln!(
         "  difficulty:  {}",
         format!("0x{:08x}", genesis.header.bits).cyan()
     );
+    eprintln!("  nonce:       {}", genesis.header.nonce);
     eprintln!("  hash:        {}", format!("{}", hash).green());
+    eprintln!(
+        "  message:     {}",
+        String::from_utf8_lossy(&genesis.transactions[0].inputs[0].signature)
+    );
 
-    let json = serde_json::to_string(&genesis).unwrap();
+    let json = serde_json::to_string(genesis).unwrap();
     println!("{}", json);
 }
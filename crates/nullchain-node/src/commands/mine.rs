@@ -1,87 +1,149 @@
+use super::wallet::address::parse_address;
 use colored::Colorize;
-use nullchain_consensus::mine_block;
-use nullchain_storage::{BlockStore, Database};
-use nullchain_types::Block;
+use nullchain_consensus::{
+    build_template, AcceptOutcome, Chain, ChainParams, Mempool, ParallelMiner,
+};
+use nullchain_storage::Database;
 use std::path::Path;
-use std::time::Instant;
-
-pub fn mine(iterations: Option<u64>, bits: u32, datadir: Option<String>) {
-    let mut block = Block::genesis();
-
-    // Check if we should continue from existing chain
-    if let Some(ref dir) = datadir {
-        let path = Path::new(dir);
-        if path.exists() {
-            match Database::open(path) {
-                Ok(db) => {
-                    let store = BlockStore::new(&db);
-                    if let Ok(height) = store.get_height() {
-                        if height > 0 {
-                            eprintln!("Continuing from height {}", height);
-                            if let Ok(last_block) = store.get_by_height(height) {
-                                block = Block::genesis();
-                                block.header.previous_block = last_block.hash();
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    eprintln!("{}: failed to open database: {}", "warning".yellow(), e);
-                }
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Coinbase recipient when no address is given (burned)
+const BURN_ADDRESS: [u8; 20] = [0u8; 20];
+
+/// Command-line options of `nullchain mine`
+pub struct MineOptions {
+    /// Hash limit per block
+    pub iterations: Option<u64>,
+    pub address: Option<String>,
+    pub threads: usize,
+    /// Blocks to mine; `None` mines until interrupted
+    pub count: Option<u64>,
+    pub datadir: String,
+    pub params: ChainParams,
+}
+
+pub fn mine(options: MineOptions) {
+    let recipient = match &options.address {
+        Some(address) => match parse_address(address, options.params.network) {
+            Ok(address) => address.to_recipient(),
+            Err(e) => {
+                eprintln!("{}: {}", "error".red(), e);
+                std::process::exit(1);
             }
+        },
+        None => BURN_ADDRESS.to_vec(),
+    };
+
+    let db = match Database::open(Path::new(&options.datadir)) {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("{}: failed to open database: {}", "error".red(), e);
+            std::process::exit(1);
         }
+    };
+    let chain = Chain::with_params(&db, &options.params);
+    if let Err(e) = chain.initialize() {
+        eprintln!("{}: {}", "error".red(), e);
+        std::process::exit(1);
+    }
+
+    let cancel = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&cancel);
+    if let Err(e) = ctrlc::set_handler(move || flag.store(true, Ordering::Relaxed)) {
+        eprintln!("{}: cannot handle Ctrl-C: {}", "warning".yellow(), e);
     }
 
-    block.header.bits = bits;
-    block.header.merkle_root = block.calculate_merkle_root();
+    let miner = ParallelMiner::new(options.threads).with_max_hashes(options.iterations);
+    eprintln!("Mining with {} threads", options.threads);
 
-    eprintln!("Mining block");
-    eprintln!("  difficulty: {}", format!("0x{:08x}", bits).cyan());
-    if let Some(max) = iterations {
-        eprintln!("  max_iter:   {}", max);
+    let mut mined = 0u64;
+    while options.count.is_none_or(|count| mined < count) {
+        if !mine_next(&chain, &miner, &recipient, &cancel) {
+            break;
+        }
+        mined += 1;
     }
 
-    let start = Instant::now();
-
-    match mine_block(block, iterations) {
-        Some((mined_block, hash)) => {
-            let elapsed = start.elapsed();
-            let hashrate = mined_block.header.nonce as f64 / elapsed.as_secs_f64();
-
-            eprintln!("{}", "Block mined".green());
-            eprintln!("  nonce:    {}", mined_block.header.nonce);
-            eprintln!("  hash:     {}", format!("{}", hash).green());
-            eprintln!("  time:     {:.3}s", elapsed.as_secs_f64());
-            eprintln!("  hashrate: {:.0} H/s", hashrate);
-
-            // Save to database
-            if let Some(dir) = datadir {
-                let path = Path::new(&dir);
-                match Database::open(path) {
-                    Ok(db) => {
-                        let store = BlockStore::new(&db);
-                        let height = store.get_height().unwrap_or(0);
-
-                        if let Err(e) = store.put(height, &mined_block) {
-                            eprintln!("{}: failed to save block: {}", "error".red(), e);
-                        } else if let Err(e) = store.set_best(height, &hash) {
-                            eprintln!("{}: failed to update chain tip: {}", "error".red(), e);
-                        } else {
-                            eprintln!("Saved to database (height {})", height);
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("{}: failed to open database: {}", "error".red(), e);
-                    }
-                }
-            }
+    if cancel.load(Ordering::Relaxed) {
+        eprintln!("{}", "Interrupted".yellow());
+    }
+    eprintln!("Mined {} blocks", mined);
+
+    if let Err(e) = db.flush() {
+        eprintln!("{}: failed to flush database: {}", "error".red(), e);
+        std::process::exit(1);
+    }
+}
 
-            let json = serde_json::to_string(&mined_block).unwrap();
-            println!("{}", json);
+/// Mine and store one block on the current tip
+///
+/// Returns `false` if mining was interrupted.
+fn mine_next(chain: &Chain, miner: &ParallelMiner, recipient: &[u8], cancel: &AtomicBool) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock before unix epoch")
+        .as_secs();
+
+    let template = match build_template(chain, &Mempool::default(), recipient, now) {
+        Ok(template) => template,
+        Err(e) => {
+            eprintln!("{}: failed to build block template: {}", "error".red(), e);
+            std::process::exit(1);
         }
+    };
+
+    let bits = template.block.header.bits;
+
+    eprintln!(
+        "Mining block {} (difficulty {})",
+        template.height,
+        format!("0x{:08x}", bits).cyan()
+    );
+
+    let mut last = None;
+    let mined = miner.mine(template.block, cancel, |progress| {
+        eprint!(
+            "\r  {:.0} H/s, {} hashes",
+            progress.hashrate(),
+            progress.hashes
+        );
+        last = Some(progress);
+    });
+    eprintln!();
+
+    let (block, hash) = match mined {
+        Some(mined) => mined,
+        None if cancel.load(Ordering::Relaxed) => return false,
         None => {
             eprintln!("{}", "Mining failed: no solution found".red());
             std::process::exit(1);
         }
+    };
+    let progress = last.expect("progress is reported when mining ends");
+
+    eprintln!("{}", "Block mined".green());
+    eprintln!("  nonce:    {}", block.header.nonce);
+    eprintln!("  hash:     {}", format!("{}", hash).green());
+    eprintln!("  time:     {:.3}s", progress.elapsed.as_secs_f64());
+    eprintln!("  hashes:   {}", progress.hashes);
+    eprintln!("  hashrate: {:.0} H/s", progress.hashrate());
+
+    match chain.accept_block(&block) {
+        Ok(AcceptOutcome::NewTip { height, .. }) => {
+            eprintln!("Saved to database (height {})", height);
+        }
+        Ok(outcome) => {
+            eprintln!("{}: block not connected: {:?}", "warning".yellow(), outcome);
+        }
+        Err(e) => {
+            eprintln!("{}: block rejected: {}", "error".red(), e);
+            std::process::exit(1);
+        }
     }
+
+    let json = serde_json::to_string(&block).unwrap();
+    println!("{}", json);
+    true
 }
@@ -2,11 +2,13 @@ pub mod chain;
 pub mod genesis;
 pub mod info;
 pub mod mine;
+pub mod node;
 pub mod version;
 pub mod wallet;
 
-pub use chain::{chain_info, get_block};
+pub use chain::{chain_info, get_block, get_tx, get_txproof};
 pub use genesis::genesis;
 pub use info::info;
-pub use mine::mine;
+pub use mine::{mine, MineOptions};
+pub use node::{node, NodeOptions};
 pub use version::version;
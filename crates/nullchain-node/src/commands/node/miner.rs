@@ -0,0 +1,92 @@
+//! Background miner for the node daemon
+
+use nullchain_consensus::{build_template, Chain, ChainError, Mempool, ParallelMiner};
+use nullchain_types::Block;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// How often the hashrate is logged
+const HASHRATE_LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+struct Job {
+    cancel: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+/// Mines on top of the active tip in background threads, one template at a time
+pub struct Miner {
+    address: Vec<u8>,
+    miner: ParallelMiner,
+    found: UnboundedSender<Block>,
+    job: Option<Job>,
+}
+
+impl Miner {
+    pub fn new(address: Vec<u8>, threads: usize, found: UnboundedSender<Block>) -> Self {
+        Self {
+            address,
+            miner: ParallelMiner::new(threads).with_report_interval(HASHRATE_LOG_INTERVAL),
+            found,
+            job: None,
+        }
+    }
+
+    /// Abandon the current template and start mining on the current tip
+    pub fn restart(&mut self, chain: &Chain, mempool: &Mempool) -> Result<(), ChainError> {
+        self.stop();
+
+        let block = self.template(chain, mempool)?;
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        let found = self.found.clone();
+        let flag = Arc::clone(&cancel);
+        let miner = self.miner.clone();
+        let handle = std::thread::spawn(move || {
+            let mined = miner.mine(block, &flag, |progress| {
+                if !flag.load(Ordering::Relaxed) {
+                    tracing::info!("Hashrate: {:.0} H/s", progress.hashrate());
+                }
+            });
+            if let Some((block, _)) = mined {
+                let _ = found.send(block);
+            }
+        });
+
+        self.job = Some(Job { cancel, handle });
+        Ok(())
+    }
+
+    /// Cancel the current template and wait for the mining threads to exit
+    pub fn stop(&mut self) {
+        if let Some(job) = self.job.take() {
+            job.cancel.store(true, Ordering::Relaxed);
+            let _ = job.handle.join();
+        }
+    }
+
+    fn template(&self, chain: &Chain, mempool: &Mempool) -> Result<Block, ChainError> {
+        let template = build_template(chain, mempool, &self.address, now())?;
+        tracing::debug!(
+            "Mining at height {} with {} transactions",
+            template.height,
+            template.block.transactions.len()
+        );
+        Ok(template.block)
+    }
+}
+
+impl Drop for Miner {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock before unix epoch")
+        .as_secs()
+}
@@ -0,0 +1,375 @@
+//! Long-running node: networking, sync, mempool and optional mining
+
+mod miner;
+
+use crate::commands::wallet::address::parse_address;
+use colored::Colorize;
+use miner::Miner;
+use nullchain_consensus::{
+    AcceptOutcome, Chain, ChainError, ChainParams, Mempool, MempoolError, ValidationError,
+};
+use nullchain_network::{
+    handle_request, MessageAcceptance, Multiaddr, Network, NetworkConfig, NetworkEvent, Request,
+    Response, SyncConfig, SyncManager,
+};
+use nullchain_storage::{BlockStore, Database};
+use nullchain_types::{Block, Hash256, Transaction};
+use std::error::Error;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// How often sync timeouts are checked
+const SYNC_TICK: Duration = Duration::from_secs(1);
+
+/// Command-line options of `nullchain node`
+pub struct NodeOptions {
+    pub datadir: String,
+    pub listen: Vec<String>,
+    pub peers: Vec<String>,
+    pub no_mdns: bool,
+    /// Address receiving mined coinbases; enables the miner
+    pub miner_address: Option<String>,
+    pub miner_threads: usize,
+    /// Build and maintain the transaction index
+    pub txindex: bool,
+    pub params: ChainParams,
+}
+
+pub fn node(options: NodeOptions) {
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("{}: failed to start runtime: {}", "error".red(), e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = runtime.block_on(run(options)) {
+        eprintln!("{}: {}", "error".red(), e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(options: NodeOptions) -> Result<(), Box<dyn Error>> {
+    let miner_address = match &options.miner_address {
+        Some(address) => Some(parse_address(address, options.params.network)?.to_recipient()),
+        None => None,
+    };
+
+    let mut config = NetworkConfig {
+        listen_addrs: vec![format!("/ip4/0.0.0.0/tcp/{}", options.params.default_port).parse()?],
+        enable_mdns: !options.no_mdns,
+        magic: options.params.magic,
+        ..NetworkConfig::default()
+    };
+    if !options.listen.is_empty() {
+        config.listen_addrs = parse_addrs(&options.listen)?;
+    }
+    config.bootstrap_peers = parse_addrs(&options.peers)?;
+
+    let db = Database::open(&options.datadir)?;
+    let (found_tx, mut found_rx) = mpsc::unbounded_channel();
+
+    let mut node = Node {
+        chain: Chain::with_params(&db, &options.params),
+        network: Network::new(config)?,
+        sync: SyncManager::new(SyncConfig::default()),
+        mempool: Mempool::default(),
+        miner: miner_address.map(|address| Miner::new(address, options.miner_threads, found_tx)),
+    };
+
+    tracing::info!("Running on {}", options.params.network);
+    node.chain.initialize()?;
+    if options.txindex {
+        BlockStore::new(&db).enable_tx_index()?;
+        tracing::info!("Transaction index enabled");
+    }
+    match node.chain.tip()? {
+        Some(tip) => tracing::info!("Chain tip {} at height {}", tip.hash(), tip.height),
+        None => tracing::info!("Chain is empty"),
+    }
+
+    if let Some(miner) = &mut node.miner {
+        miner.restart(&node.chain, &node.mempool)?;
+    }
+
+    let mut tick = tokio::time::interval(SYNC_TICK);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            event = node.network.next_event() => node.handle_event(event)?,
+            Some(block) = found_rx.recv() => node.handle_mined(block)?,
+            _ = tick.tick() => {
+                node.sync.tick(&mut node.network, &node.chain, Instant::now())?;
+            }
+        }
+    }
+
+    tracing::info!("Shutting down");
+    if let Some(miner) = &mut node.miner {
+        miner.stop();
+    }
+    db.flush()?;
+    tracing::info!("Database flushed");
+
+    Ok(())
+}
+
+struct Node<'a> {
+    chain: Chain<'a>,
+    network: Network,
+    sync: SyncManager<Network>,
+    mempool: Mempool,
+    miner: Option<Miner>,
+}
+
+impl Node<'_> {
+    fn handle_event(&mut self, event: NetworkEvent) -> Result<(), ChainError> {
+        let now = Instant::now();
+
+        match event {
+            NetworkEvent::Listening(_) => {}
+            NetworkEvent::PeerConnected(peer) => {
+                tracing::info!("Peer connected: {}", peer);
+                self.sync
+                    .add_peer(&mut self.network, &self.chain, peer, now)?;
+            }
+            NetworkEvent::PeerDisconnected(peer) => {
+                tracing::info!("Peer disconnected: {}", peer);
+                self.sync
+                    .remove_peer(&mut self.network, &self.chain, &peer, now)?;
+            }
+            NetworkEvent::Block {
+                peer,
+                message_id,
+                block,
+            } => {
+                let result = self.chain.accept_block(&block);
+                let acceptance = match &result {
+                    Ok(AcceptOutcome::NewTip { .. } | AcceptOutcome::SideBranch) => {
+                        MessageAcceptance::Accept
+                    }
+                    Ok(AcceptOutcome::AlreadyKnown)
+                    | Err(ChainError::UnknownParent(_))
+                    | Err(ChainError::Storage(_))
+                    | Err(ChainError::Invalid(ValidationError::TimestampTooFar { .. })) => {
+                        MessageAcceptance::Ignore
+                    }
+                    Err(_) => MessageAcceptance::Reject,
+                };
+                self.network.report_gossip(&message_id, &peer, acceptance);
+
+                match result {
+                    Ok(outcome) => self.handle_outcomes(vec![outcome])?,
+                    Err(ChainError::UnknownParent(_)) => {
+                        self.sync
+                            .request_headers(&mut self.network, &self.chain, &peer, now)?;
+                    }
+                    Err(ChainError::Storage(e)) => return Err(e.into()),
+                    Err(e) => {
+                        tracing::debug!("Rejected block {} from {}: {}", block.hash(), peer, e)
+                    }
+                }
+            }
+            NetworkEvent::Transaction {
+                peer,
+                message_id,
+                transaction,
+            } => {
+                let txid = transaction.txid();
+                let height = self.chain.next_height()?;
+                let result = self.mempool.insert(transaction, self.chain.utxos(), height);
+                let acceptance = match &result {
+                    Ok(_) => MessageAcceptance::Accept,
+                    Err(MempoolError::Coinbase) => MessageAcceptance::Reject,
+                    // Spends of outputs we have not seen yet, that were just
+                    // spent or that mature on a tip we lack are not the
+                    // sender's fault
+                    Err(MempoolError::Invalid(
+                        ValidationError::MissingInput { .. }
+                        | ValidationError::ImmatureCoinbase { .. }
+                        | ValidationError::Storage(_),
+                    )) => MessageAcceptance::Ignore,
+                    Err(MempoolError::Invalid(_)) => MessageAcceptance::Reject,
+                    Err(_) => MessageAcceptance::Ignore,
+                };
+                self.network.report_gossip(&message_id, &peer, acceptance);
+
+                match result {
+                    Ok(_) => tracing::debug!("Added transaction {} from {}", txid, peer),
+                    Err(MempoolError::Invalid(ValidationError::Storage(e))) => return Err(e.into()),
+                    Err(e) => tracing::debug!("Rejected transaction {} from {}: {}", txid, peer, e),
+                }
+            }
+            NetworkEvent::InboundRequest {
+                peer,
+                request: Request::SubmitTransaction(transaction),
+                channel,
+            } => {
+                let response = Response::Submitted(self.submit_transaction(transaction)?);
+                if let Err(e) = self.network.respond(channel, response) {
+                    tracing::debug!("Failed to answer {}: {}", peer, e);
+                }
+            }
+            NetworkEvent::InboundRequest {
+                peer,
+                request,
+                channel,
+            } => {
+                let response = handle_request(&self.chain, &request)?;
+                if let Err(e) = self.network.respond(channel, response) {
+                    tracing::debug!("Failed to answer {}: {}", peer, e);
+                }
+            }
+            NetworkEvent::Response {
+                request_id,
+                response,
+                ..
+            } => {
+                let outcomes = self.sync.on_response(
+                    &mut self.network,
+                    &self.chain,
+                    request_id,
+                    response,
+                    now,
+                )?;
+                self.handle_outcomes(outcomes)?;
+            }
+            NetworkEvent::RequestFailed {
+                peer,
+                request_id,
+                error,
+            } => {
+                tracing::debug!("Request to {} failed: {}", peer, error);
+                self.sync
+                    .on_request_failed(&mut self.network, &self.chain, request_id, now)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add a transaction handed to us directly and relay it to our peers
+    fn submit_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<Result<Hash256, String>, ChainError> {
+        let height = self.chain.next_height()?;
+        match self
+            .mempool
+            .insert(transaction.clone(), self.chain.utxos(), height)
+        {
+            Ok(txid) => {
+                tracing::info!("Accepted submitted transaction {}", txid);
+                if let Err(e) = self.network.publish_transaction(&transaction) {
+                    tracing::debug!("Failed to publish transaction {}: {}", txid, e);
+                }
+                Ok(Ok(txid))
+            }
+            Err(MempoolError::Invalid(ValidationError::Storage(e))) => Err(e.into()),
+            Err(e) => Ok(Err(e.to_string())),
+        }
+    }
+
+    fn handle_mined(&mut self, block: Block) -> Result<(), ChainError> {
+        match self.chain.accept_block(&block) {
+            Ok(outcome) => {
+                if let Err(e) = self.network.publish_block(&block) {
+                    tracing::debug!("Failed to publish block {}: {}", block.hash(), e);
+                }
+                self.handle_outcomes(vec![outcome])
+            }
+            Err(ChainError::Storage(e)) => Err(e.into()),
+            Err(e) => {
+                tracing::warn!("Mined block {} rejected: {}", block.hash(), e);
+                self.restart_miner()
+            }
+        }
+    }
+
+    /// Update the mempool and miner after blocks were connected
+    ///
+    /// Blocks leaving the active chain are handled before those joining it,
+    /// as a reorganization happened in that order. Returning them to the
+    /// mempool also drops entries that became invalid, so the miner restarts
+    /// on a clean pool.
+    fn handle_outcomes(&mut self, outcomes: Vec<AcceptOutcome>) -> Result<(), ChainError> {
+        let mut new_tip = false;
+        // The UTXO set already reflects the final tip
+        let next_height = self.chain.next_height()?;
+
+        for outcome in outcomes {
+            if let AcceptOutcome::NewTip {
+                height,
+                disconnected,
+                connected,
+            } = outcome
+            {
+                tracing::info!("New tip at height {}", height);
+                for hash in disconnected {
+                    if let Some(block) = self.chain.get_block(&hash)? {
+                        self.mempool
+                            .block_disconnected(&block, self.chain.utxos(), next_height);
+                    }
+                }
+                for hash in connected {
+                    if let Some(block) = self.chain.get_block(&hash)? {
+                        self.mempool.block_connected(&block);
+                    }
+                }
+                new_tip = true;
+            }
+        }
+
+        if new_tip {
+            self.restart_miner()?;
+        }
+        Ok(())
+    }
+
+    fn restart_miner(&mut self) -> Result<(), ChainError> {
+        match &mut self.miner {
+            Some(miner) => miner.restart(&self.chain, &self.mempool),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse_addrs(addrs: &[String]) -> Result<Vec<Multiaddr>, Box<dyn Error>> {
+    addrs
+        .iter()
+        .map(|addr| {
+            addr.parse()
+                .map_err(|e| format!("invalid address {}: {}", addr, e).into())
+        })
+        .collect()
+}
+
+/// Resolves on Ctrl-C or SIGTERM
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(e) => {
+                tracing::warn!("Cannot listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
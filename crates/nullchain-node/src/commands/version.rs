@@ -1,5 +1,7 @@
-pub fn version() {
+use nullchain_consensus::ChainParams;
+
+pub fn version(params: &ChainParams) {
     println!("nullchain v{}", env!("CARGO_PKG_VERSION"));
-    println!("Network: testnet");
+    println!("Network: {}", params.network);
     println!("Consensus: proof-of-work");
 }
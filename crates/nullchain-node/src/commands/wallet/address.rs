@@ -1,7 +1,9 @@
 use colored::Colorize;
+use nullchain_crypto::pubkey_hash;
+use nullchain_types::{Address, Network};
 use std::fs;
 
-pub fn show_address(pubkey_file: String) {
+pub fn show_address(pubkey_file: String, network: Network) {
     let pubkey_hex = match fs::read_to_string(&pubkey_file) {
         Ok(content) => content.trim().to_string(),
         Err(e) => {
@@ -18,9 +20,34 @@ pub fn show_address(pubkey_file: String) {
         }
     };
 
-    let hash = blake3::hash(&pubkey_bytes);
-    let address_bytes = &hash.as_bytes()[0..20];
-    let address = hex::encode(address_bytes);
+    println!("{}", display_address(&pubkey_bytes, network).cyan());
+}
+
+/// Address string of a public key on `network`
+pub fn display_address(pubkey: &[u8], network: Network) -> String {
+    Address::new(network, pubkey_hash(pubkey)).to_string()
+}
+
+/// Address string of an output recipient, or its hex if it is not a
+/// public key hash
+pub fn recipient_address(recipient: &[u8], network: Network) -> String {
+    match Address::from_recipient(network, recipient) {
+        Some(address) => address.to_string(),
+        None => hex::encode(recipient),
+    }
+}
 
-    println!("{}", format!("null1{}", address).cyan());
+/// Parse an address of `network`
+///
+/// Errors that point at a character show the address with a marker under it.
+pub fn parse_address(address: &str, network: Network) -> Result<Address, String> {
+    Address::parse_on(address, network).map_err(|e| match e.position() {
+        Some(position) => format!(
+            "invalid address: {}\n  {}\n  {}^",
+            e,
+            address,
+            " ".repeat(position)
+        ),
+        None => format!("invalid address: {}", e),
+    })
 }
@@ -0,0 +1,86 @@
+/// nanoNULL per NULL
+const COIN: u64 = 1_000_000_000;
+
+/// Decimal digits of a NULL amount
+const DECIMALS: usize = 9;
+
+pub fn format_amount(amount: u64) -> String {
+    format!("{}.{:09} NULL", amount / COIN, amount % COIN)
+}
+
+/// Parse a decimal NULL amount such as `12.5` into nanoNULL
+pub fn parse_amount(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid amount {:?}", s);
+
+    let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+    if (whole.is_empty() && fraction.is_empty())
+        || fraction.len() > DECIMALS
+        || !whole
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+
+    let whole: u64 = if whole.is_empty() {
+        0
+    } else {
+        whole.parse().map_err(|_| invalid())?
+    };
+    let fraction: u64 = format!("{:0<9}", fraction).parse().map_err(|_| invalid())?;
+
+    whole
+        .checked_mul(COIN)
+        .and_then(|amount| amount.checked_add(fraction))
+        .ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_amount() {
+        assert_eq!(parse_amount("12.5"), Ok(12_500_000_000));
+        assert_eq!(parse_amount("0.000000001"), Ok(1));
+        assert_eq!(parse_amount(".5"), Ok(500_000_000));
+        assert_eq!(parse_amount("12."), Ok(12 * COIN));
+        assert_eq!(parse_amount("7"), Ok(7 * COIN));
+    }
+
+    #[test]
+    fn test_parse_amount_rejects_invalid() {
+        for s in [
+            "",
+            ".",
+            "0.0000000001",
+            "-1",
+            "+1",
+            "1.2.3",
+            "1e9",
+            " 1",
+            "1,5",
+        ] {
+            assert!(parse_amount(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn test_parse_amount_overflow() {
+        assert_eq!(parse_amount("18446744073.709551615"), Ok(u64::MAX));
+        assert!(parse_amount("18446744073.709551616").is_err());
+        assert!(parse_amount("18446744074").is_err());
+        assert!(parse_amount("99999999999999999999").is_err());
+    }
+
+    #[test]
+    fn test_format_roundtrip() {
+        assert_eq!(format_amount(12_500_000_000), "12.500000000 NULL");
+        let formatted = format_amount(u64::MAX);
+        assert_eq!(
+            parse_amount(formatted.trim_end_matches(" NULL")),
+            Ok(u64::MAX)
+        );
+    }
+}
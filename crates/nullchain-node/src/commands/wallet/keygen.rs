@@ -1,66 +1,187 @@
+use super::address::display_address;
+use super::keystore::{
+    read_line, save_key, save_seed, PUBLIC_KEY_FILE, SECRET_KEY_FILE, SEED_FILE,
+};
+use super::tracking::{exit_on_error, open_synced, register_keys, KEY_LOOKAHEAD, WALLET_DB};
 use colored::Colorize;
-use nullchain_crypto::generate_keypair;
+use ed25519_dalek::SigningKey;
+use nullchain_crypto::{generate_keypair, wallet_key_path, ExtendedKey, Mnemonic};
+use nullchain_types::Network;
+use nullchain_wallet::WalletKey;
 use std::fs;
-use std::io::{self, Write};
 use std::path::Path;
+use zeroize::Zeroizing;
 
-pub fn keygen(output_dir: Option<String>) {
-    let (signing_key, verifying_key) = generate_keypair();
-    let pubkey_hex = hex::encode(verifying_key.to_bytes());
-
-    eprintln!("Generated new keypair");
-    println!("{}", pubkey_hex.green());
-
-    if let Some(dir) = output_dir {
-        eprint!("Enter passphrase: ");
-        io::stderr().flush().unwrap();
+pub fn keygen(output_dir: Option<String>, mnemonic_words: Option<usize>, force: bool) {
+    if let Some(dir) = &output_dir {
+        prepare_dir(Path::new(dir), force);
+    }
 
-        let mut passphrase = String::new();
-        io::stdin()
-            .read_line(&mut passphrase)
-            .expect("Failed to read passphrase");
-        let passphrase = passphrase.trim();
+    let (signing_key, seed) = match mnemonic_words {
+        Some(words) => {
+            let mnemonic = match Mnemonic::generate(words) {
+                Ok(mnemonic) => mnemonic,
+                Err(e) => {
+                    eprintln!("{}: {}", "error".red(), e);
+                    std::process::exit(1);
+                }
+            };
 
-        if passphrase.is_empty() {
-            eprintln!("{}", "error: empty passphrase".red());
-            std::process::exit(1);
-        }
-
-        if passphrase.len() < 8 {
+            eprintln!("Generated new wallet");
             eprintln!(
                 "{}",
-                "warning: weak passphrase (minimum 8 characters recommended)".yellow()
+                "Write down this mnemonic; it restores all keys of the wallet:".yellow()
             );
-        }
+            eprintln!("  {}", mnemonic.to_string().bold());
 
-        let privkey_bytes = signing_key.to_bytes();
-        let key_hash = blake3::hash(passphrase.as_bytes());
-        let mut encrypted = privkey_bytes.to_vec();
-        for (i, byte) in encrypted.iter_mut().enumerate() {
-            *byte ^= key_hash.as_bytes()[i % 32];
+            let (signing_key, seed) = derive_wallet_key(&mnemonic);
+            (signing_key, Some(seed))
         }
+        None => {
+            eprintln!("Generated new keypair");
+            (generate_keypair().0, None)
+        }
+    };
+
+    let pubkey_hex = hex::encode(signing_key.verifying_key().to_bytes());
+    println!("{}", pubkey_hex.green());
+
+    if let Some(dir) = output_dir {
+        save(
+            Path::new(&dir),
+            &signing_key,
+            seed.as_ref().map(|seed| &seed[..]),
+        );
+    }
+}
+
+/// Recreate a wallet from its mnemonic and find its keys in use on the
+/// chain in `datadir`
+pub fn restore(dir: String, datadir: String, network: Network, force: bool) {
+    prepare_dir(Path::new(&dir), force);
 
-        let path = Path::new(&dir);
-        if let Err(e) = fs::create_dir_all(path) {
+    let phrase = read_line("Enter mnemonic: ");
+    let mnemonic = match Mnemonic::parse(&phrase) {
+        Ok(mnemonic) => mnemonic,
+        Err(e) => {
             eprintln!("{}: {}", "error".red(), e);
             std::process::exit(1);
         }
+    };
 
-        let pubkey_path = path.join("key.pub");
-        let privkey_path = path.join("key.enc");
+    let (signing_key, seed) = derive_wallet_key(&mnemonic);
+    let pubkey = signing_key.verifying_key().to_bytes();
 
-        if let Err(e) = fs::write(&pubkey_path, &pubkey_hex) {
-            eprintln!("{}: failed to write public key: {}", "error".red(), e);
-            std::process::exit(1);
-        }
+    eprintln!("Restored wallet");
+    eprintln!("  public:  {}", hex::encode(pubkey));
+    eprintln!("  address: {}", display_address(&pubkey, network).cyan());
+
+    save(Path::new(&dir), &signing_key, Some(&seed[..]));
+
+    let wallet = open_synced(&dir, &datadir, network, Some(&seed[..]));
+    let keys = exit_on_error(wallet.keys()).len();
+    eprintln!("Watching {} keys", keys);
+}
 
-        if let Err(e) = fs::write(&privkey_path, hex::encode(&encrypted)) {
-            eprintln!("{}: failed to write private key: {}", "error".red(), e);
+/// Seed of `mnemonic` and the first wallet key derived from it
+fn derive_wallet_key(mnemonic: &Mnemonic) -> (SigningKey, Zeroizing<[u8; 64]>) {
+    let seed = mnemonic.to_seed("");
+    (derive_key(&*seed, 0), seed)
+}
+
+/// Wallet key at `index` of the HD seed
+pub fn derive_key(seed: &[u8], index: u32) -> SigningKey {
+    ExtendedKey::from_seed(seed)
+        .derive_path(&wallet_key_path(index))
+        .expect("wallet key path is valid")
+        .signing_key()
+}
+
+/// Public keys the wallet watches: the first derived keys, or the single key
+fn watched_keys(signing_key: &SigningKey, seed: Option<&[u8]>) -> Vec<WalletKey> {
+    match seed {
+        Some(seed) => (0..KEY_LOOKAHEAD)
+            .map(|index| WalletKey {
+                public_key: derive_key(seed, index).verifying_key().to_bytes(),
+                index: Some(index),
+            })
+            .collect(),
+        None => vec![WalletKey {
+            public_key: signing_key.verifying_key().to_bytes(),
+            index: None,
+        }],
+    }
+}
+
+/// Make sure writing a wallet into `path` loses nothing
+///
+/// Exits if `path` already holds a key or seed, unless `force` is set; then
+/// the old wallet database goes too, since it watches the replaced keys.
+fn prepare_dir(path: &Path, force: bool) {
+    let existing: Vec<_> = [SECRET_KEY_FILE, SEED_FILE]
+        .into_iter()
+        .filter(|file| path.join(file).exists())
+        .collect();
+    if existing.is_empty() {
+        return;
+    }
+
+    if !force {
+        eprintln!(
+            "{}: {} already holds {}; use --force to overwrite",
+            "error".red(),
+            path.display(),
+            existing.join(" and ")
+        );
+        std::process::exit(1);
+    }
+
+    let wallet_db = path.join(WALLET_DB);
+    if wallet_db.exists() {
+        if let Err(e) = fs::remove_dir_all(&wallet_db) {
+            eprintln!(
+                "{}: failed to remove old wallet database: {}",
+                "error".red(),
+                e
+            );
             std::process::exit(1);
         }
+    }
+    eprintln!(
+        "{}: overwriting the wallet in {}",
+        "warning".yellow(),
+        path.display()
+    );
+}
+
+/// Prompt for a passphrase and write the encrypted key (and seed) to `path`
+fn save(path: &Path, signing_key: &SigningKey, seed: Option<&[u8]>) {
+    let passphrase = read_line("Enter passphrase: ");
 
-        eprintln!("Saved to {}", path.display().to_string().cyan());
-        eprintln!("  public:  {}", pubkey_path.display());
-        eprintln!("  private: {} (encrypted)", privkey_path.display());
+    if passphrase.is_empty() {
+        eprintln!("{}", "error: empty passphrase".red());
+        std::process::exit(1);
     }
+
+    if passphrase.len() < 8 {
+        eprintln!(
+            "{}",
+            "warning: weak passphrase (minimum 8 characters recommended)".yellow()
+        );
+    }
+
+    let saved = save_key(path, signing_key, &passphrase)
+        .and_then(|_| seed.map_or(Ok(()), |seed| save_seed(path, seed, &passphrase)))
+        .and_then(|_| register_keys(path, &watched_keys(signing_key, seed)));
+    if let Err(e) = saved {
+        eprintln!("{}: {}", "error".red(), e);
+        std::process::exit(1);
+    }
+
+    eprintln!("Saved to {}", path.display().to_string().cyan());
+    eprintln!("  public:  {}", path.join(PUBLIC_KEY_FILE).display());
+    eprintln!(
+        "  private: {} (encrypted)",
+        path.join(SECRET_KEY_FILE).display()
+    );
 }
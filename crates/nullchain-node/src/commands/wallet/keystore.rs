@@ -0,0 +1,158 @@
+use super::address::display_address;
+use colored::Colorize;
+use ed25519_dalek::SigningKey;
+use nullchain_crypto::{decrypt_legacy, KdfParams, Keystore};
+use nullchain_types::Network;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use zeroize::Zeroizing;
+
+/// File holding the hex-encoded public key
+pub const PUBLIC_KEY_FILE: &str = "key.pub";
+/// File holding the hex-encoded keystore
+pub const SECRET_KEY_FILE: &str = "key.enc";
+/// File holding the keystore of the HD wallet seed, if any
+pub const SEED_FILE: &str = "seed.enc";
+
+/// Prompt on stderr and read a trimmed line from stdin
+pub fn read_line(prompt: &str) -> String {
+    eprint!("{}", prompt);
+    io::stderr().flush().unwrap();
+
+    let mut line = String::new();
+    io::stdin()
+        .read_line(&mut line)
+        .expect("Failed to read input");
+    line.trim().to_string()
+}
+
+/// Encrypt `signing_key` and write both key files into `dir`
+pub fn save_key(dir: &Path, signing_key: &SigningKey, passphrase: &str) -> Result<(), String> {
+    let keystore = Keystore::encrypt(
+        signing_key.as_bytes(),
+        passphrase.as_bytes(),
+        KdfParams::default(),
+    )
+    .map_err(|e| format!("failed to encrypt key: {}", e))?;
+
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    fs::write(
+        dir.join(PUBLIC_KEY_FILE),
+        hex::encode(signing_key.verifying_key().to_bytes()),
+    )
+    .map_err(|e| format!("failed to write public key: {}", e))?;
+    write_keystore(dir, SECRET_KEY_FILE, &keystore)
+}
+
+/// Encrypt the HD wallet seed into `dir`
+pub fn save_seed(dir: &Path, seed: &[u8], passphrase: &str) -> Result<(), String> {
+    let keystore = Keystore::encrypt(seed, passphrase.as_bytes(), KdfParams::default())
+        .map_err(|e| format!("failed to encrypt seed: {}", e))?;
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    write_keystore(dir, SEED_FILE, &keystore)
+}
+
+/// Decrypt the key stored in `dir`
+///
+/// Legacy XOR-encrypted key files are checked against `key.pub` and
+/// rewritten as keystores on success.
+pub fn load_key(dir: &Path, passphrase: &str) -> Result<SigningKey, String> {
+    let path = dir.join(SECRET_KEY_FILE);
+    let contents = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let data =
+        hex::decode(contents.trim()).map_err(|_| format!("{}: invalid hex", path.display()))?;
+
+    let legacy = Keystore::is_legacy(&data);
+    let secret = if legacy {
+        decrypt_legacy(&data, passphrase.as_bytes())
+    } else {
+        Keystore::from_bytes(&data).and_then(|keystore| keystore.decrypt(passphrase.as_bytes()))
+    }
+    .map_err(|e| e.to_string())?;
+
+    let secret: [u8; 32] = secret
+        .as_slice()
+        .try_into()
+        .map_err(|_| "decrypted key has the wrong length".to_string())?;
+    let signing_key = SigningKey::from_bytes(&secret);
+
+    let pubkey_path = dir.join(PUBLIC_KEY_FILE);
+    match fs::read_to_string(&pubkey_path) {
+        Ok(pubkey) => {
+            if pubkey.trim() != hex::encode(signing_key.verifying_key().to_bytes()) {
+                return Err("wrong passphrase or corrupted key file".to_string());
+            }
+        }
+        // The legacy format cannot tell a wrong passphrase apart without it
+        Err(e) if legacy => {
+            return Err(format!("{}: {}", pubkey_path.display(), e));
+        }
+        Err(_) => {}
+    }
+
+    if legacy {
+        let keystore = Keystore::encrypt(&secret, passphrase.as_bytes(), KdfParams::default())
+            .map_err(|e| format!("failed to encrypt key: {}", e))?;
+        write_keystore(dir, SECRET_KEY_FILE, &keystore)?;
+        eprintln!(
+            "Upgraded {} to the encrypted keystore format",
+            path.display().to_string().cyan()
+        );
+    }
+
+    Ok(signing_key)
+}
+
+/// Decrypt the HD wallet seed stored in `dir`
+pub fn load_seed(dir: &Path, passphrase: &str) -> Result<Zeroizing<Vec<u8>>, String> {
+    let path = dir.join(SEED_FILE);
+    let contents = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let data =
+        hex::decode(contents.trim()).map_err(|_| format!("{}: invalid hex", path.display()))?;
+
+    Keystore::from_bytes(&data)
+        .and_then(|keystore| keystore.decrypt(passphrase.as_bytes()))
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Replace `dir/name` without leaving a half-written file behind
+fn write_keystore(dir: &Path, name: &str, keystore: &Keystore) -> Result<(), String> {
+    let path = dir.join(name);
+    let tmp = dir.join(format!("{}.tmp", name));
+    fs::write(&tmp, hex::encode(keystore.to_bytes()))
+        .and_then(|_| fs::rename(&tmp, &path))
+        .map_err(|e| format!("failed to write {}: {}", path.display(), e))
+}
+
+fn load_or_exit(dir: &str) -> SigningKey {
+    let passphrase = read_line("Enter passphrase: ");
+    match load_key(Path::new(dir), &passphrase) {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("{}: {}", "error".red(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Check the passphrase of the wallet in `dir` and show its address
+pub fn unlock(dir: String, network: Network) {
+    let signing_key = load_or_exit(&dir);
+    let pubkey = signing_key.verifying_key().to_bytes();
+
+    eprintln!("{}", "Wallet unlocked".green());
+    eprintln!("  public:  {}", hex::encode(pubkey));
+    println!("{}", display_address(&pubkey, network).cyan());
+}
+
+/// Print the secret key of the wallet in `dir`
+pub fn decrypt(dir: String) {
+    let signing_key = load_or_exit(&dir);
+
+    eprintln!(
+        "{}",
+        "warning: anyone who sees this key can spend your funds".yellow()
+    );
+    println!("{}", hex::encode(signing_key.to_bytes()));
+}
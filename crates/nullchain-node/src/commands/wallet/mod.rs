@@ -1,5 +1,12 @@
 pub mod address;
+pub mod amount;
 pub mod keygen;
+pub mod keystore;
+pub mod send;
+pub mod tracking;
 
 pub use address::show_address;
-pub use keygen::keygen;
+pub use keygen::{keygen, restore};
+pub use keystore::{decrypt, unlock};
+pub use send::{send, SendOptions};
+pub use tracking::{balance, history, utxos};
@@ -0,0 +1,187 @@
+use super::address::parse_address;
+use super::amount::{format_amount, parse_amount};
+use super::keygen::derive_key;
+use super::keystore::{load_key, load_seed, read_line, SEED_FILE};
+use super::tracking::{exit_on_error, open_synced};
+use colored::Colorize;
+use ed25519_dalek::SigningKey;
+use nullchain_consensus::ChainParams;
+use nullchain_network::{Multiaddr, Network, NetworkConfig, NetworkEvent, Request, Response};
+use nullchain_types::{Hash256, Transaction};
+use nullchain_wallet::{Wallet, WalletKey};
+use std::path::Path;
+use std::time::Duration;
+
+/// How long to wait for the node when submitting
+const SUBMIT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Command-line options of `nullchain wallet send`
+pub struct SendOptions {
+    /// Wallet directory written by keygen
+    pub dir: String,
+    pub datadir: String,
+    pub to: String,
+    /// Decimal NULL amount
+    pub amount: String,
+    /// nanoNULL per byte
+    pub fee_rate: u64,
+    /// Node to submit the transaction to instead of printing it
+    pub submit: Option<String>,
+    pub params: ChainParams,
+}
+
+pub fn send(options: SendOptions) {
+    let to = parse_address(&options.to, options.params.network).unwrap_or_else(|e| exit_with(e));
+    let amount = parse_amount(&options.amount).unwrap_or_else(|e| exit_with(e));
+    let node: Option<Multiaddr> = options.submit.as_ref().map(|addr| {
+        addr.parse()
+            .unwrap_or_else(|e| exit_with(format!("invalid node address {}: {}", addr, e)))
+    });
+
+    let dir = Path::new(&options.dir);
+    let passphrase = read_line("Enter passphrase: ");
+    let seed = dir
+        .join(SEED_FILE)
+        .exists()
+        .then(|| load_seed(dir, &passphrase).unwrap_or_else(|e| exit_with(e)));
+    let seed = seed.as_ref().map(|seed| &seed[..]);
+    let wallet = open_synced(&options.dir, &options.datadir, options.params.network, seed);
+    let keys = signing_keys(&wallet, dir, &passphrase, seed);
+
+    let change = match seed {
+        Some(seed) => fresh_key(&wallet, seed),
+        // Single-key wallets can only send change back to that key
+        None => match exit_on_error(wallet.keys()).first() {
+            Some(key) => *key,
+            None => exit_with("wallet has no keys".to_string()),
+        },
+    };
+
+    let change_address = change.address(options.params.network);
+    let pending = exit_on_error(wallet.create_transaction(
+        &[(to, amount)],
+        options.fee_rate,
+        &change_address,
+    ));
+    let fee = pending.fee;
+    let change_amount = pending
+        .change_index
+        .map(|index| pending.transaction.outputs[index].amount);
+    let tx = exit_on_error(pending.sign(&keys));
+    exit_on_error(wallet.flush());
+
+    eprintln!("{}", "Transaction signed".green());
+    eprintln!("  txid:    {}", tx.txid());
+    eprintln!("  amount:  {}", format_amount(amount));
+    eprintln!("  fee:     {} ({} bytes)", format_amount(fee), tx.size());
+    if let Some(change_amount) = change_amount {
+        eprintln!(
+            "  change:  {} to {}",
+            format_amount(change_amount),
+            change_address
+        );
+    }
+
+    match node {
+        Some(addr) => match submit(addr, tx.clone(), options.params.magic) {
+            Ok(txid) => println!("{}", format!("Submitted {}", txid).green()),
+            Err(e) => exit_with(format!("node rejected transaction: {}", e)),
+        },
+        None => println!(
+            "{}",
+            hex::encode(bincode::serialize(&tx).expect("tx serialization"))
+        ),
+    }
+
+    // Keep the next send off these outputs while the chain has not caught up
+    exit_on_error(wallet.add_pending_spend(&tx));
+    exit_on_error(wallet.flush());
+}
+
+fn exit_with(message: String) -> ! {
+    eprintln!("{}: {}", "error".red(), message);
+    std::process::exit(1);
+}
+
+/// Keys able to sign for the wallet: every derived key if it has a seed
+fn signing_keys(
+    wallet: &Wallet,
+    dir: &Path,
+    passphrase: &str,
+    seed: Option<&[u8]>,
+) -> Vec<SigningKey> {
+    match seed {
+        Some(seed) => exit_on_error(wallet.keys())
+            .iter()
+            .filter_map(|key| key.index)
+            .map(|index| derive_key(seed, index))
+            .collect(),
+        None => vec![load_key(dir, passphrase).unwrap_or_else(|e| exit_with(e))],
+    }
+}
+
+/// Unused derived key for change, deriving a new one when all are used
+///
+/// A new key is only scanned for at the next sync, which is fine: it was
+/// never handed out, so nothing can have paid it.
+fn fresh_key(wallet: &Wallet, seed: &[u8]) -> WalletKey {
+    if let Some(key) = exit_on_error(wallet.fresh_key()) {
+        return key;
+    }
+
+    let index = exit_on_error(wallet.keys())
+        .iter()
+        .filter_map(|key| key.index)
+        .max()
+        .map_or(0, |index| index + 1);
+    let key = WalletKey {
+        public_key: derive_key(seed, index).verifying_key().to_bytes(),
+        index: Some(index),
+    };
+    exit_on_error(wallet.add_key(&key));
+
+    exit_on_error(wallet.fresh_key()).expect("new key is unused")
+}
+
+/// Hand `tx` to the node at `addr`, which must use network `magic`, and
+/// wait for its verdict
+fn submit(addr: Multiaddr, tx: Transaction, magic: [u8; 4]) -> Result<Hash256, String> {
+    let runtime = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
+    runtime.block_on(async {
+        let config = NetworkConfig {
+            listen_addrs: vec![],
+            bootstrap_peers: vec![addr],
+            enable_mdns: false,
+            magic,
+            ..NetworkConfig::default()
+        };
+        let mut network = Network::new(config).map_err(|e| e.to_string())?;
+
+        let exchange = async {
+            let peer = loop {
+                if let NetworkEvent::PeerConnected(peer) = network.next_event().await {
+                    break peer;
+                }
+            };
+
+            let id = network.request(&peer, Request::SubmitTransaction(tx));
+            loop {
+                match network.next_event().await {
+                    NetworkEvent::Response {
+                        request_id,
+                        response: Response::Submitted(result),
+                        ..
+                    } if request_id == id => return result,
+                    NetworkEvent::RequestFailed {
+                        request_id, error, ..
+                    } if request_id == id => return Err(error),
+                    _ => {}
+                }
+            }
+        };
+
+        tokio::time::timeout(SUBMIT_TIMEOUT, exchange)
+            .await
+            .map_err(|_| "timed out waiting for the node".to_string())?
+    })
+}
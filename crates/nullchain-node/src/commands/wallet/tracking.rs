@@ -0,0 +1,209 @@
+use super::address::recipient_address;
+use super::amount::format_amount;
+use super::keygen::derive_key;
+use super::keystore::{PUBLIC_KEY_FILE, SEED_FILE};
+use colored::Colorize;
+use nullchain_storage::{BlockStore, Database};
+use nullchain_types::Network;
+use nullchain_wallet::{Wallet, WalletKey};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory of the wallet databases inside a wallet directory
+pub const WALLET_DB: &str = "wallet.db";
+
+/// Unused keys kept derived past the last used one, so payments to them
+/// are found when scanning
+pub const KEY_LOOKAHEAD: u32 = 20;
+
+/// Database of the wallet in `dir` on `network`
+///
+/// Every network scans its own chain, so each gets its own database and
+/// switching `--network` leaves the others as they were.
+fn wallet_db(dir: &Path, network: Network) -> PathBuf {
+    dir.join(WALLET_DB).join(network.name())
+}
+
+/// Add `keys` to the wallet databases of every network in `dir`
+pub fn register_keys(dir: &Path, keys: &[WalletKey]) -> Result<(), String> {
+    for network in Network::ALL {
+        let wallet = Wallet::open(wallet_db(dir, network)).map_err(|e| e.to_string())?;
+        for key in keys {
+            wallet.add_key(key).map_err(|e| e.to_string())?;
+        }
+        wallet.flush().map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Open the wallet in `dir` for `network` and scan the chain in `datadir`
+/// up to its tip
+///
+/// If the chain database cannot be opened, the wallet is used as last
+/// scanned.
+/// Wallets created before the wallet database existed start out watching
+/// the key in `key.pub`.
+/// Given the `seed`, HD wallets then derive keys until the last
+/// [`KEY_LOOKAHEAD`] are unused and scan again for them; keys added while
+/// the chain cannot be read are scanned for at the next sync.
+pub fn open_synced(dir: &str, datadir: &str, network: Network, seed: Option<&[u8]>) -> Wallet {
+    let dir = Path::new(dir);
+    let opened = Wallet::open(wallet_db(dir, network)).and_then(|wallet| {
+        if wallet.keys()?.is_empty() {
+            if let Some(key) = read_public_key(dir) {
+                wallet.add_key(&key)?;
+            }
+        }
+        Ok(wallet)
+    });
+    let wallet = match opened {
+        Ok(wallet) => wallet,
+        Err(e) => {
+            eprintln!("{}: failed to open wallet: {}", "error".red(), e);
+            std::process::exit(1);
+        }
+    };
+
+    // A running node holds the database lock; the last scan still counts
+    let db = match Database::open(Path::new(datadir)) {
+        Ok(db) => Some(db),
+        Err(e) => {
+            eprintln!(
+                "{}: cannot scan chain ({}); using wallet state as of height {}",
+                "warning".yellow(),
+                e,
+                wallet
+                    .tip()
+                    .ok()
+                    .flatten()
+                    .map_or("none".to_string(), |tip| tip.height.to_string())
+            );
+            None
+        }
+    };
+
+    loop {
+        if let Some(db) = &db {
+            sync(&wallet, &BlockStore::new(db));
+        }
+
+        let missing = exit_on_error(wallet.missing_lookahead(KEY_LOOKAHEAD));
+        if missing.is_empty() {
+            break;
+        }
+        let Some(seed) = seed else {
+            tracing::debug!("{} wallet keys left to derive", missing.len());
+            break;
+        };
+
+        eprintln!("Deriving keys {} to {}", missing.start, missing.end - 1);
+        for index in missing {
+            let key = WalletKey {
+                public_key: derive_key(seed, index).verifying_key().to_bytes(),
+                index: Some(index),
+            };
+            exit_on_error(wallet.add_key(&key));
+        }
+        if db.is_none() {
+            exit_on_error(wallet.flush());
+            break;
+        }
+    }
+
+    wallet
+}
+
+fn sync(wallet: &Wallet, store: &BlockStore) {
+    let synced = wallet.sync(store).and_then(|report| {
+        wallet.flush()?;
+        Ok(report)
+    });
+    match synced {
+        Ok(report) => {
+            if report.disconnected > 0 {
+                eprintln!("Rolled back {} blocks", report.disconnected);
+            }
+            if report.connected > 0 {
+                eprintln!("Scanned {} blocks", report.connected);
+            }
+        }
+        Err(e) => {
+            eprintln!("{}: failed to scan chain: {}", "error".red(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn read_public_key(dir: &Path) -> Option<WalletKey> {
+    let contents = fs::read_to_string(dir.join(PUBLIC_KEY_FILE)).ok()?;
+    let public_key = hex::decode(contents.trim()).ok()?.try_into().ok()?;
+    Some(WalletKey {
+        public_key,
+        // keygen writes the first derived key to key.pub
+        index: dir.join(SEED_FILE).exists().then_some(0),
+    })
+}
+
+pub fn exit_on_error<T>(result: nullchain_wallet::Result<T>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}: {}", "error".red(), e);
+        std::process::exit(1);
+    })
+}
+
+pub fn balance(dir: String, datadir: String, network: Network) {
+    let wallet = open_synced(&dir, &datadir, network, None);
+    let balance = exit_on_error(wallet.balance());
+    let tip = exit_on_error(wallet.tip());
+
+    eprintln!(
+        "Wallet balance at height {}",
+        tip.map_or("none".to_string(), |tip| tip.height.to_string())
+    );
+    println!("  spendable: {}", format_amount(balance.spendable).green());
+    println!("  immature:  {}", format_amount(balance.immature));
+    println!("  total:     {}", format_amount(balance.total()));
+}
+
+pub fn utxos(dir: String, datadir: String, network: Network) {
+    let wallet = open_synced(&dir, &datadir, network, None);
+    let next_height = exit_on_error(wallet.tip()).map_or(0, |tip| tip.height + 1);
+    let utxos = exit_on_error(wallet.utxos());
+
+    if utxos.is_empty() {
+        eprintln!("{}", "No unspent outputs".dimmed());
+    }
+    for utxo in utxos {
+        let maturity = if utxo.is_mature(next_height) {
+            String::new()
+        } else {
+            format!(" {}", "(immature)".yellow())
+        };
+        println!(
+            "{}:{}  {}  height {}  {}{}",
+            utxo.outpoint.txid,
+            utxo.outpoint.index,
+            format_amount(utxo.output.amount),
+            utxo.height,
+            recipient_address(&utxo.output.recipient, network),
+            maturity
+        );
+    }
+}
+
+pub fn history(dir: String, datadir: String, network: Network) {
+    let wallet = open_synced(&dir, &datadir, network, None);
+    let history = exit_on_error(wallet.history());
+
+    if history.is_empty() {
+        eprintln!("{}", "No transactions".dimmed());
+    }
+    for entry in history {
+        let net = if entry.received >= entry.sent {
+            format!("+{}", format_amount(entry.received - entry.sent)).green()
+        } else {
+            format!("-{}", format_amount(entry.sent - entry.received)).red()
+        };
+        println!("{:>8}  {}  {}", entry.height, entry.txid, net);
+    }
+}
@@ -1,14 +1,12 @@
 use clap::{Parser, Subcommand};
+use nullchain_consensus::ChainParams;
+use nullchain_types::Network;
 
 mod commands;
 
-fn parse_hex_or_decimal(s: &str) -> Result<u32, String> {
-    if let Some(hex_str) = s.strip_prefix("0x") {
-        u32::from_str_radix(hex_str, 16).map_err(|e| format!("invalid hex: {}", e))
-    } else {
-        s.parse::<u32>()
-            .map_err(|e| format!("invalid number: {}", e))
-    }
+/// One mining thread per available CPU
+fn default_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
 }
 
 #[derive(Parser)]
@@ -16,6 +14,10 @@ fn parse_hex_or_decimal(s: &str) -> Result<u32, String> {
 #[command(version)]
 #[command(about = "Privacy-first blockchain node", long_about = None)]
 struct Cli {
+    /// Chain to use: mainnet, testnet or regtest
+    #[arg(long, global = true, default_value_t = Network::Testnet)]
+    network: Network,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -26,6 +28,14 @@ enum Commands {
     Keygen {
         #[arg(short, long)]
         output: Option<String>,
+
+        /// Derive the key from a new mnemonic of this many words
+        #[arg(short, long, num_args = 0..=1, default_missing_value = "24")]
+        mnemonic: Option<usize>,
+
+        /// Overwrite an existing wallet in the output directory
+        #[arg(long)]
+        force: bool,
     },
 
     /// Derive address from public key
@@ -34,15 +44,30 @@ enum Commands {
         pubkey: String,
     },
 
-    /// Mine new block
+    /// Mine blocks on top of the stored chain
     Mine {
+        /// Give up on a block after this many hashes
         #[arg(short, long)]
         iterations: Option<u64>,
 
-        #[arg(short, long, default_value = "0x1f0fffff", value_parser = parse_hex_or_decimal)]
-        bits: u32,
+        /// Address receiving the block reward
+        #[arg(short, long)]
+        address: Option<String>,
+
+        /// Mining threads
+        #[arg(short, long, default_value_t = default_threads())]
+        threads: usize,
 
-        #[arg(short, long, default_value = ".nullchain")]
+        /// Number of blocks to mine
+        #[arg(short, long, default_value_t = 1)]
+        count: u64,
+
+        /// Mine until interrupted with Ctrl-C
+        #[arg(long, conflicts_with = "count")]
+        forever: bool,
+
+        /// Chain database [default: .nullchain/<network>]
+        #[arg(short, long)]
         datadir: Option<String>,
     },
 
@@ -58,19 +83,174 @@ enum Commands {
     /// Show version information
     Version,
 
+    /// Manage an encrypted wallet
+    Wallet {
+        #[command(subcommand)]
+        command: WalletCommand,
+    },
+
     /// Show chain information
     Chain {
-        #[arg(short, long, default_value = ".nullchain")]
-        datadir: String,
+        /// Chain database [default: .nullchain/<network>]
+        #[arg(short, long)]
+        datadir: Option<String>,
+    },
+
+    /// Run a full node
+    Node {
+        /// Chain database [default: .nullchain/<network>]
+        #[arg(short, long)]
+        datadir: Option<String>,
+
+        /// Address to listen on (repeatable), e.g. /ip4/0.0.0.0/tcp/9333
+        #[arg(short, long)]
+        listen: Vec<String>,
+
+        /// Peer to connect to on startup (repeatable)
+        #[arg(short, long)]
+        peer: Vec<String>,
+
+        /// Disable local peer discovery via mDNS
+        #[arg(long)]
+        no_mdns: bool,
+
+        /// Mine blocks paying this address
+        #[arg(long)]
+        mine: Option<String>,
+
+        /// Mining threads
+        #[arg(long, default_value_t = default_threads())]
+        threads: usize,
+
+        /// Maintain a txid index for get-tx (stays enabled once built)
+        #[arg(long)]
+        txindex: bool,
     },
 
     /// Get block at height
     GetBlock {
-        #[arg(short, long, default_value = ".nullchain")]
-        datadir: String,
+        /// Chain database [default: .nullchain/<network>]
+        #[arg(short, long)]
+        datadir: Option<String>,
 
         height: u64,
     },
+
+    /// Prove a transaction is included in a block
+    GetTxproof {
+        /// Chain database [default: .nullchain/<network>]
+        #[arg(short, long)]
+        datadir: Option<String>,
+
+        /// Only look in the block at this height
+        #[arg(long)]
+        height: Option<u64>,
+
+        /// Transaction id (hex)
+        txid: String,
+    },
+
+    /// Show a main-chain transaction and its confirmations
+    GetTx {
+        /// Chain database [default: .nullchain/<network>]
+        #[arg(short, long)]
+        datadir: Option<String>,
+
+        /// Transaction id (hex)
+        txid: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum WalletCommand {
+    /// Check the passphrase and show the wallet address
+    Unlock {
+        /// Wallet directory written by keygen
+        #[arg(short, long)]
+        dir: String,
+    },
+
+    /// Print the decrypted secret key
+    Decrypt {
+        /// Wallet directory written by keygen
+        #[arg(short, long)]
+        dir: String,
+    },
+
+    /// Recreate a wallet from its mnemonic
+    Restore {
+        /// Directory to write the wallet to
+        #[arg(short, long)]
+        dir: String,
+
+        /// Chain database to scan for the wallet's keys [default: .nullchain/<network>]
+        #[arg(long)]
+        datadir: Option<String>,
+
+        /// Overwrite an existing wallet in the directory
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Show spendable and immature funds
+    Balance {
+        /// Wallet directory written by keygen
+        #[arg(short, long)]
+        dir: String,
+
+        /// Chain database [default: .nullchain/<network>]
+        #[arg(long)]
+        datadir: Option<String>,
+    },
+
+    /// List unspent outputs owned by the wallet
+    Utxos {
+        /// Wallet directory written by keygen
+        #[arg(short, long)]
+        dir: String,
+
+        /// Chain database [default: .nullchain/<network>]
+        #[arg(long)]
+        datadir: Option<String>,
+    },
+
+    /// List transactions paying to or spending from the wallet
+    History {
+        /// Wallet directory written by keygen
+        #[arg(short, long)]
+        dir: String,
+
+        /// Chain database [default: .nullchain/<network>]
+        #[arg(long)]
+        datadir: Option<String>,
+    },
+
+    /// Build and sign a payment, printing it hex-encoded or submitting it
+    Send {
+        /// Wallet directory written by keygen
+        #[arg(short, long)]
+        dir: String,
+
+        /// Chain database [default: .nullchain/<network>]
+        #[arg(long)]
+        datadir: Option<String>,
+
+        /// Recipient address
+        #[arg(short, long)]
+        to: String,
+
+        /// Amount in NULL, e.g. 12.5
+        #[arg(short, long)]
+        amount: String,
+
+        /// Fee in nanoNULL per byte
+        #[arg(short, long, default_value_t = 1)]
+        fee_rate: u64,
+
+        /// Submit to the node listening on this address
+        #[arg(short, long)]
+        submit: Option<String>,
+    },
 }
 
 fn main() {
@@ -81,19 +261,93 @@ fn main() {
         .init();
 
     let cli = Cli::parse();
+    let params = ChainParams::for_network(cli.network);
+    let data_dir = |datadir: Option<String>| datadir.unwrap_or_else(|| params.data_dir.to_string());
 
     match cli.command {
-        Commands::Keygen { output } => commands::wallet::keygen(output),
-        Commands::Address { pubkey } => commands::wallet::show_address(pubkey),
+        Commands::Keygen {
+            output,
+            mnemonic,
+            force,
+        } => commands::wallet::keygen(output, mnemonic, force),
+        Commands::Address { pubkey } => commands::wallet::show_address(pubkey, params.network),
         Commands::Mine {
             iterations,
-            bits,
+            address,
+            threads,
+            count,
+            forever,
             datadir,
-        } => commands::mine(iterations, bits, datadir),
-        Commands::Genesis => commands::genesis(),
+        } => commands::mine(commands::MineOptions {
+            iterations,
+            address,
+            threads,
+            count: (!forever).then_some(count),
+            datadir: data_dir(datadir),
+            params: params.clone(),
+        }),
+        Commands::Genesis => commands::genesis(&params),
         Commands::Info { json } => commands::info(&json),
-        Commands::Version => commands::version(),
-        Commands::Chain { datadir } => commands::chain_info(datadir),
-        Commands::GetBlock { datadir, height } => commands::get_block(datadir, height),
+        Commands::Version => commands::version(&params),
+        Commands::Wallet { command } => match command {
+            WalletCommand::Unlock { dir } => commands::wallet::unlock(dir, params.network),
+            WalletCommand::Decrypt { dir } => commands::wallet::decrypt(dir),
+            WalletCommand::Restore {
+                dir,
+                datadir,
+                force,
+            } => commands::wallet::restore(dir, data_dir(datadir), params.network, force),
+            WalletCommand::Balance { dir, datadir } => {
+                commands::wallet::balance(dir, data_dir(datadir), params.network)
+            }
+            WalletCommand::Utxos { dir, datadir } => {
+                commands::wallet::utxos(dir, data_dir(datadir), params.network)
+            }
+            WalletCommand::History { dir, datadir } => {
+                commands::wallet::history(dir, data_dir(datadir), params.network)
+            }
+            WalletCommand::Send {
+                dir,
+                datadir,
+                to,
+                amount,
+                fee_rate,
+                submit,
+            } => commands::wallet::send(commands::wallet::SendOptions {
+                dir,
+                datadir: data_dir(datadir),
+                to,
+                amount,
+                fee_rate,
+                submit,
+                params: params.clone(),
+            }),
+        },
+        Commands::Chain { datadir } => commands::chain_info(data_dir(datadir)),
+        Commands::GetBlock { datadir, height } => commands::get_block(data_dir(datadir), height),
+        Commands::GetTxproof {
+            datadir,
+            height,
+            txid,
+        } => commands::get_txproof(data_dir(datadir), txid, height, &params),
+        Commands::GetTx { datadir, txid } => commands::get_tx(data_dir(datadir), txid, &params),
+        Commands::Node {
+            datadir,
+            listen,
+            peer,
+            no_mdns,
+            mine,
+            threads,
+            txindex,
+        } => commands::node(commands::NodeOptions {
+            datadir: data_dir(datadir),
+            listen,
+            peers: peer,
+            no_mdns,
+            miner_address: mine,
+            miner_threads: threads,
+            txindex,
+            params: params.clone(),
+        }),
     }
 }
@@ -1,11 +1,12 @@
 use crate::{Database, Result, StorageError};
 use nullchain_types::{Block, Hash256};
+use rocksdb::WriteBatch;
 
 const KEY_BEST: &[u8] = b"best";
 const KEY_HEIGHT: &[u8] = b"height";
 
 pub struct BlockStore<'a> {
-    db: &'a Database,
+    pub(crate) db: &'a Database,
 }
 
 impl<'a> BlockStore<'a> {
@@ -15,19 +16,38 @@ impl<'a> BlockStore<'a> {
 
     /// Store block at height
     pub fn put(&self, height: u64, block: &Block) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        Self::batch_put(&mut batch, height, block)?;
+        self.db.inner().write(batch)?;
+        Ok(())
+    }
+
+    /// Store block data by hash only, without making it part of the main chain
+    pub fn put_data(&self, block: &Block) -> Result<()> {
+        let data = bincode::serialize(block)?;
+        self.db.inner().put(block.hash().as_bytes(), &data)?;
+        Ok(())
+    }
+
+    /// Queue the writes of [`BlockStore::put`] into `batch`
+    pub(crate) fn batch_put(batch: &mut WriteBatch, height: u64, block: &Block) -> Result<()> {
         let hash = block.hash();
         let block_data = bincode::serialize(block)?;
 
         // Store block by hash
-        self.db.inner().put(hash.as_bytes(), &block_data)?;
+        batch.put(hash.as_bytes(), &block_data);
 
         // Index by height
-        let height_key = height.to_be_bytes();
-        self.db.inner().put(height_key, hash.as_bytes())?;
+        batch.put(height.to_be_bytes(), hash.as_bytes());
 
         Ok(())
     }
 
+    /// Queue removal of the height index entry into `batch`
+    pub(crate) fn batch_remove_height(batch: &mut WriteBatch, height: u64) {
+        batch.delete(height.to_be_bytes());
+    }
+
     /// Get block by hash
     pub fn get_by_hash(&self, hash: &Hash256) -> Result<Block> {
         match self.db.inner().get(hash.as_bytes())? {
@@ -38,31 +58,42 @@ impl<'a> BlockStore<'a> {
 
     /// Get block by height
     pub fn get_by_height(&self, height: u64) -> Result<Block> {
-        let height_key = height.to_be_bytes();
-
-        let hash_bytes = self
-            .db
-            .inner()
-            .get(height_key)?
+        let hash = self
+            .get_hash_by_height(height)?
             .ok_or(StorageError::InvalidHeight)?;
 
-        let hash = Hash256::from_bytes(
-            hash_bytes
-                .as_slice()
-                .try_into()
-                .map_err(|_| StorageError::InvalidHeight)?,
-        );
-
         self.get_by_hash(&hash)
     }
 
+    /// Get hash of the main-chain block at height
+    pub fn get_hash_by_height(&self, height: u64) -> Result<Option<Hash256>> {
+        let height_key = height.to_be_bytes();
+
+        match self.db.inner().get(height_key)? {
+            Some(hash_bytes) => Ok(Some(Hash256::from_bytes(
+                hash_bytes
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| StorageError::InvalidHeight)?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
     /// Update best block
     pub fn set_best(&self, height: u64, hash: &Hash256) -> Result<()> {
-        self.db.inner().put(KEY_BEST, hash.as_bytes())?;
-        self.db.inner().put(KEY_HEIGHT, height.to_be_bytes())?;
+        let mut batch = WriteBatch::default();
+        Self::batch_set_best(&mut batch, height, hash);
+        self.db.inner().write(batch)?;
         Ok(())
     }
 
+    /// Queue the writes of [`BlockStore::set_best`] into `batch`
+    pub(crate) fn batch_set_best(batch: &mut WriteBatch, height: u64, hash: &Hash256) {
+        batch.put(KEY_BEST, hash.as_bytes());
+        batch.put(KEY_HEIGHT, height.to_be_bytes());
+    }
+
     /// Get best block hash
     pub fn get_best(&self) -> Result<Option<Hash256>> {
         match self.db.inner().get(KEY_BEST)? {
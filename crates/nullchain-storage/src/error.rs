@@ -16,6 +16,12 @@ pub enum StorageError {
 
     #[error("invalid height")]
     InvalidHeight,
+
+    #[error("block {0} is not the chain tip")]
+    NotTip(String),
+
+    #[error("undo data not found for block {0}")]
+    UndoNotFound(String),
 }
 
 pub type Result<T> = std::result::Result<T, StorageError>;
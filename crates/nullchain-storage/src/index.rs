@@ -0,0 +1,132 @@
+//! Block index: one entry per known block header, on any branch
+
+use crate::{BlockStore, Result, StorageError};
+use nullchain_types::{BlockHeader, Hash256, U256};
+use serde::{Deserialize, Serialize};
+
+const INDEX_PREFIX: &[u8] = b"index:";
+const KEY_BEST_HEADER: &[u8] = b"best_header";
+
+/// Validation state of an indexed block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockStatus {
+    /// Block data is stored and passed context-free checks
+    HaveData,
+    /// Block (or one of its ancestors) failed validation
+    Invalid,
+    /// Only the header is known; the block body still has to be downloaded
+    HeaderOnly,
+}
+
+/// Index entry for a block header
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockIndexEntry {
+    pub header: BlockHeader,
+    pub height: u64,
+    /// Total work of the chain ending at this block
+    pub chain_work: U256,
+    pub status: BlockStatus,
+}
+
+impl BlockIndexEntry {
+    pub fn hash(&self) -> Hash256 {
+        self.header.hash()
+    }
+
+    pub fn parent(&self) -> Hash256 {
+        self.header.previous_block
+    }
+}
+
+fn index_key(hash: &Hash256) -> Vec<u8> {
+    let mut key = Vec::with_capacity(INDEX_PREFIX.len() + 32);
+    key.extend_from_slice(INDEX_PREFIX);
+    key.extend_from_slice(hash.as_bytes());
+    key
+}
+
+impl BlockStore<'_> {
+    /// Insert or update an index entry
+    pub fn put_index(&self, entry: &BlockIndexEntry) -> Result<()> {
+        let data = bincode::serialize(entry)?;
+        self.db.inner().put(index_key(&entry.hash()), data)?;
+        Ok(())
+    }
+
+    /// Get index entry by block hash
+    pub fn get_index(&self, hash: &Hash256) -> Result<Option<BlockIndexEntry>> {
+        match self.db.inner().get(index_key(hash))? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Record the header with the most work seen so far
+    pub fn set_best_header(&self, hash: &Hash256) -> Result<()> {
+        self.db.inner().put(KEY_BEST_HEADER, hash.as_bytes())?;
+        Ok(())
+    }
+
+    /// Hash of the header with the most work seen so far
+    pub fn get_best_header(&self) -> Result<Option<Hash256>> {
+        match self.db.inner().get(KEY_BEST_HEADER)? {
+            Some(data) => {
+                let bytes: [u8; 32] = data
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| StorageError::BlockNotFound("best_header".to_string()))?;
+                Ok(Some(Hash256::from_bytes(bytes)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Database;
+    use nullchain_types::Block;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_index_roundtrip() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let store = BlockStore::new(&db);
+
+        let genesis = Block::genesis();
+        assert!(store.get_index(&genesis.hash()).unwrap().is_none());
+
+        let mut entry = BlockIndexEntry {
+            header: genesis.header.clone(),
+            height: 0,
+            chain_work: U256::from_u64(42),
+            status: BlockStatus::HaveData,
+        };
+        store.put_index(&entry).unwrap();
+
+        let stored = store.get_index(&genesis.hash()).unwrap().unwrap();
+        assert_eq!(stored.height, 0);
+        assert_eq!(stored.chain_work, U256::from_u64(42));
+        assert_eq!(stored.status, BlockStatus::HaveData);
+
+        entry.status = BlockStatus::Invalid;
+        store.put_index(&entry).unwrap();
+        let stored = store.get_index(&genesis.hash()).unwrap().unwrap();
+        assert_eq!(stored.status, BlockStatus::Invalid);
+    }
+
+    #[test]
+    fn test_best_header() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let store = BlockStore::new(&db);
+
+        assert_eq!(store.get_best_header().unwrap(), None);
+
+        let hash = Block::genesis().hash();
+        store.set_best_header(&hash).unwrap();
+        assert_eq!(store.get_best_header().unwrap(), Some(hash));
+    }
+}
@@ -2,16 +2,23 @@
 //!
 //! Provides persistent storage for:
 //! - Blocks (by height and hash)
+//! - Block index (parent, height and cumulative work of every known block)
 //! - UTXO set (unspent transaction outputs)
 //! - Chain state (best block, height)
+//! - Undo data for disconnecting blocks
+//! - Optional transaction index (txid to block and position)
 
 mod blockstore;
 mod error;
+mod index;
+mod txindex;
 mod utxo;
 
 pub use blockstore::BlockStore;
 pub use error::{Result, StorageError};
-pub use utxo::UtxoSet;
+pub use index::{BlockIndexEntry, BlockStatus};
+pub use txindex::TxLocation;
+pub use utxo::{BlockUndo, UtxoSet};
 
 use rocksdb::{Options, DB};
 use std::path::Path;
@@ -36,6 +43,12 @@ impl Database {
     pub fn inner(&self) -> &DB {
         &self.db
     }
+
+    /// Flush memtables to disk
+    pub fn flush(&self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,182 @@
+//! Optional transaction index: where each main-chain transaction is stored
+//!
+//! Disabled by default. Once enabled with [`BlockStore::enable_tx_index`] the
+//! index is kept up to date by [`UtxoSet::apply_block`] and
+//! [`UtxoSet::disconnect_block`] in the same write as the block itself.
+//!
+//! [`UtxoSet::apply_block`]: crate::UtxoSet::apply_block
+//! [`UtxoSet::disconnect_block`]: crate::UtxoSet::disconnect_block
+
+use crate::{BlockStore, Result};
+use nullchain_types::{Block, Hash256};
+use rocksdb::WriteBatch;
+use serde::{Deserialize, Serialize};
+
+const TX_PREFIX: &[u8] = b"tx:";
+const KEY_TX_INDEX: &[u8] = b"txindex";
+
+/// Position of a transaction in a main-chain block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxLocation {
+    /// Hash of the block containing the transaction
+    pub block: Hash256,
+    /// Index of the transaction within the block
+    pub position: u32,
+}
+
+fn tx_key(txid: &Hash256) -> Vec<u8> {
+    let mut key = Vec::with_capacity(TX_PREFIX.len() + 32);
+    key.extend_from_slice(TX_PREFIX);
+    key.extend_from_slice(txid.as_bytes());
+    key
+}
+
+impl BlockStore<'_> {
+    /// Whether the transaction index is maintained
+    pub fn tx_index_enabled(&self) -> Result<bool> {
+        Ok(self.db.inner().get(KEY_TX_INDEX)?.is_some())
+    }
+
+    /// Start maintaining the transaction index, indexing the current main chain
+    ///
+    /// Does nothing if the index is already enabled.
+    pub fn enable_tx_index(&self) -> Result<()> {
+        if self.tx_index_enabled()? {
+            return Ok(());
+        }
+
+        let mut batch = WriteBatch::default();
+        if self.get_best()?.is_some() {
+            for height in 0..=self.get_height()? {
+                Self::batch_index_block(&mut batch, &self.get_by_height(height)?)?;
+            }
+        }
+        batch.put(KEY_TX_INDEX, []);
+
+        self.db.inner().write(batch)?;
+        Ok(())
+    }
+
+    /// Look up where a main-chain transaction is stored
+    ///
+    /// Always `None` while the index is disabled.
+    pub fn get_tx_location(&self, txid: &Hash256) -> Result<Option<TxLocation>> {
+        match self.db.inner().get(tx_key(txid))? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Queue index entries for every transaction of `block` into `batch`
+    pub(crate) fn batch_index_block(batch: &mut WriteBatch, block: &Block) -> Result<()> {
+        let hash = block.hash();
+        for (position, tx) in block.transactions.iter().enumerate() {
+            let location = TxLocation {
+                block: hash,
+                position: position as u32,
+            };
+            batch.put(tx_key(&tx.txid()), bincode::serialize(&location)?);
+        }
+        Ok(())
+    }
+
+    /// Queue removal of the index entries of `block` into `batch`
+    pub(crate) fn batch_unindex_block(batch: &mut WriteBatch, block: &Block) {
+        for tx in &block.transactions {
+            batch.delete(tx_key(&tx.txid()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Database, UtxoSet};
+    use nullchain_test_utils::child_block;
+    use nullchain_types::Transaction;
+    use tempfile::TempDir;
+
+    fn child(parent: &Block, height: u64) -> Block {
+        child_block(
+            parent,
+            vec![
+                Transaction::coinbase(vec![1u8; 20], 50, height),
+                Transaction::coinbase(vec![2u8; 20], 50, height),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_disabled_by_default() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let store = BlockStore::new(&db);
+
+        let genesis = Block::genesis();
+        UtxoSet::new(&db).apply_block(0, &genesis).unwrap();
+
+        assert!(!store.tx_index_enabled().unwrap());
+        let txid = genesis.transactions[0].txid();
+        assert_eq!(store.get_tx_location(&txid).unwrap(), None);
+    }
+
+    #[test]
+    fn test_enable_indexes_existing_chain() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let store = BlockStore::new(&db);
+        let utxo = UtxoSet::new(&db);
+
+        let genesis = Block::genesis();
+        let block = child(&genesis, 1);
+        utxo.apply_block(0, &genesis).unwrap();
+        utxo.apply_block(1, &block).unwrap();
+
+        store.enable_tx_index().unwrap();
+        assert!(store.tx_index_enabled().unwrap());
+
+        let location = store
+            .get_tx_location(&block.transactions[1].txid())
+            .unwrap()
+            .unwrap();
+        assert_eq!(location.block, block.hash());
+        assert_eq!(location.position, 1);
+
+        let location = store
+            .get_tx_location(&genesis.transactions[0].txid())
+            .unwrap()
+            .unwrap();
+        assert_eq!(location.block, genesis.hash());
+    }
+
+    #[test]
+    fn test_index_follows_connect_and_disconnect() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let store = BlockStore::new(&db);
+        let utxo = UtxoSet::new(&db);
+
+        store.enable_tx_index().unwrap();
+
+        let genesis = Block::genesis();
+        let block = child(&genesis, 1);
+        utxo.apply_block(0, &genesis).unwrap();
+        utxo.apply_block(1, &block).unwrap();
+
+        let txid = block.transactions[0].txid();
+        assert_eq!(
+            store.get_tx_location(&txid).unwrap(),
+            Some(TxLocation {
+                block: block.hash(),
+                position: 0,
+            })
+        );
+
+        utxo.disconnect_block(&block).unwrap();
+        assert_eq!(store.get_tx_location(&txid).unwrap(), None);
+        assert!(store
+            .get_tx_location(&genesis.transactions[0].txid())
+            .unwrap()
+            .is_some());
+    }
+}
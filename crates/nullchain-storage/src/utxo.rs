@@ -1,38 +1,23 @@
-use crate::{Database, Result};
-use nullchain_types::{Hash256, TransactionOutput};
-
-/// Outpoint (transaction output reference)
-#[derive(Debug, Clone)]
-pub struct OutPoint {
-    pub txid: Hash256,
-    pub index: u32,
-}
-
-impl OutPoint {
-    pub fn to_key(&self) -> Vec<u8> {
-        let mut key = Vec::with_capacity(36);
-        key.extend_from_slice(self.txid.as_bytes());
-        key.extend_from_slice(&self.index.to_be_bytes());
-        key
-    }
-
-    #[allow(dead_code)]
-    pub fn from_key(key: &[u8]) -> Option<Self> {
-        if key.len() != 36 {
-            return None;
-        }
+use crate::{BlockStore, Database, Result, StorageError};
+use nullchain_types::{Block, Hash256, OutPoint, UtxoEntry};
+use rocksdb::WriteBatch;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
-        let mut txid_bytes = [0u8; 32];
-        txid_bytes.copy_from_slice(&key[0..32]);
+const UNDO_PREFIX: &[u8] = b"undo:";
 
-        let mut index_bytes = [0u8; 4];
-        index_bytes.copy_from_slice(&key[32..36]);
+/// Outputs spent by a block, needed to disconnect it again
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BlockUndo {
+    /// Spent outputs in the order they were spent
+    pub spent: Vec<(OutPoint, UtxoEntry)>,
+}
 
-        Some(Self {
-            txid: Hash256::from_bytes(txid_bytes),
-            index: u32::from_be_bytes(index_bytes),
-        })
-    }
+fn undo_key(hash: &Hash256) -> Vec<u8> {
+    let mut key = Vec::with_capacity(UNDO_PREFIX.len() + 32);
+    key.extend_from_slice(UNDO_PREFIX);
+    key.extend_from_slice(hash.as_bytes());
+    key
 }
 
 pub struct UtxoSet<'a> {
@@ -45,9 +30,9 @@ impl<'a> UtxoSet<'a> {
     }
 
     /// Add UTXO
-    pub fn add(&self, outpoint: &OutPoint, output: &TransactionOutput) -> Result<()> {
+    pub fn add(&self, outpoint: &OutPoint, entry: &UtxoEntry) -> Result<()> {
         let key = outpoint.to_key();
-        let value = bincode::serialize(output)?;
+        let value = bincode::serialize(entry)?;
         self.db.inner().put(&key, &value)?;
         Ok(())
     }
@@ -60,7 +45,7 @@ impl<'a> UtxoSet<'a> {
     }
 
     /// Get UTXO
-    pub fn get(&self, outpoint: &OutPoint) -> Result<Option<TransactionOutput>> {
+    pub fn get(&self, outpoint: &OutPoint) -> Result<Option<UtxoEntry>> {
         let key = outpoint.to_key();
         match self.db.inner().get(&key)? {
             Some(data) => Ok(Some(bincode::deserialize(&data)?)),
@@ -73,11 +58,120 @@ impl<'a> UtxoSet<'a> {
         let key = outpoint.to_key();
         Ok(self.db.inner().get(&key)?.is_some())
     }
+
+    /// Connect a block as the new tip at `height`
+    ///
+    /// Spends its inputs, creates its outputs, stores the block, its undo
+    /// data, the new best block and (if enabled) its transaction index
+    /// entries in a single atomic write. The block must already have passed
+    /// consensus validation.
+    pub fn apply_block(&self, height: u64, block: &Block) -> Result<()> {
+        let store = BlockStore::new(self.db);
+        let hash = block.hash();
+        let mut batch = WriteBatch::default();
+        let mut created: HashMap<OutPoint, UtxoEntry> = HashMap::new();
+        let mut undo = BlockUndo::default();
+
+        for tx in &block.transactions {
+            if !tx.is_coinbase() {
+                for input in &tx.inputs {
+                    let outpoint = input.outpoint();
+
+                    // Outputs created earlier in this block never hit the
+                    // database, so they need no undo entry
+                    if created.remove(&outpoint).is_none() {
+                        let entry = self.get(&outpoint)?.ok_or(StorageError::UtxoNotFound)?;
+                        undo.spent.push((outpoint, entry));
+                    }
+                    batch.delete(outpoint.to_key());
+                }
+            }
+
+            let txid = tx.txid();
+            for (index, output) in tx.outputs.iter().enumerate() {
+                let outpoint = OutPoint {
+                    txid,
+                    index: index as u32,
+                };
+                let entry = UtxoEntry {
+                    output: output.clone(),
+                    height,
+                    coinbase: tx.is_coinbase(),
+                };
+                batch.put(outpoint.to_key(), bincode::serialize(&entry)?);
+                created.insert(outpoint, entry);
+            }
+        }
+
+        batch.put(undo_key(&hash), bincode::serialize(&undo)?);
+        BlockStore::batch_put(&mut batch, height, block)?;
+        BlockStore::batch_set_best(&mut batch, height, &hash);
+        if store.tx_index_enabled()? {
+            BlockStore::batch_index_block(&mut batch, block)?;
+        }
+
+        self.db.inner().write(batch)?;
+        Ok(())
+    }
+
+    /// Disconnect the current tip, restoring the outputs it spent
+    ///
+    /// The block data itself is kept so it can be reconnected later.
+    pub fn disconnect_block(&self, block: &Block) -> Result<()> {
+        let store = BlockStore::new(self.db);
+        let hash = block.hash();
+
+        if store.get_best()? != Some(hash) {
+            return Err(StorageError::NotTip(hash.to_string()));
+        }
+        let height = store.get_height()?;
+        if height == 0 {
+            return Err(StorageError::InvalidHeight);
+        }
+
+        let undo = self.get_undo(&hash)?;
+        let mut batch = WriteBatch::default();
+
+        for tx in block.transactions.iter().rev() {
+            let txid = tx.txid();
+            for index in 0..tx.outputs.len() {
+                let outpoint = OutPoint {
+                    txid,
+                    index: index as u32,
+                };
+                batch.delete(outpoint.to_key());
+            }
+        }
+
+        for (outpoint, entry) in undo.spent.iter().rev() {
+            batch.put(outpoint.to_key(), bincode::serialize(entry)?);
+        }
+
+        batch.delete(undo_key(&hash));
+        BlockStore::batch_remove_height(&mut batch, height);
+        BlockStore::batch_set_best(&mut batch, height - 1, &block.header.previous_block);
+        if store.tx_index_enabled()? {
+            BlockStore::batch_unindex_block(&mut batch, block);
+        }
+
+        self.db.inner().write(batch)?;
+        Ok(())
+    }
+
+    /// Get the undo data recorded when `hash` was connected
+    pub fn get_undo(&self, hash: &Hash256) -> Result<BlockUndo> {
+        match self.db.inner().get(undo_key(hash))? {
+            Some(data) => Ok(bincode::deserialize(&data)?),
+            None => Err(StorageError::UndoNotFound(hash.to_string())),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use nullchain_test_utils::{child_block, first_output, owned_output, spend};
+    use nullchain_types::{Transaction, TransactionOutput};
     use tempfile::TempDir;
 
     #[test]
@@ -91,20 +185,122 @@ mod tests {
             index: 0,
         };
 
-        let output = TransactionOutput {
-            amount: 100,
-            recipient: vec![0u8; 20],
+        let entry = UtxoEntry {
+            output: TransactionOutput {
+                amount: 100,
+                recipient: vec![0u8; 20],
+            },
+            height: 3,
+            coinbase: false,
         };
 
         assert!(!utxo.exists(&outpoint).unwrap());
 
-        utxo.add(&outpoint, &output).unwrap();
+        utxo.add(&outpoint, &entry).unwrap();
         assert!(utxo.exists(&outpoint).unwrap());
 
         let retrieved = utxo.get(&outpoint).unwrap().unwrap();
-        assert_eq!(retrieved.amount, 100);
+        assert_eq!(retrieved.output.amount, 100);
+        assert_eq!(retrieved.height, 3);
 
         utxo.remove(&outpoint).unwrap();
         assert!(!utxo.exists(&outpoint).unwrap());
     }
+
+    #[test]
+    fn test_apply_and_disconnect_block() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let utxo = UtxoSet::new(&db);
+        let store = BlockStore::new(&db);
+
+        let genesis = Block::genesis();
+        utxo.apply_block(0, &genesis).unwrap();
+        let genesis_out = first_output(&genesis.transactions[0]);
+        assert!(utxo.exists(&genesis_out).unwrap());
+        assert_eq!(store.get_best().unwrap(), Some(genesis.hash()));
+
+        // Spend the genesis coinbase, then spend that output in the same block
+        let first = spend(&[genesis_out], vec![owned_output(60)]);
+        let second = spend(&[first_output(&first)], vec![owned_output(50)]);
+        let coinbase = Transaction::coinbase(vec![2u8; 20], 10, 1);
+        let block = child_block(
+            &genesis,
+            vec![coinbase.clone(), first.clone(), second.clone()],
+        );
+
+        utxo.apply_block(1, &block).unwrap();
+        assert!(!utxo.exists(&genesis_out).unwrap());
+        assert!(!utxo.exists(&first_output(&first)).unwrap());
+        assert!(utxo.exists(&first_output(&second)).unwrap());
+        assert!(utxo.exists(&first_output(&coinbase)).unwrap());
+        let created = utxo.get(&first_output(&coinbase)).unwrap().unwrap();
+        assert!(created.coinbase);
+        assert_eq!(created.height, 1);
+        assert!(!utxo.get(&first_output(&second)).unwrap().unwrap().coinbase);
+        assert_eq!(store.get_best().unwrap(), Some(block.hash()));
+        assert_eq!(store.get_height().unwrap(), 1);
+        assert_eq!(utxo.get_undo(&block.hash()).unwrap().spent.len(), 1);
+
+        utxo.disconnect_block(&block).unwrap();
+        let restored = utxo.get(&genesis_out).unwrap().unwrap();
+        assert!(restored.coinbase);
+        assert_eq!(restored.height, 0);
+        assert!(!utxo.exists(&first_output(&second)).unwrap());
+        assert!(!utxo.exists(&first_output(&coinbase)).unwrap());
+        assert_eq!(store.get_best().unwrap(), Some(genesis.hash()));
+        assert_eq!(store.get_height().unwrap(), 0);
+        assert!(store.get_by_height(1).is_err());
+        assert!(store.get_by_hash(&block.hash()).is_ok());
+        assert!(utxo.get_undo(&block.hash()).is_err());
+    }
+
+    #[test]
+    fn test_apply_block_missing_input_writes_nothing() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let utxo = UtxoSet::new(&db);
+        let store = BlockStore::new(&db);
+
+        let genesis = Block::genesis();
+        utxo.apply_block(0, &genesis).unwrap();
+
+        let missing = OutPoint {
+            txid: Hash256::from_bytes([9u8; 32]),
+            index: 0,
+        };
+        let coinbase = Transaction::coinbase(vec![2u8; 20], 10, 1);
+        let block = child_block(
+            &genesis,
+            vec![coinbase.clone(), spend(&[missing], vec![owned_output(5)])],
+        );
+
+        assert!(matches!(
+            utxo.apply_block(1, &block),
+            Err(StorageError::UtxoNotFound)
+        ));
+        assert!(!utxo.exists(&first_output(&coinbase)).unwrap());
+        assert_eq!(store.get_best().unwrap(), Some(genesis.hash()));
+        assert!(store.get_by_hash(&block.hash()).is_err());
+    }
+
+    #[test]
+    fn test_disconnect_requires_tip() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path()).unwrap();
+        let utxo = UtxoSet::new(&db);
+
+        let genesis = Block::genesis();
+        utxo.apply_block(0, &genesis).unwrap();
+        let block = child_block(&genesis, vec![Transaction::coinbase(vec![2u8; 20], 10, 1)]);
+
+        assert!(matches!(
+            utxo.disconnect_block(&block),
+            Err(StorageError::NotTip(_))
+        ));
+        assert!(matches!(
+            utxo.disconnect_block(&genesis),
+            Err(StorageError::InvalidHeight)
+        ));
+    }
 }
@@ -0,0 +1,97 @@
+//! Fixtures shared by the test suites of the other crates
+//!
+//! Only ever used as a dev-dependency.
+
+use ed25519_dalek::SigningKey;
+use nullchain_crypto::{pubkey_hash, sign_message};
+use nullchain_types::{
+    Block, Hash256, OutPoint, Transaction, TransactionInput, TransactionOutput, UtxoEntry,
+};
+use std::collections::HashMap;
+
+/// Easiest difficulty: every hash meets the target
+pub const EASY_BITS: u32 = 0x20ffffff;
+
+/// Key owning the outputs created by these fixtures
+pub fn owner() -> SigningKey {
+    SigningKey::from_bytes(&[7u8; 32])
+}
+
+/// Recipient of outputs spendable by [`owner`]
+pub fn owner_hash() -> Vec<u8> {
+    pubkey_hash(owner().verifying_key().as_bytes()).to_vec()
+}
+
+/// Output of `amount` paying [`owner`]
+pub fn owned_output(amount: u64) -> TransactionOutput {
+    TransactionOutput {
+        amount,
+        recipient: owner_hash(),
+    }
+}
+
+/// Made-up outpoint number `i`, as funded by [`funded`]
+pub fn funded_outpoint(i: u8) -> OutPoint {
+    OutPoint {
+        txid: Hash256::from_bytes([i; 32]),
+        index: 0,
+    }
+}
+
+/// UTXO view of `count` non-coinbase outputs of `amount` owned by [`owner`],
+/// created at height 0 at [`funded_outpoint`] `0..count`
+pub fn funded(count: u8, amount: u64) -> HashMap<OutPoint, UtxoEntry> {
+    (0..count)
+        .map(|i| {
+            let entry = UtxoEntry {
+                output: owned_output(amount),
+                height: 0,
+                coinbase: false,
+            };
+            (funded_outpoint(i), entry)
+        })
+        .collect()
+}
+
+/// Transaction spending `inputs` (owned by [`owner`]) into `outputs`, signed
+pub fn spend(inputs: &[OutPoint], outputs: Vec<TransactionOutput>) -> Transaction {
+    let key = owner();
+    let mut tx = Transaction {
+        version: 1,
+        inputs: inputs
+            .iter()
+            .map(|outpoint| TransactionInput {
+                previous_output: outpoint.txid,
+                output_index: outpoint.index,
+                signature: vec![],
+                public_key: key.verifying_key().to_bytes().to_vec(),
+            })
+            .collect(),
+        outputs,
+        locktime: 0,
+    };
+    let signature = sign_message(&key, tx.sighash().as_bytes());
+    for input in &mut tx.inputs {
+        input.signature = signature.clone();
+    }
+    tx
+}
+
+/// Unmined child of `parent` holding `transactions`, with a valid merkle root
+pub fn child_block(parent: &Block, transactions: Vec<Transaction>) -> Block {
+    let mut block = Block {
+        header: parent.header.clone(),
+        transactions,
+    };
+    block.header.previous_block = parent.hash();
+    block.header.merkle_root = block.calculate_merkle_root();
+    block
+}
+
+/// First output of `tx`
+pub fn first_output(tx: &Transaction) -> OutPoint {
+    OutPoint {
+        txid: tx.txid(),
+        index: 0,
+    }
+}
@@ -0,0 +1,481 @@
+//! Checksummed addresses
+//!
+//! An address is the network's human-readable prefix, the separator `1`,
+//! then a version and the 20-byte public key hash in base32, followed by a
+//! six-character bech32m checksum (BIP-350):
+//!
+//! ```text
+//! tnull1 q <32 chars of pubkey hash> <6 chars of checksum>
+//! ```
+//!
+//! The checksum detects any error in up to four characters. Parse errors
+//! report the character position at fault, counted from zero.
+
+use crate::network::Network;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Current address version
+pub const ADDRESS_VERSION: u8 = 0;
+
+/// Length of the public key hash an address encodes
+pub const ADDRESS_HASH_LEN: usize = 20;
+
+const SEPARATOR: char = '1';
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const CHECKSUM_LEN: usize = 6;
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+const MAX_LEN: usize = 90;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AddressError {
+    #[error("address is {0} characters long (maximum {MAX_LEN})")]
+    TooLong(usize),
+
+    #[error("invalid character {character:?} at position {position}")]
+    InvalidChar { position: usize, character: char },
+
+    #[error("mixed upper and lower case at position {0}")]
+    MixedCase(usize),
+
+    #[error("missing separator '1'")]
+    MissingSeparator,
+
+    #[error("unknown address prefix {0:?}")]
+    UnknownPrefix(String),
+
+    #[error("invalid checksum (likely error at position {0})")]
+    ChecksumAt(usize),
+
+    #[error("invalid checksum")]
+    InvalidChecksum,
+
+    #[error("unsupported address version {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("address encodes {0} bytes instead of {ADDRESS_HASH_LEN}")]
+    InvalidLength(usize),
+
+    #[error("non-zero padding bits")]
+    InvalidPadding,
+
+    #[error("{found} address used on {expected}")]
+    WrongNetwork { expected: Network, found: Network },
+}
+
+impl AddressError {
+    /// Character position the error points at, if any
+    pub fn position(&self) -> Option<usize> {
+        match self {
+            AddressError::InvalidChar { position, .. } => Some(*position),
+            AddressError::MixedCase(position) | AddressError::ChecksumAt(position) => {
+                Some(*position)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Public key hash of an output recipient, tagged with its network
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Address {
+    network: Network,
+    version: u8,
+    hash: [u8; ADDRESS_HASH_LEN],
+}
+
+impl Address {
+    /// Current-version address paying to the public key hash `hash`
+    pub fn new(network: Network, hash: [u8; ADDRESS_HASH_LEN]) -> Self {
+        Self {
+            network,
+            version: ADDRESS_VERSION,
+            hash,
+        }
+    }
+
+    /// Address of a [`TransactionOutput::recipient`](crate::TransactionOutput)
+    ///
+    /// `None` if the recipient is not a 20-byte hash.
+    pub fn from_recipient(network: Network, recipient: &[u8]) -> Option<Self> {
+        Some(Self::new(network, recipient.try_into().ok()?))
+    }
+
+    /// Parse an address of any network
+    pub fn parse(s: &str) -> Result<Self, AddressError> {
+        if s.len() > MAX_LEN {
+            return Err(AddressError::TooLong(s.len()));
+        }
+
+        let mut case = None;
+        for (position, character) in s.chars().enumerate() {
+            if !(33..=126).contains(&(character as u32)) {
+                return Err(AddressError::InvalidChar {
+                    position,
+                    character,
+                });
+            }
+            if character.is_ascii_alphabetic() {
+                let upper = character.is_ascii_uppercase();
+                if *case.get_or_insert(upper) != upper {
+                    return Err(AddressError::MixedCase(position));
+                }
+            }
+        }
+
+        // All characters are ASCII from here on, so bytes and positions agree
+        let lower = s.to_ascii_lowercase();
+        let separator = lower
+            .rfind(SEPARATOR)
+            .filter(|&separator| separator > 0)
+            .ok_or(AddressError::MissingSeparator)?;
+        let prefix = &lower[..separator];
+        let network = Network::from_address_prefix(prefix)
+            .ok_or_else(|| AddressError::UnknownPrefix(prefix.to_string()))?;
+
+        let data_start = separator + 1;
+        let data = lower[data_start..]
+            .bytes()
+            .enumerate()
+            .map(|(i, byte)| {
+                CHARSET
+                    .iter()
+                    .position(|&c| c == byte)
+                    .map(|value| value as u8)
+                    .ok_or(AddressError::InvalidChar {
+                        position: data_start + i,
+                        character: byte as char,
+                    })
+            })
+            .collect::<Result<Vec<u8>, _>>()?;
+        if data.len() < CHECKSUM_LEN + 1 {
+            return Err(AddressError::InvalidLength(0));
+        }
+
+        if !verify_checksum(prefix, &data) {
+            return Err(match locate_error(prefix, &data) {
+                Some(i) => AddressError::ChecksumAt(data_start + i),
+                None => AddressError::InvalidChecksum,
+            });
+        }
+
+        let payload = &data[..data.len() - CHECKSUM_LEN];
+        let version = payload[0];
+        if version != ADDRESS_VERSION {
+            return Err(AddressError::UnsupportedVersion(version));
+        }
+
+        let bytes = convert_bits(&payload[1..], 5, 8, false)?;
+        let hash = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| AddressError::InvalidLength(bytes.len()))?;
+
+        Ok(Self {
+            network,
+            version,
+            hash,
+        })
+    }
+
+    /// Parse an address, requiring it to belong to `network`
+    pub fn parse_on(s: &str, network: Network) -> Result<Self, AddressError> {
+        let address = Self::parse(s)?;
+        if address.network != network {
+            return Err(AddressError::WrongNetwork {
+                expected: network,
+                found: address.network,
+            });
+        }
+        Ok(address)
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn hash(&self) -> &[u8; ADDRESS_HASH_LEN] {
+        &self.hash
+    }
+
+    /// Recipient bytes for a transaction output paying this address
+    pub fn to_recipient(&self) -> Vec<u8> {
+        self.hash.to_vec()
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let prefix = self.network.address_prefix();
+
+        let mut data = vec![self.version];
+        data.extend(convert_bits(&self.hash, 8, 5, true).expect("padding allowed"));
+        let checksum = create_checksum(prefix, &data);
+
+        f.write_str(prefix)?;
+        write!(f, "{}", SEPARATOR)?;
+        for value in data.iter().chain(&checksum) {
+            write!(f, "{}", CHARSET[*value as usize] as char)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+fn polymod(values: impl IntoIterator<Item = u8>) -> u32 {
+    const GENERATOR: [u32; 5] = [
+        0x3b6a_57b2,
+        0x2650_8e6d,
+        0x1ea1_19fa,
+        0x3d42_33dd,
+        0x2a14_62b3,
+    ];
+
+    let mut checksum = 1u32;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x01ff_ffff) << 5) ^ value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+/// Prefix characters as checksummed: high bits, a zero, then low bits
+fn expand_prefix(prefix: &str) -> impl Iterator<Item = u8> + '_ {
+    prefix
+        .bytes()
+        .map(|byte| byte >> 5)
+        .chain([0])
+        .chain(prefix.bytes().map(|byte| byte & 0x1f))
+}
+
+fn verify_checksum(prefix: &str, data: &[u8]) -> bool {
+    polymod(expand_prefix(prefix).chain(data.iter().copied())) == BECH32M_CONST
+}
+
+fn create_checksum(prefix: &str, data: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let values = expand_prefix(prefix)
+        .chain(data.iter().copied())
+        .chain([0; CHECKSUM_LEN]);
+    let residue = polymod(values) ^ BECH32M_CONST;
+
+    let mut checksum = [0u8; CHECKSUM_LEN];
+    for (i, value) in checksum.iter_mut().enumerate() {
+        *value = ((residue >> (5 * (CHECKSUM_LEN - 1 - i))) & 0x1f) as u8;
+    }
+    checksum
+}
+
+/// Index into `data` of the single substituted character that would make
+/// the checksum valid, if exactly one such index exists
+fn locate_error(prefix: &str, data: &[u8]) -> Option<usize> {
+    let mut candidate = data.to_vec();
+    let mut found = None;
+
+    for i in 0..data.len() {
+        let fixable = (0..32u8).filter(|&value| value != data[i]).any(|value| {
+            candidate[i] = value;
+            verify_checksum(prefix, &candidate)
+        });
+        candidate[i] = data[i];
+
+        if fixable {
+            if found.is_some() {
+                return None;
+            }
+            found = Some(i);
+        }
+    }
+    found
+}
+
+/// Regroup `data` from `from`-bit to `to`-bit values
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>, AddressError> {
+    let mut accumulator = 0u32;
+    let mut bits = 0u32;
+    let mut out = Vec::with_capacity(data.len() * from as usize / to as usize + 1);
+    let max = (1u32 << to) - 1;
+
+    for &value in data {
+        accumulator = (accumulator << from) | value as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            out.push(((accumulator >> bits) & max) as u8);
+        }
+    }
+
+    if pad {
+        if bits > 0 {
+            out.push(((accumulator << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || (accumulator << (to - bits)) & max != 0 {
+        return Err(AddressError::InvalidPadding);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address() -> Address {
+        Address::new(Network::Testnet, *b"0123456789abcdefghij")
+    }
+
+    /// Split a BIP-350 test vector and decode its data part
+    fn decode_vector(s: &str) -> (String, Vec<u8>) {
+        let lower = s.to_ascii_lowercase();
+        let separator = lower.rfind(SEPARATOR).unwrap();
+        let data = lower[separator + 1..]
+            .bytes()
+            .map(|byte| CHARSET.iter().position(|&c| c == byte).unwrap() as u8)
+            .collect();
+        (lower[..separator].to_string(), data)
+    }
+
+    #[test]
+    fn test_bip350_checksums() {
+        for vector in [
+            "A1LQFN3A",
+            "a1lqfn3a",
+            "abcdef1l7aum6echk45nj3s0wdvt2fg8x9yrzpqzd3ryx",
+            "split1checkupstagehandshakeupstreamerranterredcaperredlc445v",
+            "?1v759aa",
+        ] {
+            let (prefix, data) = decode_vector(vector);
+            assert!(verify_checksum(&prefix, &data), "{}", vector);
+
+            let payload = &data[..data.len() - CHECKSUM_LEN];
+            assert_eq!(
+                create_checksum(&prefix, payload),
+                data[data.len() - CHECKSUM_LEN..],
+                "{}",
+                vector
+            );
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let address = address();
+        let encoded = address.to_string();
+        assert!(encoded.starts_with("tnull1q"));
+        assert_eq!(encoded.len(), 5 + 1 + 1 + 32 + CHECKSUM_LEN);
+
+        assert_eq!(Address::parse(&encoded).unwrap(), address);
+        assert_eq!(
+            Address::parse(&encoded.to_ascii_uppercase()).unwrap(),
+            address
+        );
+        assert_eq!(
+            Address::from_recipient(Network::Testnet, &address.to_recipient()),
+            Some(address)
+        );
+
+        for network in Network::ALL {
+            let other = Address::new(network, *address.hash());
+            assert!(other
+                .to_string()
+                .starts_with(&format!("{}1", network.address_prefix())));
+            assert_eq!(Address::parse(&other.to_string()).unwrap(), other);
+        }
+    }
+
+    #[test]
+    fn test_typo_position() {
+        let encoded = address().to_string();
+        for position in [6, 20, encoded.len() - 1] {
+            let mut typo = encoded.clone().into_bytes();
+            typo[position] = if typo[position] == b'q' { b'p' } else { b'q' };
+            let typo = String::from_utf8(typo).unwrap();
+
+            let error = Address::parse(&typo).unwrap_err();
+            assert_eq!(error, AddressError::ChecksumAt(position));
+            assert_eq!(error.position(), Some(position));
+        }
+    }
+
+    #[test]
+    fn test_reject_malformed() {
+        let encoded = address().to_string();
+
+        let mut bad_char = encoded.clone();
+        bad_char.replace_range(10..11, "b");
+        assert_eq!(
+            Address::parse(&bad_char),
+            Err(AddressError::InvalidChar {
+                position: 10,
+                character: 'b'
+            })
+        );
+
+        let mut mixed = encoded.clone();
+        mixed.replace_range(12..13, &encoded[12..13].to_ascii_uppercase());
+        assert!(matches!(
+            Address::parse(&mixed),
+            Err(AddressError::MixedCase(_))
+        ));
+
+        assert_eq!(
+            Address::parse(&encoded.replacen("tnull", "xnull", 1)),
+            Err(AddressError::UnknownPrefix("xnull".to_string()))
+        );
+        assert_eq!(
+            Address::parse("tnullqqqqqq"),
+            Err(AddressError::MissingSeparator)
+        );
+        assert_eq!(
+            Address::parse_on(&encoded, Network::Mainnet),
+            Err(AddressError::WrongNetwork {
+                expected: Network::Mainnet,
+                found: Network::Testnet
+            })
+        );
+    }
+
+    #[test]
+    fn test_reject_version_and_length() {
+        let prefix = Network::Regtest.address_prefix();
+        let encode = |data: &[u8]| {
+            let checksum = create_checksum(prefix, data);
+            let chars: String = data
+                .iter()
+                .chain(&checksum)
+                .map(|&value| CHARSET[value as usize] as char)
+                .collect();
+            format!("{}1{}", prefix, chars)
+        };
+
+        let hash = convert_bits(&[7u8; ADDRESS_HASH_LEN], 8, 5, true).unwrap();
+        let mut future = vec![1];
+        future.extend(&hash);
+        assert_eq!(
+            Address::parse(&encode(&future)),
+            Err(AddressError::UnsupportedVersion(1))
+        );
+
+        let mut short = vec![ADDRESS_VERSION];
+        short.extend(convert_bits(&[7u8; 16], 8, 5, true).unwrap());
+        assert_eq!(
+            Address::parse(&encode(&short)),
+            Err(AddressError::InvalidLength(16))
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use crate::hash::Hash256;
-use crate::merkle::MerkleTree;
+use crate::merkle::{MerkleProof, MerkleTree};
+use crate::target::Target;
 use crate::transaction::Transaction;
 use serde::{Deserialize, Serialize};
 
@@ -46,6 +47,11 @@ impl BlockHeader {
 
         Hash256::from_bytes(*second_hasher.finalize().as_bytes())
     }
+
+    /// Check if the header hash satisfies its own difficulty target
+    pub fn meets_difficulty_target(&self) -> bool {
+        Target::from_compact(self.bits).is_some_and(|target| target.is_met_by(&self.hash()))
+    }
 }
 
 /// Block (collection of transactions)
@@ -59,25 +65,39 @@ pub struct Block {
 }
 
 impl Block {
-    /// Create genesis block (first block in the chain)
+    /// Unmined genesis-shaped block: a lone coinbase to the burn address
+    ///
+    /// Each network's actual, mined genesis block is built with
+    /// [`Block::genesis_with_message`].
     pub fn genesis() -> Self {
-        let coinbase = Transaction::coinbase(
+        Self::genesis_with_message(b"", 1609459200, 0x1d00ffff) // 2021-01-01 00:00:00 UTC
+    }
+
+    /// Genesis block at `timestamp` and difficulty `bits`, with a zero nonce
+    ///
+    /// `message` is embedded in the coinbase input, where a spending
+    /// signature would otherwise go.
+    pub fn genesis_with_message(message: &[u8], timestamp: u64, bits: u32) -> Self {
+        let mut coinbase = Transaction::coinbase(
             vec![0u8; 20], // Genesis address (burn address)
             crate::constants::INITIAL_BLOCK_REWARD,
             0,
         );
+        coinbase.inputs[0].signature = message.to_vec();
 
-        Self {
+        let mut block = Self {
             header: BlockHeader {
                 version: 1,
                 previous_block: Hash256::zero(),
-                merkle_root: Hash256::zero(), // Will be computed
-                timestamp: 1609459200,        // 2021-01-01 00:00:00 UTC (symbolic)
-                bits: 0x1d00ffff,             // Initial difficulty (Bitcoin's)
+                merkle_root: Hash256::zero(),
+                timestamp,
+                bits,
                 nonce: 0,
             },
             transactions: vec![coinbase],
-        }
+        };
+        block.header.merkle_root = block.calculate_merkle_root();
+        block
     }
 
     /// Calculate the merkle root of all transactions
@@ -86,89 +106,33 @@ impl Block {
             return Hash256::zero();
         }
 
-        // Hash each transaction
-        let tx_hashes: Vec<Hash256> = self
-            .transactions
-            .iter()
-            .map(|tx| {
-                let serialized = bincode::serialize(tx).expect("tx serialization");
-                use blake3::Hasher;
-                let mut hasher = Hasher::new();
-                hasher.update(&serialized);
-                Hash256::from_bytes(*hasher.finalize().as_bytes())
-            })
-            .collect();
+        let tx_hashes: Vec<Hash256> = self.transactions.iter().map(|tx| tx.txid()).collect();
 
         let tree = MerkleTree::new(tx_hashes);
         tree.root()
     }
 
+    /// Proof that the transaction with `txid` is committed to by the merkle root
+    pub fn merkle_proof(&self, txid: &Hash256) -> Option<MerkleProof> {
+        let tx_hashes: Vec<Hash256> = self.transactions.iter().map(|tx| tx.txid()).collect();
+        let index = tx_hashes.iter().position(|hash| hash == txid)?;
+
+        MerkleTree::new(tx_hashes).proof(index)
+    }
+
     /// Get the hash of this block
     pub fn hash(&self) -> Hash256 {
         self.header.hash()
     }
 
-    /// Check if the block satisfies the difficulty target
-    pub fn meets_difficulty_target(&self) -> bool {
-        let hash = self.hash();
-        let target = Self::bits_to_target(self.header.bits);
-
-        // Compare as big-endian (most significant bytes first)
-        for (hash_byte, target_byte) in hash
-            .as_bytes()
-            .iter()
-            .rev()
-            .zip(target.as_bytes().iter().rev())
-        {
-            if hash_byte < target_byte {
-                return true;
-            } else if hash_byte > target_byte {
-                return false;
-            }
-        }
-        true // Equal is also valid
+    /// Serialized size of the block in bytes
+    pub fn size(&self) -> usize {
+        bincode::serialized_size(self).expect("Block serialization failed") as usize
     }
 
-    /// Convert compact bits representation to full target
-    /// Simplified version for testing
-    fn bits_to_target(bits: u32) -> Hash256 {
-        let exponent = ((bits >> 24) & 0xff) as usize;
-        let mantissa = bits & 0x00ffffff;
-
-        let mut target = [0xffu8; 32];
-
-        if exponent <= 3 {
-            // Very small target
-            let bytes = mantissa.to_le_bytes();
-            for (i, byte) in bytes.iter().enumerate().take(exponent) {
-                target[i] = *byte;
-            }
-            for item in target.iter_mut().skip(exponent).take(32 - exponent) {
-                *item = 0;
-            }
-        } else if exponent < 32 {
-            // Normal case
-            let start = exponent - 3;
-            target[start..32].fill(0xff);
-
-            let mantissa_bytes = mantissa.to_be_bytes();
-            if start < 32 {
-                target[start] = mantissa_bytes[1];
-            }
-            if start + 1 < 32 {
-                target[start + 1] = mantissa_bytes[2];
-            }
-            if start + 2 < 32 {
-                target[start + 2] = mantissa_bytes[3];
-            }
-
-            // Fill rest with zeros
-            for item in target.iter_mut().take(32).skip(start + 3) {
-                *item = 0;
-            }
-        }
-
-        Hash256::from_bytes(target)
+    /// Check if the block satisfies the difficulty target
+    pub fn meets_difficulty_target(&self) -> bool {
+        self.header.meets_difficulty_target()
     }
 }
 
@@ -184,6 +148,38 @@ mod tests {
         assert_eq!(genesis.header.previous_block, Hash256::zero());
         assert_eq!(genesis.transactions.len(), 1);
         assert!(genesis.transactions[0].is_coinbase());
+        assert_eq!(genesis.header.merkle_root, genesis.calculate_merkle_root());
+    }
+
+    #[test]
+    fn test_genesis_message() {
+        let genesis = Block::genesis_with_message(b"hello", 1, 0x207fffff);
+
+        assert_eq!(genesis.transactions[0].inputs[0].signature, b"hello");
+        assert_eq!(genesis.header.merkle_root, genesis.calculate_merkle_root());
+        assert_ne!(
+            genesis.header.merkle_root,
+            Block::genesis_with_message(b"world", 1, 0x207fffff)
+                .header
+                .merkle_root
+        );
+    }
+
+    #[test]
+    fn test_merkle_proof() {
+        let mut block = Block::genesis();
+        for height in 1..=4 {
+            block
+                .transactions
+                .push(Transaction::coinbase(vec![1; 20], 50, height));
+        }
+        block.header.merkle_root = block.calculate_merkle_root();
+
+        for tx in &block.transactions {
+            let proof = block.merkle_proof(&tx.txid()).unwrap();
+            assert!(proof.verify(&tx.txid(), &block.header.merkle_root));
+        }
+        assert!(block.merkle_proof(&Hash256::zero()).is_none());
     }
 
     #[test]
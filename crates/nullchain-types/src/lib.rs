@@ -3,21 +3,34 @@
 //! This crate defines the fundamental data structures used throughout
 //! the NullChain protocol: blocks, transactions, and cryptographic primitives.
 
+pub mod address;
 pub mod block;
 pub mod hash;
 pub mod merkle;
+pub mod network;
+pub mod target;
 pub mod transaction;
 
+pub use address::{Address, AddressError};
 pub use block::{Block, BlockHeader};
 pub use hash::Hash256;
-pub use merkle::MerkleTree;
-pub use transaction::{Transaction, TransactionInput, TransactionOutput};
+pub use merkle::{MerkleProof, MerkleTree};
+pub use network::Network;
+pub use target::{Target, U256};
+pub use transaction::{OutPoint, Transaction, TransactionInput, TransactionOutput, UtxoEntry};
 
 /// Chain parameters and constants
 pub mod constants {
     /// Block time target in seconds (Bitcoin-style: 10 minutes)
     pub const BLOCK_TIME_SECONDS: u64 = 600;
 
+    /// Number of recent blocks whose median timestamp a new block must exceed
+    pub const MEDIAN_TIME_SPAN: usize = 11;
+
+    /// Default limit on how far a block timestamp may run ahead of the local
+    /// clock (seconds)
+    pub const MAX_FUTURE_BLOCK_TIME: u64 = 2 * 60 * 60;
+
     /// Difficulty adjustment interval (blocks)
     pub const DIFFICULTY_ADJUSTMENT_INTERVAL: u64 = 2016;
 
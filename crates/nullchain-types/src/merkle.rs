@@ -1,106 +1,177 @@
 //! Merkle tree implementation for transaction commitment
+//!
+//! Leaves and interior nodes are hashed with distinct one-byte prefixes, so
+//! a node can never be passed off as a leaf or the other way round. A node
+//! left without a sibling moves up a level unchanged instead of being paired
+//! with a copy of itself, so `[a, b, c]` and `[a, b, c, c]` have different
+//! roots (the duplicated-leaf malleability of CVE-2012-2459). The result is
+//! the tree hash of RFC 6962.
 
 use crate::hash::Hash256;
 use serde::{Deserialize, Serialize};
 
+/// Prefix of hashed leaves
+const LEAF_TAG: u8 = 0x00;
+
+/// Prefix of hashed interior nodes
+const NODE_TAG: u8 = 0x01;
+
 /// Merkle tree for efficient transaction verification
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MerkleTree {
-    /// Leaves (transaction hashes)
-    leaves: Vec<Hash256>,
-    /// Internal nodes
-    nodes: Vec<Hash256>,
-    /// Root hash
-    root: Hash256,
+    /// Hashes of each level, from the hashed leaves up to the root
+    levels: Vec<Vec<Hash256>>,
+}
+
+/// Sibling hashes linking one leaf to the root of its tree
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    /// Position of the leaf
+    pub index: u32,
+
+    /// Number of leaves in the tree
+    pub leaf_count: u32,
+
+    /// Siblings from the leaf level up; levels where the path has no
+    /// sibling are skipped
+    pub siblings: Vec<Hash256>,
 }
 
 impl MerkleTree {
     /// Build a Merkle tree from transaction hashes
     pub fn new(leaves: Vec<Hash256>) -> Self {
         if leaves.is_empty() {
-            return Self {
-                leaves: vec![],
-                nodes: vec![],
-                root: Hash256::zero(),
-            };
+            return Self { levels: vec![] };
         }
 
-        let mut nodes = leaves.clone();
-        let mut current_level = leaves.clone();
+        let mut levels = vec![leaves.iter().map(hash_leaf).collect::<Vec<_>>()];
 
         // Build tree bottom-up
-        while current_level.len() > 1 {
-            let mut next_level = Vec::new();
-
-            for i in (0..current_level.len()).step_by(2) {
-                let left = current_level[i];
-                let right = if i + 1 < current_level.len() {
-                    current_level[i + 1]
-                } else {
-                    // Duplicate last node if odd number
-                    current_level[i]
-                };
-
-                let parent = Self::hash_pair(&left, &right);
-                next_level.push(parent);
-                nodes.push(parent);
-            }
-
-            current_level = next_level;
+        while levels.last().expect("at least the leaves").len() > 1 {
+            let level = levels.last().expect("at least the leaves");
+            let next = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_node(left, right),
+                    [single] => *single,
+                    _ => unreachable!("chunks of two"),
+                })
+                .collect();
+            levels.push(next);
         }
 
-        let root = current_level[0];
-
-        Self {
-            leaves,
-            nodes,
-            root,
-        }
+        Self { levels }
     }
 
-    /// Get the Merkle root
+    /// Get the Merkle root, zero for an empty tree
     pub fn root(&self) -> Hash256 {
-        self.root
+        self.levels.last().map_or(Hash256::zero(), |level| level[0])
     }
 
     /// Get number of leaves
     pub fn len(&self) -> usize {
-        self.leaves.len()
+        self.levels.first().map_or(0, Vec::len)
     }
 
     /// Check if tree is empty
     pub fn is_empty(&self) -> bool {
-        self.leaves.is_empty()
+        self.levels.is_empty()
     }
 
-    /// Hash two nodes together (left || right)
-    fn hash_pair(left: &Hash256, right: &Hash256) -> Hash256 {
-        use blake3::Hasher;
+    /// Proof that the leaf at `index` is part of this tree
+    ///
+    /// `None` if there is no such leaf.
+    pub fn proof(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.len() {
+            return None;
+        }
 
-        let mut hasher = Hasher::new();
-        hasher.update(left.as_bytes());
-        hasher.update(right.as_bytes());
+        let mut siblings = Vec::new();
+        let mut position = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            if let Some(sibling) = level.get(position ^ 1) {
+                siblings.push(*sibling);
+            }
+            position /= 2;
+        }
 
-        Hash256::from_bytes(*hasher.finalize().as_bytes())
+        Some(MerkleProof {
+            index: index as u32,
+            leaf_count: self.len() as u32,
+            siblings,
+        })
     }
 }
 
+impl MerkleProof {
+    /// Whether `leaf` at `self.index` hashes up to `root`
+    pub fn verify(&self, leaf: &Hash256, root: &Hash256) -> bool {
+        if self.index >= self.leaf_count {
+            return false;
+        }
+
+        let mut siblings = self.siblings.iter();
+        let mut hash = hash_leaf(leaf);
+        let mut position = self.index;
+        let mut width = self.leaf_count;
+
+        while width > 1 {
+            if position % 2 == 1 {
+                let Some(left) = siblings.next() else {
+                    return false;
+                };
+                hash = hash_node(left, &hash);
+            } else if position + 1 < width {
+                let Some(right) = siblings.next() else {
+                    return false;
+                };
+                hash = hash_node(&hash, right);
+            }
+            position /= 2;
+            width = width.div_ceil(2);
+        }
+
+        siblings.next().is_none() && hash == *root
+    }
+}
+
+fn hash_leaf(leaf: &Hash256) -> Hash256 {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[LEAF_TAG]);
+    hasher.update(leaf.as_bytes());
+    Hash256::from_bytes(*hasher.finalize().as_bytes())
+}
+
+/// Hash two nodes together (tag || left || right)
+fn hash_node(left: &Hash256, right: &Hash256) -> Hash256 {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[NODE_TAG]);
+    hasher.update(left.as_bytes());
+    hasher.update(right.as_bytes());
+    Hash256::from_bytes(*hasher.finalize().as_bytes())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn leaves(count: u8) -> Vec<Hash256> {
+        (1..=count).map(|i| Hash256::from_bytes([i; 32])).collect()
+    }
+
     #[test]
     fn test_empty_tree() {
         let tree = MerkleTree::new(vec![]);
         assert_eq!(tree.root(), Hash256::zero());
         assert!(tree.is_empty());
+        assert!(tree.proof(0).is_none());
     }
 
     #[test]
     fn test_single_leaf() {
         let leaf = Hash256::from_bytes([1u8; 32]);
         let tree = MerkleTree::new(vec![leaf]);
-        assert_eq!(tree.root(), leaf);
+        assert_eq!(tree.root(), hash_leaf(&leaf));
         assert_eq!(tree.len(), 1);
     }
 
@@ -111,7 +182,10 @@ mod tests {
         let tree = MerkleTree::new(vec![leaf1, leaf2]);
 
         // Root should be hash of both leaves
-        assert_ne!(tree.root(), Hash256::zero());
+        assert_eq!(
+            tree.root(),
+            hash_node(&hash_leaf(&leaf1), &hash_leaf(&leaf2))
+        );
         assert_eq!(tree.len(), 2);
     }
 
@@ -128,4 +202,76 @@ mod tests {
 
         assert_eq!(tree1.root(), tree2.root());
     }
+
+    #[test]
+    fn test_duplicated_last_leaf_changes_root() {
+        let three = leaves(3);
+        let mut four = three.clone();
+        four.push(three[2]);
+
+        assert_ne!(MerkleTree::new(three).root(), MerkleTree::new(four).root());
+    }
+
+    #[test]
+    fn test_node_is_not_a_leaf() {
+        let two = leaves(2);
+        let tree = MerkleTree::new(two.clone());
+        let node = hash_node(&hash_leaf(&two[0]), &hash_leaf(&two[1]));
+
+        // The interior node hashed as a leaf does not reproduce the root
+        assert_ne!(MerkleTree::new(vec![node]).root(), tree.root());
+    }
+
+    #[test]
+    fn test_proofs_verify() {
+        for count in 1..=9 {
+            let leaves = leaves(count);
+            let tree = MerkleTree::new(leaves.clone());
+
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = tree.proof(index).unwrap();
+                assert!(proof.verify(leaf, &tree.root()), "{} of {}", index, count);
+
+                let other = Hash256::from_bytes([0xff; 32]);
+                assert!(!proof.verify(&other, &tree.root()));
+                assert!(!proof.verify(leaf, &other));
+            }
+            assert!(tree.proof(leaves.len()).is_none());
+        }
+    }
+
+    #[test]
+    fn test_tampered_proof_fails() {
+        let leaves = leaves(5);
+        let tree = MerkleTree::new(leaves.clone());
+        let proof = tree.proof(2).unwrap();
+
+        let mut wrong_index = proof.clone();
+        wrong_index.index = 3;
+        assert!(!wrong_index.verify(&leaves[2], &tree.root()));
+
+        let mut extra = proof.clone();
+        extra.siblings.push(tree.root());
+        assert!(!extra.verify(&leaves[2], &tree.root()));
+
+        let mut short = proof.clone();
+        short.siblings.pop();
+        assert!(!short.verify(&leaves[2], &tree.root()));
+
+        let mut out_of_range = proof;
+        out_of_range.index = 5;
+        assert!(!out_of_range.verify(&leaves[2], &tree.root()));
+    }
+
+    #[test]
+    fn test_proof_serde_roundtrip() {
+        let leaves = leaves(6);
+        let tree = MerkleTree::new(leaves.clone());
+        let proof = tree.proof(5).unwrap();
+
+        let bytes = bincode::serialize(&proof).unwrap();
+        let decoded: MerkleProof = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded, proof);
+        assert!(decoded.verify(&leaves[5], &tree.root()));
+    }
 }
@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Chain a node or address belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    /// Local chains for testing, with trivial difficulty
+    Regtest,
+}
+
+impl Network {
+    pub const ALL: [Network; 3] = [Network::Mainnet, Network::Testnet, Network::Regtest];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "mainnet",
+            Network::Testnet => "testnet",
+            Network::Regtest => "regtest",
+        }
+    }
+
+    /// Human-readable prefix of addresses on this network
+    pub fn address_prefix(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "null",
+            Network::Testnet => "tnull",
+            Network::Regtest => "rnull",
+        }
+    }
+
+    /// Network using address prefix `prefix`
+    pub fn from_address_prefix(prefix: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|network| network.address_prefix() == prefix)
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|network| network.name() == s)
+            .ok_or_else(|| format!("unknown network {:?}", s))
+    }
+}
@@ -0,0 +1,387 @@
+//! 256-bit integers and proof-of-work targets
+
+use crate::hash::Hash256;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Not, Shl, Shr};
+
+/// Unsigned 256-bit integer (four little-endian 64-bit limbs)
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct U256([u64; 4]);
+
+impl U256 {
+    pub const ZERO: U256 = U256([0; 4]);
+    pub const ONE: U256 = U256([1, 0, 0, 0]);
+    pub const MAX: U256 = U256([u64::MAX; 4]);
+
+    pub const fn from_u64(value: u64) -> Self {
+        Self([value, 0, 0, 0])
+    }
+
+    pub const fn from_u128(value: u128) -> Self {
+        Self([value as u64, (value >> 64) as u64, 0, 0])
+    }
+
+    /// Interpret 32 bytes with the least significant byte first
+    pub fn from_le_bytes(bytes: [u8; 32]) -> Self {
+        let mut limbs = [0u64; 4];
+        for (limb, chunk) in limbs.iter_mut().zip(bytes.chunks_exact(8)) {
+            *limb = u64::from_le_bytes(chunk.try_into().expect("8-byte chunk"));
+        }
+        Self(limbs)
+    }
+
+    pub fn to_le_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (chunk, limb) in bytes.chunks_exact_mut(8).zip(self.0.iter()) {
+            chunk.copy_from_slice(&limb.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 4]
+    }
+
+    /// Number of significant bits
+    pub fn bits(&self) -> u32 {
+        for (i, limb) in self.0.iter().enumerate().rev() {
+            if *limb != 0 {
+                return 64 * i as u32 + (64 - limb.leading_zeros());
+            }
+        }
+        0
+    }
+
+    /// The low 64 bits, discarding the rest
+    pub fn low_u64(&self) -> u64 {
+        self.0[0]
+    }
+
+    pub fn checked_add(self, other: U256) -> Option<U256> {
+        let mut result = [0u64; 4];
+        let mut carry = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (sum, c1) = self.0[i].overflowing_add(other.0[i]);
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = c1 || c2;
+        }
+        (!carry).then_some(U256(result))
+    }
+
+    pub fn saturating_add(self, other: U256) -> U256 {
+        self.checked_add(other).unwrap_or(U256::MAX)
+    }
+
+    pub fn checked_sub(self, other: U256) -> Option<U256> {
+        if self < other {
+            return None;
+        }
+        let mut result = [0u64; 4];
+        let mut borrow = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (diff, b1) = self.0[i].overflowing_sub(other.0[i]);
+            let (diff, b2) = diff.overflowing_sub(borrow as u64);
+            *limb = diff;
+            borrow = b1 || b2;
+        }
+        Some(U256(result))
+    }
+
+    /// `self * mul / div` with a 320-bit intermediate, so nothing is lost
+    /// to overflow before the division
+    ///
+    /// Returns `None` if `div` is zero or the quotient does not fit.
+    pub fn mul_div(self, mul: u64, div: u64) -> Option<U256> {
+        if div == 0 {
+            return None;
+        }
+
+        let mut product = [0u64; 5];
+        let mut carry = 0u128;
+        for (i, limb) in self.0.iter().enumerate() {
+            let value = *limb as u128 * mul as u128 + carry;
+            product[i] = value as u64;
+            carry = value >> 64;
+        }
+        product[4] = carry as u64;
+
+        let mut remainder = 0u128;
+        for limb in product.iter_mut().rev() {
+            let value = (remainder << 64) | *limb as u128;
+            *limb = (value / div as u128) as u64;
+            remainder = value % div as u128;
+        }
+
+        (product[4] == 0).then(|| U256([product[0], product[1], product[2], product[3]]))
+    }
+
+    /// Quotient and remainder of `self / divisor`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `divisor` is zero.
+    pub fn div_rem(self, divisor: U256) -> (U256, U256) {
+        assert!(!divisor.is_zero(), "division by zero");
+
+        let mut quotient = U256::ZERO;
+        let mut remainder = U256::ZERO;
+        for bit in (0..self.bits()).rev() {
+            remainder = remainder << 1;
+            remainder.0[0] |= self.bit(bit) as u64;
+            if remainder >= divisor {
+                remainder = remainder
+                    .checked_sub(divisor)
+                    .expect("remainder >= divisor");
+                quotient.0[bit as usize / 64] |= 1 << (bit % 64);
+            }
+        }
+        (quotient, remainder)
+    }
+
+    fn bit(&self, index: u32) -> bool {
+        (self.0[index as usize / 64] >> (index % 64)) & 1 == 1
+    }
+}
+
+impl From<u64> for U256 {
+    fn from(value: u64) -> Self {
+        U256::from_u64(value)
+    }
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Not for U256 {
+    type Output = U256;
+
+    fn not(self) -> U256 {
+        U256(self.0.map(|limb| !limb))
+    }
+}
+
+impl Shl<u32> for U256 {
+    type Output = U256;
+
+    /// Shift left, dropping bits shifted past the top
+    fn shl(self, shift: u32) -> U256 {
+        let mut result = [0u64; 4];
+        let limbs = (shift / 64) as usize;
+        let bits = shift % 64;
+        for (i, limb) in result.iter_mut().enumerate().skip(limbs) {
+            *limb = self.0[i - limbs] << bits;
+            if bits > 0 && i > limbs {
+                *limb |= self.0[i - limbs - 1] >> (64 - bits);
+            }
+        }
+        U256(result)
+    }
+}
+
+impl Shr<u32> for U256 {
+    type Output = U256;
+
+    fn shr(self, shift: u32) -> U256 {
+        let mut result = [0u64; 4];
+        let limbs = (shift / 64) as usize;
+        let bits = shift % 64;
+        for (i, limb) in result
+            .iter_mut()
+            .take(4usize.saturating_sub(limbs))
+            .enumerate()
+        {
+            *limb = self.0[i + limbs] >> bits;
+            if bits > 0 && i + limbs + 1 < 4 {
+                *limb |= self.0[i + limbs + 1] << (64 - bits);
+            }
+        }
+        U256(result)
+    }
+}
+
+impl fmt::Display for U256 {
+    /// Big-endian hex, 64 digits
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for limb in self.0.iter().rev() {
+            write!(f, "{:016x}", limb)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "U256(0x{})", self)
+    }
+}
+
+/// Proof-of-work target: a header is valid if its hash, read as a
+/// little-endian 256-bit integer, is at most the target
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Target(U256);
+
+impl Target {
+    pub const fn from_u256(value: U256) -> Self {
+        Self(value)
+    }
+
+    pub fn as_u256(&self) -> U256 {
+        self.0
+    }
+
+    /// Decode the compact `bits` encoding
+    ///
+    /// The top byte is a length in bytes and the low 24 bits an unsigned
+    /// mantissa: `target = mantissa * 256^(length - 3)`. Returns `None` if
+    /// the target does not fit in 256 bits.
+    pub fn from_compact(bits: u32) -> Option<Target> {
+        let size = bits >> 24;
+        let mantissa = U256::from_u64((bits & 0x00ff_ffff) as u64);
+
+        if size <= 3 {
+            return Some(Target(mantissa >> (8 * (3 - size))));
+        }
+
+        let shift = 8 * (size - 3);
+        if !mantissa.is_zero() && mantissa.bits() + shift > 256 {
+            return None;
+        }
+        Some(Target(mantissa << shift))
+    }
+
+    /// Encode as compact `bits`, truncating to the 24 most significant bits
+    pub fn to_compact(&self) -> u32 {
+        let size = self.0.bits().div_ceil(8);
+        let mantissa = if size <= 3 {
+            self.0.low_u64() << (8 * (3 - size))
+        } else {
+            (self.0 >> (8 * (size - 3))).low_u64()
+        };
+        (size << 24) | mantissa as u32
+    }
+
+    /// Whether `hash` satisfies this target
+    pub fn is_met_by(&self, hash: &Hash256) -> bool {
+        U256::from_le_bytes(*hash.as_bytes()) <= self.0
+    }
+
+    /// Expected number of hashes needed to meet this target,
+    /// `2^256 / (target + 1)`
+    ///
+    /// A zero target has no defined work and counts as zero.
+    pub fn work(&self) -> U256 {
+        if self.0.is_zero() {
+            return U256::ZERO;
+        }
+        // 2^256 does not fit, but 2^256 / (t + 1) == (2^256 - t - 1) / (t + 1) + 1
+        match self.0.checked_add(U256::ONE) {
+            Some(divisor) => (!self.0).div_rem(divisor).0.saturating_add(U256::ONE),
+            None => U256::ONE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact_roundtrip() {
+        for bits in [
+            0x1d00ffffu32,
+            0x1f0fffff,
+            0x20ffffff,
+            0x1b0404cb,
+            0x03123456,
+        ] {
+            let target = Target::from_compact(bits).unwrap();
+            assert_eq!(Target::from_compact(target.to_compact()), Some(target));
+        }
+
+        assert_eq!(
+            Target::from_compact(0x1f0fffff).unwrap().as_u256(),
+            U256::from_u64(0x0fffff) << (8 * 28)
+        );
+        assert_eq!(
+            Target::from_compact(0x02123456).unwrap().as_u256(),
+            U256::from_u64(0x1234)
+        );
+        assert_eq!(
+            Target::from_compact(0x20ffffff).unwrap().to_compact(),
+            0x20ffffff
+        );
+        assert_eq!(
+            Target::from_compact(0x1f0fffff).unwrap().to_compact(),
+            0x1f0fffff
+        );
+    }
+
+    #[test]
+    fn test_compact_overflow() {
+        assert!(Target::from_compact(0x21010000).is_none());
+        assert!(Target::from_compact(0xff000001).is_none());
+        assert!(Target::from_compact(0x21000001).is_some());
+    }
+
+    #[test]
+    fn test_is_met_by() {
+        let target = Target::from_compact(0x1f0fffff).unwrap();
+
+        let mut bytes = [0u8; 32];
+        bytes[30] = 0x0f;
+        bytes[29] = 0xff;
+        bytes[28] = 0xff;
+        assert!(target.is_met_by(&Hash256::from_bytes(bytes)));
+
+        bytes[0] = 1;
+        assert!(!target.is_met_by(&Hash256::from_bytes(bytes)));
+    }
+
+    #[test]
+    fn test_work() {
+        assert_eq!(Target::from_u256(U256::MAX).work(), U256::ONE);
+        assert_eq!(Target::from_u256(U256::ONE).work(), U256::ONE << 255);
+        assert_eq!(Target::from_u256(U256::ZERO).work(), U256::ZERO);
+
+        // 2^256 / (2^224) with the +1 rounding the quotient down
+        let target = Target::from_u256(U256::ONE << 224);
+        assert_eq!(target.work(), U256::from_u64(u32::MAX as u64));
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let a = U256::from_u128(u128::MAX);
+        assert_eq!(a.checked_add(U256::ONE), Some(U256::ONE << 128));
+        assert_eq!(U256::MAX.checked_add(U256::ONE), None);
+        assert_eq!((U256::ONE << 128).checked_sub(U256::ONE), Some(a));
+        assert_eq!(U256::ONE.checked_sub(a), None);
+
+        assert_eq!((U256::ONE << 200) >> 199, U256::from_u64(2));
+        assert_eq!(U256::MAX.mul_div(4, 4), Some(U256::MAX));
+        assert_eq!(U256::MAX.mul_div(4, 2), None);
+        assert_eq!(U256::from_u64(10).mul_div(3, 4), Some(U256::from_u64(7)));
+
+        let (q, r) = (U256::ONE << 130).div_rem(U256::from_u64(3));
+        assert_eq!(
+            q.checked_add(q)
+                .unwrap()
+                .checked_add(q)
+                .unwrap()
+                .checked_add(r),
+            Some(U256::ONE << 130)
+        );
+        assert_eq!(r, U256::ONE);
+    }
+}
@@ -1,6 +1,51 @@
+use crate::constants::COINBASE_MATURITY;
 use crate::hash::Hash256;
 use serde::{Deserialize, Serialize};
 
+/// Domain separation tag for signature hashes
+const SIGHASH_DOMAIN: &[u8] = b"nullchain/sighash/v1";
+
+/// Domain separation tag for transaction ids
+const TXID_DOMAIN: &[u8] = b"nullchain/txid/v1";
+
+/// Outpoint (reference to a specific transaction output)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct OutPoint {
+    /// Hash of the transaction containing the output
+    pub txid: Hash256,
+
+    /// Index of the output in that transaction
+    pub index: u32,
+}
+
+impl OutPoint {
+    /// Serialize to a fixed 36-byte key (txid || big-endian index)
+    pub fn to_key(&self) -> Vec<u8> {
+        let mut key = Vec::with_capacity(36);
+        key.extend_from_slice(self.txid.as_bytes());
+        key.extend_from_slice(&self.index.to_be_bytes());
+        key
+    }
+
+    /// Parse from a key produced by [`OutPoint::to_key`]
+    pub fn from_key(key: &[u8]) -> Option<Self> {
+        if key.len() != 36 {
+            return None;
+        }
+
+        let mut txid_bytes = [0u8; 32];
+        txid_bytes.copy_from_slice(&key[0..32]);
+
+        let mut index_bytes = [0u8; 4];
+        index_bytes.copy_from_slice(&key[32..36]);
+
+        Some(Self {
+            txid: Hash256::from_bytes(txid_bytes),
+            index: u32::from_be_bytes(index_bytes),
+        })
+    }
+}
+
 /// Transaction input (spending a previous output)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransactionInput {
@@ -17,16 +62,48 @@ pub struct TransactionInput {
     pub public_key: Vec<u8>,
 }
 
+impl TransactionInput {
+    /// The output this input spends
+    pub fn outpoint(&self) -> OutPoint {
+        OutPoint {
+            txid: self.previous_output,
+            index: self.output_index,
+        }
+    }
+}
+
 /// Transaction output (creating new spendable coins)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransactionOutput {
     /// Amount in nanoNULL (1 NULL = 10^9 nanoNULL)
     pub amount: u64,
 
-    /// Public key hash of the recipient (20 bytes, RIPEMD160(SHA256(pubkey)))
+    /// Public key hash of the recipient (20 bytes, truncated Blake3(pubkey))
     pub recipient: Vec<u8>,
 }
 
+/// Unspent output together with where it was created
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UtxoEntry {
+    /// The unspent output
+    pub output: TransactionOutput,
+
+    /// Height of the block that created the output
+    pub height: u64,
+
+    /// Whether the output was created by a coinbase
+    pub coinbase: bool,
+}
+
+impl UtxoEntry {
+    /// Whether the output may be spent in a block at `height`
+    ///
+    /// Coinbase outputs only mature after `COINBASE_MATURITY` blocks.
+    pub fn is_spendable_at(&self, height: u64) -> bool {
+        !self.coinbase || height >= self.height.saturating_add(COINBASE_MATURITY)
+    }
+}
+
 /// Transaction (transfer of value)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
@@ -65,6 +142,54 @@ impl Transaction {
             && self.inputs[0].previous_output == Hash256::zero()
             && self.inputs[0].output_index == 0xFFFFFFFF
     }
+
+    /// Message signed by every input
+    ///
+    /// Commits to the version, the outpoints being spent, all outputs and the
+    /// locktime. Signatures and public keys are excluded so that inputs can be
+    /// signed independently and in any order.
+    pub fn sighash(&self) -> Hash256 {
+        #[derive(Serialize)]
+        struct Preimage<'a> {
+            version: u32,
+            inputs: Vec<OutPoint>,
+            outputs: &'a [TransactionOutput],
+            locktime: u64,
+        }
+
+        let preimage = Preimage {
+            version: self.version,
+            inputs: self.inputs.iter().map(|input| input.outpoint()).collect(),
+            outputs: &self.outputs,
+            locktime: self.locktime,
+        };
+        let serialized = bincode::serialize(&preimage).expect("sighash serialization");
+
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(SIGHASH_DOMAIN);
+        hasher.update(&serialized);
+        Hash256::from_bytes(*hasher.finalize().as_bytes())
+    }
+
+    /// Transaction identifier (Blake3 of the serialized transaction)
+    ///
+    /// Commits to everything including signatures. This is the id used in
+    /// outpoints, merkle roots and the transaction index. The hash is
+    /// domain-separated, so a txid never equals a sighash or any other
+    /// Blake3 digest of the same bytes.
+    pub fn txid(&self) -> Hash256 {
+        let serialized = bincode::serialize(self).expect("tx serialization");
+
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(TXID_DOMAIN);
+        hasher.update(&serialized);
+        Hash256::from_bytes(*hasher.finalize().as_bytes())
+    }
+
+    /// Serialized size of the transaction in bytes
+    pub fn size(&self) -> usize {
+        bincode::serialized_size(self).expect("tx serialization") as usize
+    }
 }
 
 #[cfg(test)]
@@ -81,4 +206,104 @@ mod tests {
         assert_eq!(tx.outputs.len(), 1);
         assert_eq!(tx.outputs[0].amount, 100_000_000_000);
     }
+
+    fn sample_spend() -> Transaction {
+        Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output: Hash256::from_bytes([5u8; 32]),
+                output_index: 1,
+                signature: vec![],
+                public_key: vec![2u8; 32],
+            }],
+            outputs: vec![TransactionOutput {
+                amount: 50,
+                recipient: vec![3u8; 20],
+            }],
+            locktime: 0,
+        }
+    }
+
+    #[test]
+    fn test_sighash_excludes_signatures() {
+        let tx = sample_spend();
+        let mut signed = tx.clone();
+        signed.inputs[0].signature = vec![0xaa; 64];
+        signed.inputs[0].public_key = vec![0xbb; 32];
+
+        assert_eq!(tx.sighash(), signed.sighash());
+        assert_ne!(tx.sighash(), tx.txid());
+    }
+
+    #[test]
+    fn test_sighash_commits_to_spend() {
+        let tx = sample_spend();
+
+        let mut changed = tx.clone();
+        changed.outputs[0].amount += 1;
+        assert_ne!(tx.sighash(), changed.sighash());
+
+        let mut changed = tx.clone();
+        changed.inputs[0].output_index = 0;
+        assert_ne!(tx.sighash(), changed.sighash());
+
+        let mut changed = tx.clone();
+        changed.locktime = 1;
+        assert_ne!(tx.sighash(), changed.sighash());
+
+        let mut changed = tx.clone();
+        changed.version = 2;
+        assert_ne!(tx.sighash(), changed.sighash());
+    }
+
+    #[test]
+    fn test_txid() {
+        let tx = Transaction::coinbase(vec![1u8; 20], 50, 7);
+        assert_eq!(
+            tx.txid().to_string(),
+            "6d941f08f717f9d0315c47a9b985b6ef5edbd654bddcb0ce6a54f4fddb9140d4"
+        );
+
+        // Not the plain hash of the serialized transaction
+        let serialized = bincode::serialize(&tx).unwrap();
+        assert_ne!(tx.txid().as_bytes(), blake3::hash(&serialized).as_bytes());
+
+        let mut signed = tx.clone();
+        signed.inputs[0].signature = vec![1];
+        assert_ne!(signed.txid(), tx.txid());
+        assert_eq!(signed.sighash(), tx.sighash());
+    }
+
+    #[test]
+    fn test_outpoint_key_roundtrip() {
+        let outpoint = OutPoint {
+            txid: Hash256::from_bytes([7u8; 32]),
+            index: 3,
+        };
+
+        let key = outpoint.to_key();
+        assert_eq!(key.len(), 36);
+        assert_eq!(OutPoint::from_key(&key), Some(outpoint));
+        assert_eq!(OutPoint::from_key(&key[1..]), None);
+    }
+
+    #[test]
+    fn test_coinbase_maturity() {
+        let entry = UtxoEntry {
+            output: TransactionOutput {
+                amount: 1,
+                recipient: vec![0u8; 20],
+            },
+            height: 5,
+            coinbase: true,
+        };
+        assert!(!entry.is_spendable_at(5 + COINBASE_MATURITY - 1));
+        assert!(entry.is_spendable_at(5 + COINBASE_MATURITY));
+
+        let regular = UtxoEntry {
+            coinbase: false,
+            ..entry
+        };
+        assert!(regular.is_spendable_at(5));
+    }
 }
@@ -0,0 +1,316 @@
+//! Building and signing spends of wallet outputs
+
+use crate::coin_selection::{select_coins, SelectionParams};
+use crate::{Result, Wallet, WalletError, WalletUtxo};
+use ed25519_dalek::SigningKey;
+use nullchain_crypto::{pubkey_hash, sign_message};
+use nullchain_types::{Address, Hash256, Transaction, TransactionInput, TransactionOutput};
+
+/// Length of an Ed25519 signature
+const SIGNATURE_LEN: usize = 64;
+
+/// Length of an Ed25519 public key
+const PUBLIC_KEY_LEN: usize = 32;
+
+/// Funded transaction whose inputs still need signatures
+#[derive(Debug, Clone)]
+pub struct PendingTransaction {
+    pub transaction: Transaction,
+
+    /// Outputs spent by the inputs, in input order
+    pub spent: Vec<WalletUtxo>,
+
+    pub fee: u64,
+
+    /// Index of the change output, if there is one
+    pub change_index: Option<usize>,
+}
+
+impl PendingTransaction {
+    /// Sign every input over the sighash with the key its spent output pays to
+    pub fn sign(mut self, keys: &[SigningKey]) -> Result<Transaction> {
+        let sighash = self.transaction.sighash();
+
+        for (input, spent) in self.transaction.inputs.iter_mut().zip(&self.spent) {
+            let key = keys
+                .iter()
+                .find(|key| pubkey_hash(key.verifying_key().as_bytes()) == *spent.output.recipient)
+                .ok_or_else(|| WalletError::MissingKey(hex::encode(&spent.output.recipient)))?;
+
+            input.public_key = key.verifying_key().to_bytes().to_vec();
+            input.signature = sign_message(key, sighash.as_bytes());
+        }
+
+        Ok(self.transaction)
+    }
+}
+
+impl Wallet {
+    /// Fund `payments` of an amount to an address from spendable wallet
+    /// outputs that no pending transaction spends
+    ///
+    /// The fee is `fee_rate` nanoNULL per byte of the signed transaction.
+    /// Any change is paid to `change_address`.
+    pub fn create_transaction(
+        &self,
+        payments: &[(Address, u64)],
+        fee_rate: u64,
+        change_address: &Address,
+    ) -> Result<PendingTransaction> {
+        if payments.is_empty() {
+            return Err(WalletError::NoPayments);
+        }
+        let target = payments
+            .iter()
+            .try_fold(0u64, |sum, (_, amount)| sum.checked_add(*amount))
+            .ok_or(WalletError::AmountOverflow)?;
+
+        let next_height = self.tip()?.map_or(0, |tip| tip.height + 1);
+        let mut spendable = Vec::new();
+        for utxo in self.utxos()? {
+            if utxo.is_mature(next_height) && !self.is_pending_spend(&utxo.outpoint)? {
+                spendable.push(utxo);
+            }
+        }
+
+        let mut transaction = Transaction {
+            version: 1,
+            inputs: vec![],
+            outputs: payments
+                .iter()
+                .map(|(address, amount)| TransactionOutput {
+                    amount: *amount,
+                    recipient: address.to_recipient(),
+                })
+                .collect(),
+            locktime: 0,
+        };
+        let change_output = TransactionOutput {
+            amount: 0,
+            recipient: change_address.to_recipient(),
+        };
+        let params = SelectionParams {
+            target,
+            fee_rate,
+            base_size: transaction.size() as u64,
+            input_size: serialized_size(&signed_input()),
+            change_size: serialized_size(&change_output),
+        };
+        let selection = select_coins(&spendable, &params)?;
+
+        transaction.inputs = selection
+            .inputs
+            .iter()
+            .map(|utxo| TransactionInput {
+                previous_output: utxo.outpoint.txid,
+                output_index: utxo.outpoint.index,
+                signature: vec![],
+                public_key: vec![],
+            })
+            .collect();
+
+        let mut change_index = None;
+        if selection.change > 0 {
+            change_index = Some(transaction.outputs.len());
+            transaction.outputs.push(TransactionOutput {
+                amount: selection.change,
+                recipient: change_address.to_recipient(),
+            });
+        }
+
+        Ok(PendingTransaction {
+            transaction,
+            spent: selection.inputs,
+            fee: selection.fee,
+            change_index,
+        })
+    }
+}
+
+/// Input with signature and public key of the final sizes
+fn signed_input() -> TransactionInput {
+    TransactionInput {
+        previous_output: Hash256::zero(),
+        output_index: 0,
+        signature: vec![0; SIGNATURE_LEN],
+        public_key: vec![0; PUBLIC_KEY_LEN],
+    }
+}
+
+fn serialized_size<T: serde::Serialize>(value: &T) -> u64 {
+    bincode::serialized_size(value).expect("serialization")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletKey;
+    use nullchain_crypto::TransactionExt;
+    use nullchain_storage::{BlockStore, Database};
+    use nullchain_types::{Block, Network};
+    use tempfile::TempDir;
+
+    fn signing_key(byte: u8) -> SigningKey {
+        SigningKey::from_bytes(&[byte; 32])
+    }
+
+    fn watch(wallet: &Wallet, key: &SigningKey, index: u32) -> [u8; 20] {
+        let key = WalletKey {
+            public_key: key.verifying_key().to_bytes(),
+            index: Some(index),
+        };
+        wallet.add_key(&key).unwrap();
+        key.pubkey_hash()
+    }
+
+    fn payee() -> Address {
+        Address::new(Network::Regtest, [9; 20])
+    }
+
+    /// Block paying `amounts` to `recipient` in a non-coinbase transaction
+    fn funding_block(recipient: &[u8], amounts: &[u64]) -> Block {
+        let mut block = Block::genesis();
+        block.transactions.push(Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output: Hash256::from_bytes([7; 32]),
+                output_index: 0,
+                signature: vec![],
+                public_key: vec![],
+            }],
+            outputs: amounts
+                .iter()
+                .map(|&amount| TransactionOutput {
+                    amount,
+                    recipient: recipient.to_vec(),
+                })
+                .collect(),
+            locktime: 0,
+        });
+        block.header.merkle_root = block.calculate_merkle_root();
+        block
+    }
+
+    #[test]
+    fn test_create_and_sign() {
+        let dir = TempDir::new().unwrap();
+        let chain_db = Database::open(dir.path().join("chain")).unwrap();
+        let wallet = Wallet::open(dir.path().join("wallet")).unwrap();
+
+        let (first, second) = (signing_key(1), signing_key(2));
+        let first_address = watch(&wallet, &first, 0);
+        let second_address = watch(&wallet, &second, 1);
+        watch(&wallet, &signing_key(3), 2);
+
+        let store = BlockStore::new(&chain_db);
+        let mut block = funding_block(&first_address, &[40_000]);
+        block.transactions[1].outputs.push(TransactionOutput {
+            amount: 50_000,
+            recipient: second_address.to_vec(),
+        });
+        block.header.merkle_root = block.calculate_merkle_root();
+        store.put(0, &block).unwrap();
+        store.set_best(0, &block.hash()).unwrap();
+        wallet.sync(&store).unwrap();
+
+        let change = wallet.fresh_key().unwrap().unwrap();
+        // Keys 0 and 1 were paid
+        assert_eq!(change.index, Some(2));
+        let change_address = change.address(Network::Regtest);
+        let pending = wallet
+            .create_transaction(&[(payee(), 70_000)], 2, &change_address)
+            .unwrap();
+        assert_eq!(pending.spent.len(), 2);
+
+        let spent: Vec<TransactionOutput> = pending
+            .spent
+            .iter()
+            .map(|utxo| utxo.output.clone())
+            .collect();
+        let fee = pending.fee;
+        let change_index = pending.change_index.unwrap();
+
+        let tx = pending.sign(&[first, second]).unwrap();
+        tx.verify_inputs(&spent).unwrap();
+
+        assert_eq!(fee, 2 * tx.size() as u64);
+        assert_eq!(tx.outputs[0].recipient, payee().to_recipient());
+        assert_eq!(
+            tx.outputs[change_index].recipient,
+            change_address.to_recipient()
+        );
+        let output_total: u64 = tx.outputs.iter().map(|output| output.amount).sum();
+        assert_eq!(output_total + fee, 90_000);
+    }
+
+    #[test]
+    fn test_sign_requires_keys() {
+        let dir = TempDir::new().unwrap();
+        let chain_db = Database::open(dir.path().join("chain")).unwrap();
+        let wallet = Wallet::open(dir.path().join("wallet")).unwrap();
+        let key = signing_key(1);
+        let address = watch(&wallet, &key, 0);
+
+        let store = BlockStore::new(&chain_db);
+        let block = funding_block(&address, &[10_000]);
+        store.put(0, &block).unwrap();
+        store.set_best(0, &block.hash()).unwrap();
+        wallet.sync(&store).unwrap();
+
+        let change_address = Address::new(Network::Regtest, address);
+        let pending = wallet
+            .create_transaction(&[(payee(), 5_000)], 1, &change_address)
+            .unwrap();
+        assert!(matches!(
+            pending.sign(&[signing_key(2)]),
+            Err(WalletError::MissingKey(_))
+        ));
+    }
+
+    #[test]
+    fn test_pending_spends_are_not_reselected() {
+        let dir = TempDir::new().unwrap();
+        let chain_db = Database::open(dir.path().join("chain")).unwrap();
+        let wallet = Wallet::open(dir.path().join("wallet")).unwrap();
+        let keys = [signing_key(1)];
+        let address = watch(&wallet, &keys[0], 0);
+
+        let store = BlockStore::new(&chain_db);
+        let block = funding_block(&address, &[10_000, 10_000]);
+        store.put(0, &block).unwrap();
+        store.set_best(0, &block.hash()).unwrap();
+        wallet.sync(&store).unwrap();
+
+        let change_address = Address::new(Network::Regtest, address);
+        let first = wallet
+            .create_transaction(&[(payee(), 5_000)], 1, &change_address)
+            .unwrap()
+            .sign(&keys)
+            .unwrap();
+        wallet.add_pending_spend(&first).unwrap();
+
+        let second = wallet
+            .create_transaction(&[(payee(), 5_000)], 1, &change_address)
+            .unwrap();
+        assert_ne!(second.spent[0].outpoint, first.inputs[0].outpoint());
+
+        let second = second.sign(&keys).unwrap();
+        wallet.add_pending_spend(&second).unwrap();
+        assert!(matches!(
+            wallet.create_transaction(&[(payee(), 5_000)], 1, &change_address),
+            Err(WalletError::InsufficientFunds { .. })
+        ));
+    }
+
+    #[test]
+    fn test_fresh_keys_are_not_reused() {
+        let dir = TempDir::new().unwrap();
+        let wallet = Wallet::open(dir.path()).unwrap();
+        watch(&wallet, &signing_key(1), 0);
+        watch(&wallet, &signing_key(2), 1);
+
+        assert_eq!(wallet.fresh_key().unwrap().unwrap().index, Some(0));
+        assert_eq!(wallet.fresh_key().unwrap().unwrap().index, Some(1));
+        assert_eq!(wallet.fresh_key().unwrap(), None);
+    }
+}
@@ -0,0 +1,255 @@
+//! Choosing wallet outputs to fund a payment
+//!
+//! Fees are a fee rate times the serialized transaction size, so every
+//! input pays for its own bytes. Coins are compared by their *effective
+//! value*: amount minus the fee of spending them.
+//!
+//! Branch-and-bound first looks for a set of coins that covers the payment
+//! without change, wasting at most what a change output would cost. When no
+//! such set exists, coins are added largest first and the excess returns as
+//! change.
+
+use crate::{Result, WalletError, WalletUtxo};
+
+/// Search steps after which branch-and-bound gives up
+const BNB_MAX_TRIES: usize = 100_000;
+
+/// Sizes and fee rate of the transaction being funded
+#[derive(Debug, Clone, Copy)]
+pub struct SelectionParams {
+    /// Sum of the payment outputs
+    pub target: u64,
+
+    /// Fee per serialized byte (nanoNULL)
+    pub fee_rate: u64,
+
+    /// Size of the transaction with its payments but no inputs or change
+    pub base_size: u64,
+
+    /// Size added by one signed input
+    pub input_size: u64,
+
+    /// Size added by the change output
+    pub change_size: u64,
+}
+
+impl SelectionParams {
+    fn fee(&self, size: u64) -> u64 {
+        self.fee_rate.saturating_mul(size)
+    }
+}
+
+/// Coins funding a payment
+#[derive(Debug, Clone)]
+pub struct Selection {
+    pub inputs: Vec<WalletUtxo>,
+
+    /// Amount returned as change, zero for none
+    pub change: u64,
+
+    pub fee: u64,
+}
+
+/// Select coins from `utxos` paying `params.target` plus fees
+pub fn select_coins(utxos: &[WalletUtxo], params: &SelectionParams) -> Result<Selection> {
+    let input_fee = params.fee(params.input_size);
+    let mut candidates: Vec<(&WalletUtxo, u64)> = utxos
+        .iter()
+        .filter(|utxo| utxo.output.amount > input_fee)
+        .map(|utxo| (utxo, utxo.output.amount - input_fee))
+        .collect();
+    candidates.sort_by_key(|&(_, value)| std::cmp::Reverse(value));
+
+    let needed = params
+        .target
+        .checked_add(params.fee(params.base_size))
+        .ok_or(WalletError::AmountOverflow)?;
+    let available: u64 = candidates.iter().map(|(_, value)| value).sum();
+    if available < needed {
+        return Err(WalletError::InsufficientFunds { available, needed });
+    }
+
+    // Change costs its output now and an input when it is spent later
+    let cost_of_change = params.fee(params.change_size) + input_fee;
+
+    let values: Vec<u64> = candidates.iter().map(|(_, value)| *value).collect();
+    if let Some(chosen) = branch_and_bound(&values, needed, needed + cost_of_change) {
+        let inputs: Vec<WalletUtxo> = chosen.iter().map(|&i| candidates[i].0.clone()).collect();
+        return Ok(finish(inputs, 0, params));
+    }
+
+    // Largest first until the payment and a change output are covered
+    let mut inputs = Vec::new();
+    let mut total = 0u64;
+    for (utxo, value) in &candidates {
+        inputs.push((*utxo).clone());
+        total += value;
+        if total >= needed + params.fee(params.change_size) {
+            break;
+        }
+    }
+
+    let excess = total - needed;
+    let change = match excess.checked_sub(params.fee(params.change_size)) {
+        // Change worth less than spending it is left to the fee
+        Some(change) if change > input_fee => change,
+        _ => 0,
+    };
+    Ok(finish(inputs, change, params))
+}
+
+fn finish(inputs: Vec<WalletUtxo>, change: u64, params: &SelectionParams) -> Selection {
+    let total: u64 = inputs.iter().map(|utxo| utxo.output.amount).sum();
+    Selection {
+        fee: total - params.target - change,
+        inputs,
+        change,
+    }
+}
+
+/// Indices into `values` (sorted descending) summing to within
+/// `[target, upper]`
+fn branch_and_bound(values: &[u64], target: u64, upper: u64) -> Option<Vec<usize>> {
+    // remaining[i] is the sum of values[i..]
+    let mut remaining = vec![0u64; values.len() + 1];
+    for i in (0..values.len()).rev() {
+        remaining[i] = remaining[i + 1] + values[i];
+    }
+
+    let mut search = Search {
+        values,
+        remaining,
+        target,
+        upper,
+        tries: 0,
+        selected: Vec::new(),
+    };
+    search.explore(0, 0).then_some(search.selected)
+}
+
+struct Search<'a> {
+    values: &'a [u64],
+    remaining: Vec<u64>,
+    target: u64,
+    upper: u64,
+    tries: usize,
+    selected: Vec<usize>,
+}
+
+impl Search<'_> {
+    /// Decide on values[index..] given the selected `sum` so far
+    fn explore(&mut self, index: usize, sum: u64) -> bool {
+        if sum > self.upper {
+            return false;
+        }
+        if sum >= self.target {
+            return true;
+        }
+        if sum + self.remaining[index] < self.target || self.tries >= BNB_MAX_TRIES {
+            return false;
+        }
+        self.tries += 1;
+
+        self.selected.push(index);
+        if self.explore(index + 1, sum + self.values[index]) {
+            return true;
+        }
+        self.selected.pop();
+
+        // Leaving out a value equal to an excluded one gives the same sums
+        let mut next = index + 1;
+        while next < self.values.len() && self.values[next] == self.values[index] {
+            next += 1;
+        }
+        self.explore(next, sum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nullchain_types::{Hash256, OutPoint, TransactionOutput};
+
+    fn utxos(amounts: &[u64]) -> Vec<WalletUtxo> {
+        amounts
+            .iter()
+            .enumerate()
+            .map(|(i, &amount)| WalletUtxo {
+                outpoint: OutPoint {
+                    txid: Hash256::zero(),
+                    index: i as u32,
+                },
+                output: TransactionOutput {
+                    amount,
+                    recipient: vec![0; 20],
+                },
+                height: 0,
+                coinbase: false,
+            })
+            .collect()
+    }
+
+    fn params(target: u64, fee_rate: u64) -> SelectionParams {
+        SelectionParams {
+            target,
+            fee_rate,
+            base_size: 100,
+            input_size: 150,
+            change_size: 40,
+        }
+    }
+
+    fn amounts(selection: &Selection) -> Vec<u64> {
+        selection
+            .inputs
+            .iter()
+            .map(|utxo| utxo.output.amount)
+            .collect()
+    }
+
+    #[test]
+    fn test_exact_match_without_change() {
+        let coins = utxos(&[50_000, 30_000, 20_000, 7_000]);
+
+        // 26_600 + base fee 100 + two input fees 300 = 20_000 + 7_000
+        let selection = select_coins(&coins, &params(26_600, 1)).unwrap();
+        assert_eq!(amounts(&selection), vec![20_000, 7_000]);
+        assert_eq!(selection.change, 0);
+        assert_eq!(selection.fee, 400);
+    }
+
+    #[test]
+    fn test_fallback_with_change() {
+        let coins = utxos(&[50_000, 30_000, 20_000]);
+
+        let selection = select_coins(&coins, &params(60_000, 1)).unwrap();
+        assert_eq!(amounts(&selection), vec![50_000, 30_000]);
+        // base 100 + 2 inputs 300 + change output 40
+        assert_eq!(selection.fee, 440);
+        assert_eq!(selection.change, 80_000 - 60_000 - 440);
+    }
+
+    #[test]
+    fn test_small_change_goes_to_fee() {
+        let coins = utxos(&[10_000]);
+
+        // The 140 left over is less than a change output would cost
+        let selection = select_coins(&coins, &params(9_610, 1)).unwrap();
+        assert_eq!(selection.change, 0);
+        assert_eq!(selection.fee, 390);
+    }
+
+    #[test]
+    fn test_insufficient_funds() {
+        let coins = utxos(&[1_000, 100]);
+
+        // The 100 coin costs more to spend than it is worth
+        assert!(matches!(
+            select_coins(&coins, &params(1_000, 1)),
+            Err(WalletError::InsufficientFunds {
+                available: 850,
+                needed: 1_100
+            })
+        ));
+    }
+}
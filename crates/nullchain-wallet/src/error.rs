@@ -0,0 +1,28 @@
+use nullchain_storage::StorageError;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum WalletError {
+    #[error("database error: {0}")]
+    Database(#[from] rocksdb::Error),
+
+    #[error("serialization error: {0}")]
+    Serialization(#[from] bincode::Error),
+
+    #[error("chain storage error: {0}")]
+    Storage(#[from] StorageError),
+
+    #[error("insufficient funds: {available} spendable, {needed} needed")]
+    InsufficientFunds { available: u64, needed: u64 },
+
+    #[error("amount overflow")]
+    AmountOverflow,
+
+    #[error("transaction has no outputs")]
+    NoPayments,
+
+    #[error("no signing key for recipient {0}")]
+    MissingKey(String),
+}
+
+pub type Result<T> = std::result::Result<T, WalletError>;
@@ -0,0 +1,25 @@
+//! Wallet state for NullChain
+//!
+//! Tracks the keys of a wallet and follows the main chain in a
+//! [`BlockStore`](nullchain_storage::BlockStore), recording:
+//! - Outputs paying to wallet addresses (the wallet's UTXOs)
+//! - Inputs spending them
+//! - Per-transaction history of amounts received and sent
+//! - Outputs spent by transactions that have not confirmed yet
+//!
+//! Spends are funded by coin selection and signed with the wallet keys.
+//!
+//! Scanning is incremental. Blocks that leave the main chain are rolled back
+//! before the new ones are scanned.
+
+mod builder;
+mod coin_selection;
+mod error;
+mod records;
+mod wallet;
+
+pub use builder::PendingTransaction;
+pub use coin_selection::{select_coins, Selection, SelectionParams};
+pub use error::{Result, WalletError};
+pub use records::{Balance, HistoryEntry, ScanTip, SyncReport, WalletKey, WalletUtxo};
+pub use wallet::{Wallet, PENDING_SPEND_EXPIRY};
@@ -0,0 +1,128 @@
+use nullchain_crypto::pubkey_hash;
+use nullchain_types::constants::COINBASE_MATURITY;
+use nullchain_types::{Address, Hash256, Network, OutPoint, TransactionOutput};
+use serde::{Deserialize, Serialize};
+
+/// Public key watched by the wallet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletKey {
+    /// Ed25519 public key
+    pub public_key: [u8; 32],
+
+    /// HD derivation index, for keys derived from the wallet seed
+    pub index: Option<u32>,
+}
+
+impl WalletKey {
+    /// Public key hash that outputs paying this key use as recipient
+    pub fn pubkey_hash(&self) -> [u8; 20] {
+        pubkey_hash(&self.public_key)
+    }
+
+    /// Address of this key on `network`
+    pub fn address(&self, network: Network) -> Address {
+        Address::new(network, self.pubkey_hash())
+    }
+}
+
+/// Unspent output owned by the wallet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletUtxo {
+    pub outpoint: OutPoint,
+    pub output: TransactionOutput,
+
+    /// Height of the block that created the output
+    pub height: u64,
+
+    /// Whether the output was created by a coinbase
+    pub coinbase: bool,
+}
+
+impl WalletUtxo {
+    /// Whether the output may be spent in a block at `height`
+    pub fn is_mature(&self, height: u64) -> bool {
+        !self.coinbase || height >= self.height + COINBASE_MATURITY
+    }
+}
+
+/// Effect of one transaction on the wallet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub txid: Hash256,
+    pub block_hash: Hash256,
+    pub height: u64,
+
+    /// Sum of outputs paying to the wallet
+    pub received: u64,
+
+    /// Sum of wallet outputs spent by the inputs
+    pub sent: u64,
+}
+
+/// Wallet funds by spendability
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Balance {
+    /// Outputs that can be spent in the next block
+    pub spendable: u64,
+
+    /// Coinbase outputs still waiting for maturity
+    pub immature: u64,
+}
+
+impl Balance {
+    pub fn total(&self) -> u64 {
+        self.spendable + self.immature
+    }
+}
+
+/// Last main-chain block scanned by the wallet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanTip {
+    pub height: u64,
+    pub hash: Hash256,
+}
+
+/// Blocks processed by [`Wallet::sync`](crate::Wallet::sync)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Blocks scanned on top of the previous tip
+    pub connected: u64,
+
+    /// Blocks rolled back because they left the main chain
+    pub disconnected: u64,
+}
+
+/// Wallet output spent by a transaction that has not confirmed yet
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct PendingSpend {
+    /// Spending transaction
+    pub txid: Hash256,
+
+    /// Scanned height when the spend was made
+    pub height: u64,
+}
+
+/// Changes a block made to the wallet, kept to roll it back
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct BlockRecord {
+    /// Wallet outputs created by the block
+    pub received: Vec<OutPoint>,
+
+    /// Wallet outputs spent by the block
+    pub spent: Vec<WalletUtxo>,
+
+    /// Wallet transactions in block order
+    pub history: Vec<HistoryEntry>,
+
+    /// Pending spends the block confirmed, with the outputs they spent
+    pub pending: Vec<(OutPoint, PendingSpend)>,
+
+    /// Recipients the block marked as used for the first time
+    pub used: Vec<Vec<u8>>,
+}
+
+impl BlockRecord {
+    pub fn is_empty(&self) -> bool {
+        self.received.is_empty() && self.spent.is_empty()
+    }
+}
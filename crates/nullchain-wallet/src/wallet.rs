@@ -0,0 +1,760 @@
+use crate::records::{BlockRecord, PendingSpend};
+use crate::{Balance, HistoryEntry, Result, ScanTip, SyncReport, WalletKey, WalletUtxo};
+use nullchain_storage::{BlockStore, Database, StorageError};
+use nullchain_types::{Block, OutPoint, Transaction};
+use rocksdb::WriteBatch;
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::Path;
+
+const KEY_PREFIX: &[u8] = b"key:";
+const UTXO_PREFIX: &[u8] = b"utxo:";
+const BLOCK_PREFIX: &[u8] = b"block:";
+const USED_PREFIX: &[u8] = b"used:";
+const PENDING_PREFIX: &[u8] = b"pending:";
+const KEY_TIP: &[u8] = b"tip";
+const KEY_RESCAN: &[u8] = b"rescan";
+
+/// Scanned blocks after which an unconfirmed spend is given up and its
+/// outputs can be spent again
+pub const PENDING_SPEND_EXPIRY: u64 = 72;
+
+fn prefixed(prefix: &[u8], suffix: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(prefix.len() + suffix.len());
+    key.extend_from_slice(prefix);
+    key.extend_from_slice(suffix);
+    key
+}
+
+fn utxo_key(outpoint: &OutPoint) -> Vec<u8> {
+    prefixed(UTXO_PREFIX, &outpoint.to_key())
+}
+
+fn pending_key(outpoint: &OutPoint) -> Vec<u8> {
+    prefixed(PENDING_PREFIX, &outpoint.to_key())
+}
+
+fn block_key(height: u64) -> Vec<u8> {
+    prefixed(BLOCK_PREFIX, &height.to_be_bytes())
+}
+
+/// Keys, outputs and history of a wallet, persisted in its own database
+pub struct Wallet {
+    db: Database,
+}
+
+impl Wallet {
+    /// Open or create the wallet database at path
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self {
+            db: Database::open(path)?,
+        })
+    }
+
+    /// Start watching `key`
+    ///
+    /// Returns `false` if the key was already known. Adding a key to a wallet
+    /// that has scanned blocks before makes the next [`sync`](Self::sync)
+    /// restart from genesis, since earlier blocks may pay to it. Until then
+    /// the wallet keeps its current state.
+    pub fn add_key(&self, key: &WalletKey) -> Result<bool> {
+        let db_key = prefixed(KEY_PREFIX, &key.pubkey_hash());
+        if self.db.inner().get(&db_key)?.is_some() {
+            return Ok(false);
+        }
+
+        let mut batch = WriteBatch::default();
+        batch.put(&db_key, bincode::serialize(key)?);
+        if self.tip()?.is_some() {
+            batch.put(KEY_RESCAN, []);
+        }
+        self.db.inner().write(batch)?;
+        Ok(true)
+    }
+
+    /// Watched keys, ordered by address
+    pub fn keys(&self) -> Result<Vec<WalletKey>> {
+        self.collect(KEY_PREFIX)
+    }
+
+    /// Lowest-index derived key that has never been paid or handed out
+    ///
+    /// The key is marked as used, so it is not returned again. `None` means
+    /// every derived key is used and more must be derived.
+    pub fn fresh_key(&self) -> Result<Option<WalletKey>> {
+        let mut keys: Vec<WalletKey> = self
+            .keys()?
+            .into_iter()
+            .filter(|key| key.index.is_some())
+            .collect();
+        keys.sort_by_key(|key| key.index);
+
+        for key in keys {
+            if !self.is_used(&key)? {
+                self.db
+                    .inner()
+                    .put(prefixed(USED_PREFIX, &key.pubkey_hash()), [])?;
+                return Ok(Some(key));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Derivation indices still to add so the last `gap` derived keys are unused
+    ///
+    /// A key counts as used once it was paid or handed out by
+    /// [`fresh_key`](Self::fresh_key). Empty for wallets without derived keys.
+    pub fn missing_lookahead(&self, gap: u32) -> Result<Range<u32>> {
+        let mut next = None;
+        let mut first_unused = 0;
+        for key in self.keys()? {
+            let Some(index) = key.index else {
+                continue;
+            };
+            next = next.max(Some(index + 1));
+            if self.is_used(&key)? {
+                first_unused = first_unused.max(index + 1);
+            }
+        }
+
+        Ok(match next {
+            Some(next) => next..(first_unused + gap).max(next),
+            None => 0..0,
+        })
+    }
+
+    /// Last scanned block, if any
+    pub fn tip(&self) -> Result<Option<ScanTip>> {
+        match self.db.inner().get(KEY_TIP)? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Bring the wallet up to date with the main chain of `store`
+    ///
+    /// Scanned blocks that are no longer on the main chain are rolled back
+    /// first, then the blocks after the fork point are scanned. Pending
+    /// spends older than [`PENDING_SPEND_EXPIRY`] blocks are dropped. A wallet
+    /// last synced with another chain (such as a different network) is
+    /// rescanned from scratch.
+    pub fn sync(&self, store: &BlockStore) -> Result<SyncReport> {
+        let mut report = SyncReport::default();
+
+        if self.db.inner().get(KEY_RESCAN)?.is_some() {
+            self.rescan()?;
+        }
+        if let Some(tip) = self.tip()? {
+            if let Err(StorageError::BlockNotFound(_)) = store.get_by_hash(&tip.hash) {
+                tracing::info!("Wallet tip {} is not in this chain, rescanning", tip.hash);
+                self.rescan()?;
+            }
+        }
+
+        while let Some(tip) = self.tip()? {
+            if store.get_hash_by_height(tip.height)? == Some(tip.hash) {
+                break;
+            }
+            self.disconnect_tip(store, tip)?;
+            report.disconnected += 1;
+        }
+
+        if store.get_best()?.is_none() {
+            return Ok(report);
+        }
+        let best_height = store.get_height()?;
+        let addresses: HashSet<Vec<u8>> = self
+            .keys()?
+            .iter()
+            .map(|key| key.pubkey_hash().to_vec())
+            .collect();
+
+        let mut height = self.tip()?.map_or(0, |tip| tip.height + 1);
+        while height <= best_height {
+            let block = store.get_by_height(height)?;
+            self.connect_block(height, &block, &addresses)?;
+            report.connected += 1;
+            height += 1;
+        }
+        self.expire_pending_spends()?;
+
+        Ok(report)
+    }
+
+    /// Unspent wallet outputs, ordered by outpoint
+    pub fn utxos(&self) -> Result<Vec<WalletUtxo>> {
+        self.collect(UTXO_PREFIX)
+    }
+
+    /// Sum of unspent outputs as of the scanned tip
+    pub fn balance(&self) -> Result<Balance> {
+        let next_height = self.tip()?.map_or(0, |tip| tip.height + 1);
+
+        let mut balance = Balance::default();
+        for utxo in self.utxos()? {
+            if utxo.is_mature(next_height) {
+                balance.spendable += utxo.output.amount;
+            } else {
+                balance.immature += utxo.output.amount;
+            }
+        }
+        Ok(balance)
+    }
+
+    /// Wallet transactions, oldest first
+    pub fn history(&self) -> Result<Vec<HistoryEntry>> {
+        let records: Vec<BlockRecord> = self.collect(BLOCK_PREFIX)?;
+        Ok(records
+            .into_iter()
+            .flat_map(|record| record.history)
+            .collect())
+    }
+
+    /// Remember that `tx` spends wallet outputs, so they are not selected
+    /// again before it confirms or [`PENDING_SPEND_EXPIRY`] blocks pass
+    pub fn add_pending_spend(&self, tx: &Transaction) -> Result<()> {
+        let pending = PendingSpend {
+            txid: tx.txid(),
+            height: self.tip()?.map_or(0, |tip| tip.height),
+        };
+        let value = bincode::serialize(&pending)?;
+
+        let mut batch = WriteBatch::default();
+        for input in &tx.inputs {
+            batch.put(pending_key(&input.outpoint()), &value);
+        }
+        self.db.inner().write(batch)?;
+        Ok(())
+    }
+
+    /// Whether `outpoint` is spent by a pending transaction
+    pub fn is_pending_spend(&self, outpoint: &OutPoint) -> Result<bool> {
+        Ok(self.db.inner().get(pending_key(outpoint))?.is_some())
+    }
+
+    /// Forget all scanned blocks, keeping the keys
+    pub fn rescan(&self) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        for prefix in [UTXO_PREFIX, BLOCK_PREFIX] {
+            for item in self.db.inner().prefix_iterator(prefix) {
+                let (key, _) = item?;
+                if !key.starts_with(prefix) {
+                    break;
+                }
+                batch.delete(key);
+            }
+        }
+        batch.delete(KEY_TIP);
+        batch.delete(KEY_RESCAN);
+        self.db.inner().write(batch)?;
+        Ok(())
+    }
+
+    /// Flush the wallet database to disk
+    pub fn flush(&self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn connect_block(
+        &self,
+        height: u64,
+        block: &Block,
+        addresses: &HashSet<Vec<u8>>,
+    ) -> Result<()> {
+        let block_hash = block.hash();
+        let mut batch = WriteBatch::default();
+        let mut record = BlockRecord::default();
+        // Outputs created by this block, so later transactions in it can spend them
+        let mut created: HashMap<OutPoint, WalletUtxo> = HashMap::new();
+
+        for tx in &block.transactions {
+            let txid = tx.txid();
+            let coinbase = tx.is_coinbase();
+            let (spent_before, received_before) = (record.spent.len(), record.received.len());
+            let mut entry = HistoryEntry {
+                txid,
+                block_hash,
+                height,
+                received: 0,
+                sent: 0,
+            };
+
+            if !coinbase {
+                for input in &tx.inputs {
+                    let outpoint = input.outpoint();
+                    let spent = match created.remove(&outpoint) {
+                        Some(utxo) => Some(utxo),
+                        None => self.get_utxo(&outpoint)?,
+                    };
+                    if let Some(utxo) = spent {
+                        batch.delete(utxo_key(&outpoint));
+                        if let Some(data) = self.db.inner().get(pending_key(&outpoint))? {
+                            record
+                                .pending
+                                .push((outpoint, bincode::deserialize(&data)?));
+                            batch.delete(pending_key(&outpoint));
+                        }
+                        entry.sent += utxo.output.amount;
+                        record.spent.push(utxo);
+                    }
+                }
+            }
+
+            for (index, output) in tx.outputs.iter().enumerate() {
+                if !addresses.contains(&output.recipient) {
+                    continue;
+                }
+                let outpoint = OutPoint {
+                    txid,
+                    index: index as u32,
+                };
+                entry.received += output.amount;
+                record.received.push(outpoint);
+                let used = prefixed(USED_PREFIX, &output.recipient);
+                if !record.used.contains(&output.recipient) && self.db.inner().get(&used)?.is_none()
+                {
+                    record.used.push(output.recipient.clone());
+                }
+                batch.put(used, []);
+                created.insert(
+                    outpoint,
+                    WalletUtxo {
+                        outpoint,
+                        output: output.clone(),
+                        height,
+                        coinbase,
+                    },
+                );
+            }
+
+            if record.spent.len() > spent_before || record.received.len() > received_before {
+                record.history.push(entry);
+            }
+        }
+
+        for (outpoint, utxo) in &created {
+            batch.put(utxo_key(outpoint), bincode::serialize(utxo)?);
+        }
+        if !record.is_empty() {
+            batch.put(block_key(height), bincode::serialize(&record)?);
+        }
+        let tip = ScanTip {
+            height,
+            hash: block_hash,
+        };
+        batch.put(KEY_TIP, bincode::serialize(&tip)?);
+
+        self.db.inner().write(batch)?;
+        Ok(())
+    }
+
+    /// Undo the scan of `tip` and move back to its parent
+    fn disconnect_tip(&self, store: &BlockStore, tip: ScanTip) -> Result<()> {
+        tracing::debug!(
+            "Rolling back wallet block {} at height {}",
+            tip.hash,
+            tip.height
+        );
+
+        let mut batch = WriteBatch::default();
+        if let Some(data) = self.db.inner().get(block_key(tip.height))? {
+            let record: BlockRecord = bincode::deserialize(&data)?;
+            // Restore before removing: outputs created and spent within the
+            // block end up deleted
+            for utxo in &record.spent {
+                batch.put(utxo_key(&utxo.outpoint), bincode::serialize(utxo)?);
+            }
+            for outpoint in &record.received {
+                batch.delete(utxo_key(outpoint));
+            }
+            for (outpoint, pending) in &record.pending {
+                batch.put(pending_key(outpoint), bincode::serialize(pending)?);
+            }
+            for recipient in &record.used {
+                batch.delete(prefixed(USED_PREFIX, recipient));
+            }
+            batch.delete(block_key(tip.height));
+        }
+
+        if tip.height == 0 {
+            batch.delete(KEY_TIP);
+        } else {
+            let block = store.get_by_hash(&tip.hash)?;
+            let parent = ScanTip {
+                height: tip.height - 1,
+                hash: block.header.previous_block,
+            };
+            batch.put(KEY_TIP, bincode::serialize(&parent)?);
+        }
+
+        self.db.inner().write(batch)?;
+        Ok(())
+    }
+
+    fn expire_pending_spends(&self) -> Result<()> {
+        let Some(tip) = self.tip()? else {
+            return Ok(());
+        };
+
+        let mut batch = WriteBatch::default();
+        for item in self.db.inner().prefix_iterator(PENDING_PREFIX) {
+            let (key, value) = item?;
+            if !key.starts_with(PENDING_PREFIX) {
+                break;
+            }
+            let pending: PendingSpend = bincode::deserialize(&value)?;
+            if tip.height >= pending.height + PENDING_SPEND_EXPIRY {
+                tracing::debug!("Pending spend by {} expired", pending.txid);
+                batch.delete(key);
+            }
+        }
+        self.db.inner().write(batch)?;
+        Ok(())
+    }
+
+    fn is_used(&self, key: &WalletKey) -> Result<bool> {
+        let used = prefixed(USED_PREFIX, &key.pubkey_hash());
+        Ok(self.db.inner().get(used)?.is_some())
+    }
+
+    fn get_utxo(&self, outpoint: &OutPoint) -> Result<Option<WalletUtxo>> {
+        match self.db.inner().get(utxo_key(outpoint))? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Deserialize every value whose key starts with `prefix`
+    fn collect<T: DeserializeOwned>(&self, prefix: &[u8]) -> Result<Vec<T>> {
+        let mut values = Vec::new();
+        for item in self.db.inner().prefix_iterator(prefix) {
+            let (key, value) = item?;
+            if !key.starts_with(prefix) {
+                break;
+            }
+            values.push(bincode::deserialize(&value)?);
+        }
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nullchain_types::constants::COINBASE_MATURITY;
+    use nullchain_types::{Transaction, TransactionInput, TransactionOutput};
+    use tempfile::TempDir;
+
+    const KEY: WalletKey = WalletKey {
+        public_key: [1; 32],
+        index: Some(0),
+    };
+
+    struct Fixture {
+        _dir: TempDir,
+        chain_db: Database,
+        wallet: Wallet,
+    }
+
+    fn fixture() -> Fixture {
+        let dir = TempDir::new().unwrap();
+        let chain_db = Database::open(dir.path().join("chain")).unwrap();
+        let wallet = Wallet::open(dir.path().join("wallet")).unwrap();
+        wallet.add_key(&KEY).unwrap();
+        Fixture {
+            _dir: dir,
+            chain_db,
+            wallet,
+        }
+    }
+
+    fn payment(inputs: &[OutPoint], outputs: &[(&[u8], u64)]) -> Transaction {
+        Transaction {
+            version: 1,
+            inputs: inputs
+                .iter()
+                .map(|outpoint| TransactionInput {
+                    previous_output: outpoint.txid,
+                    output_index: outpoint.index,
+                    signature: vec![0; 64],
+                    public_key: KEY.public_key.to_vec(),
+                })
+                .collect(),
+            outputs: outputs
+                .iter()
+                .map(|(recipient, amount)| TransactionOutput {
+                    amount: *amount,
+                    recipient: recipient.to_vec(),
+                })
+                .collect(),
+            locktime: 0,
+        }
+    }
+
+    /// Store a main-chain block at `height` on top of `parent`
+    fn put_block(
+        store: &BlockStore,
+        parent: Option<&Block>,
+        height: u64,
+        miner: &[u8],
+        extra: Vec<Transaction>,
+    ) -> Block {
+        let mut block = Block::genesis();
+        if let Some(parent) = parent {
+            block.header.previous_block = parent.hash();
+            block.header.timestamp = parent.header.timestamp + 1;
+        }
+        block.transactions = vec![Transaction::coinbase(miner.to_vec(), 50, height)];
+        block.transactions.extend(extra);
+        block.header.merkle_root = block.calculate_merkle_root();
+
+        store.put(height, &block).unwrap();
+        store.set_best(height, &block.hash()).unwrap();
+        block
+    }
+
+    fn outpoint(tx: &Transaction, index: u32) -> OutPoint {
+        OutPoint {
+            txid: tx.txid(),
+            index,
+        }
+    }
+
+    #[test]
+    fn test_track_received_and_spent() {
+        let f = fixture();
+        let store = BlockStore::new(&f.chain_db);
+        let ours = KEY.pubkey_hash();
+        let theirs = [9u8; 20];
+
+        let genesis = put_block(&store, None, 0, &ours, vec![]);
+        let coinbase = outpoint(&genesis.transactions[0], 0);
+        let spend = payment(&[coinbase], &[(&theirs, 30), (&ours, 20)]);
+        put_block(&store, Some(&genesis), 1, &theirs, vec![spend.clone()]);
+
+        let report = f.wallet.sync(&store).unwrap();
+        assert_eq!(report.connected, 2);
+
+        let utxos = f.wallet.utxos().unwrap();
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(utxos[0].outpoint, outpoint(&spend, 1));
+        assert_eq!(utxos[0].height, 1);
+        assert_eq!(f.wallet.balance().unwrap().spendable, 20);
+
+        let history = f.wallet.history().unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!((history[0].received, history[0].sent), (50, 0));
+        assert_eq!((history[1].received, history[1].sent), (20, 50));
+        assert_eq!(history[1].txid, spend.txid());
+
+        // Nothing new to scan
+        assert_eq!(f.wallet.sync(&store).unwrap(), SyncReport::default());
+    }
+
+    #[test]
+    fn test_spend_within_block() {
+        let f = fixture();
+        let store = BlockStore::new(&f.chain_db);
+        let ours = KEY.pubkey_hash();
+
+        let genesis = put_block(&store, None, 0, &ours, vec![]);
+        let first = payment(&[outpoint(&genesis.transactions[0], 0)], &[(&ours, 50)]);
+        let second = payment(&[outpoint(&first, 0)], &[(&[9u8; 20], 50)]);
+        put_block(&store, Some(&genesis), 1, &[9u8; 20], vec![first, second]);
+
+        f.wallet.sync(&store).unwrap();
+        assert!(f.wallet.utxos().unwrap().is_empty());
+        assert_eq!(f.wallet.history().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_rollback_on_reorg() {
+        let f = fixture();
+        let store = BlockStore::new(&f.chain_db);
+        let ours = KEY.pubkey_hash();
+        let theirs = [9u8; 20];
+
+        let genesis = put_block(&store, None, 0, &ours, vec![]);
+        let coinbase = outpoint(&genesis.transactions[0], 0);
+        let spend = payment(&[coinbase], &[(&theirs, 50)]);
+        let a1 = put_block(&store, Some(&genesis), 1, &ours, vec![spend]);
+        put_block(&store, Some(&a1), 2, &ours, vec![]);
+
+        f.wallet.sync(&store).unwrap();
+        assert_eq!(f.wallet.balance().unwrap().total(), 100);
+
+        // Replace blocks 1 and 2 by a branch that neither spends nor pays us
+        let b1 = put_block(&store, Some(&genesis), 1, &theirs, vec![]);
+        let b2 = put_block(&store, Some(&b1), 2, &theirs, vec![]);
+        put_block(&store, Some(&b2), 3, &theirs, vec![]);
+
+        let report = f.wallet.sync(&store).unwrap();
+        assert_eq!(report.disconnected, 2);
+        assert_eq!(report.connected, 3);
+
+        let utxos = f.wallet.utxos().unwrap();
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(utxos[0].outpoint, coinbase);
+        assert_eq!(f.wallet.history().unwrap().len(), 1);
+        assert_eq!(f.wallet.tip().unwrap().unwrap().height, 3);
+    }
+
+    #[test]
+    fn test_rollback_restores_pending_and_used() {
+        let f = fixture();
+        let store = BlockStore::new(&f.chain_db);
+        let ours = KEY.pubkey_hash();
+        let theirs = [9u8; 20];
+        let change = WalletKey {
+            public_key: [2; 32],
+            index: Some(1),
+        };
+        f.wallet.add_key(&change).unwrap();
+
+        let genesis = put_block(&store, None, 0, &ours, vec![]);
+        f.wallet.sync(&store).unwrap();
+        let coinbase = outpoint(&genesis.transactions[0], 0);
+        let spend = payment(&[coinbase], &[(&change.pubkey_hash(), 50)]);
+        f.wallet.add_pending_spend(&spend).unwrap();
+
+        let a1 = put_block(&store, Some(&genesis), 1, &theirs, vec![spend]);
+        f.wallet.sync(&store).unwrap();
+        assert!(!f.wallet.is_pending_spend(&coinbase).unwrap());
+        assert!(f.wallet.is_used(&change).unwrap());
+
+        // A branch without the spend: it is pending again and the change
+        // key was never paid
+        let b1 = put_block(&store, Some(&genesis), 1, &theirs, vec![]);
+        put_block(&store, Some(&b1), 2, &theirs, vec![]);
+        assert_ne!(a1.hash(), b1.hash());
+        f.wallet.sync(&store).unwrap();
+        assert!(f.wallet.is_pending_spend(&coinbase).unwrap());
+        assert!(!f.wallet.is_used(&change).unwrap());
+        assert!(f.wallet.is_used(&KEY).unwrap());
+    }
+
+    #[test]
+    fn test_rescan_on_other_chain() {
+        let f = fixture();
+        let store = BlockStore::new(&f.chain_db);
+        let ours = KEY.pubkey_hash();
+
+        let genesis = put_block(&store, None, 0, &ours, vec![]);
+        put_block(&store, Some(&genesis), 1, &ours, vec![]);
+        f.wallet.sync(&store).unwrap();
+        assert_eq!(f.wallet.utxos().unwrap().len(), 2);
+
+        // A separate chain database, as kept for another network
+        let other_dir = TempDir::new().unwrap();
+        let other_db = Database::open(other_dir.path()).unwrap();
+        let other = BlockStore::new(&other_db);
+        let other_genesis = put_block(&other, None, 0, &[9u8; 20], vec![]);
+        put_block(&other, Some(&other_genesis), 1, &ours, vec![]);
+
+        let report = f.wallet.sync(&other).unwrap();
+        assert_eq!(report.connected, 2);
+        let utxos = f.wallet.utxos().unwrap();
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(utxos[0].height, 1);
+    }
+
+    #[test]
+    fn test_immature_coinbase() {
+        let f = fixture();
+        let store = BlockStore::new(&f.chain_db);
+
+        put_block(&store, None, 0, &KEY.pubkey_hash(), vec![]);
+        f.wallet.sync(&store).unwrap();
+
+        let balance = f.wallet.balance().unwrap();
+        assert_eq!(balance.immature, 50);
+        assert_eq!(balance.spendable, 0);
+        assert!(f.wallet.utxos().unwrap()[0].is_mature(COINBASE_MATURITY));
+    }
+
+    #[test]
+    fn test_new_key_triggers_rescan() {
+        let f = fixture();
+        let store = BlockStore::new(&f.chain_db);
+        let other = WalletKey {
+            public_key: [2; 32],
+            index: Some(1),
+        };
+
+        let genesis = put_block(&store, None, 0, &other.pubkey_hash(), vec![]);
+        put_block(&store, Some(&genesis), 1, &KEY.pubkey_hash(), vec![]);
+        f.wallet.sync(&store).unwrap();
+        assert_eq!(f.wallet.utxos().unwrap().len(), 1);
+
+        assert!(f.wallet.add_key(&other).unwrap());
+        assert!(!f.wallet.add_key(&other).unwrap());
+        // The old state stays usable until the next sync
+        assert_eq!(f.wallet.tip().unwrap().unwrap().height, 1);
+        assert_eq!(f.wallet.utxos().unwrap().len(), 1);
+
+        assert_eq!(f.wallet.sync(&store).unwrap().connected, 2);
+        assert_eq!(f.wallet.utxos().unwrap().len(), 2);
+        assert_eq!(f.wallet.keys().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_missing_lookahead() {
+        let f = fixture();
+        let store = BlockStore::new(&f.chain_db);
+        let derived = |index: u32| WalletKey {
+            public_key: [index as u8 + 1; 32],
+            index: Some(index),
+        };
+        for index in 1..5 {
+            f.wallet.add_key(&derived(index)).unwrap();
+        }
+        assert_eq!(f.wallet.missing_lookahead(5).unwrap(), 5..5);
+
+        // A payment to index 2 calls for keys up to 2 + 5
+        put_block(&store, None, 0, &derived(2).pubkey_hash(), vec![]);
+        f.wallet.sync(&store).unwrap();
+        assert_eq!(f.wallet.missing_lookahead(5).unwrap(), 5..8);
+
+        // Handed-out keys count as used too
+        assert_eq!(f.wallet.fresh_key().unwrap(), Some(KEY));
+        assert_eq!(f.wallet.fresh_key().unwrap(), Some(derived(1)));
+        assert_eq!(f.wallet.fresh_key().unwrap(), Some(derived(3)));
+        assert_eq!(f.wallet.missing_lookahead(5).unwrap(), 5..9);
+    }
+
+    #[test]
+    fn test_pending_spend_confirms_or_expires() {
+        let f = fixture();
+        let store = BlockStore::new(&f.chain_db);
+        let ours = KEY.pubkey_hash();
+
+        let genesis = put_block(&store, None, 0, &ours, vec![]);
+        let mut tip = put_block(&store, Some(&genesis), 1, &ours, vec![]);
+        f.wallet.sync(&store).unwrap();
+        let (first, second) = (
+            outpoint(&genesis.transactions[0], 0),
+            outpoint(&tip.transactions[0], 0),
+        );
+
+        let confirmed = payment(&[first], &[(&[9u8; 20], 50)]);
+        let dropped = payment(&[second], &[(&[9u8; 20], 50)]);
+        f.wallet.add_pending_spend(&confirmed).unwrap();
+        f.wallet.add_pending_spend(&dropped).unwrap();
+        assert!(f.wallet.is_pending_spend(&first).unwrap());
+        assert!(f.wallet.is_pending_spend(&second).unwrap());
+
+        tip = put_block(&store, Some(&tip), 2, &[9u8; 20], vec![confirmed]);
+        f.wallet.sync(&store).unwrap();
+        assert!(!f.wallet.is_pending_spend(&first).unwrap());
+        assert!(f.wallet.is_pending_spend(&second).unwrap());
+
+        for height in 3..=1 + PENDING_SPEND_EXPIRY {
+            tip = put_block(&store, Some(&tip), height, &[9u8; 20], vec![]);
+        }
+        f.wallet.sync(&store).unwrap();
+        assert!(!f.wallet.is_pending_spend(&second).unwrap());
+        assert_eq!(f.wallet.utxos().unwrap().len(), 1);
+    }
+}